@@ -0,0 +1,26 @@
+use crate::{config_dir, merge_toml_values, syntax};
+
+/// The built-in `languages.toml`.
+pub fn default_lang_config() -> toml::Value {
+    toml::from_slice(include_bytes!("../../languages.toml"))
+        .expect("Could not parse built-in languages.toml, something must be very wrong")
+}
+
+/// The built-in `languages.toml` merged with the user's `languages.toml`, if there is one.
+pub fn user_lang_config() -> Result<toml::Value, toml::de::Error> {
+    let def_lang_conf = default_lang_config();
+    let user_lang_conf = match std::fs::read(config_dir().join("languages.toml")) {
+        Ok(raw) => Some(toml::from_slice(&raw)?),
+        Err(_) => None,
+    };
+    Ok(match user_lang_conf {
+        Some(value) => merge_toml_values(def_lang_conf, value),
+        None => def_lang_conf,
+    })
+}
+
+/// The language configuration of the syntax loader, from the merged built-in and user
+/// `languages.toml`.
+pub fn user_syntax_config() -> Result<syntax::Configuration, toml::de::Error> {
+    user_lang_config()?.try_into()
+}
@@ -0,0 +1,156 @@
+//! The sticky context of a line: the first lines of the syntax nodes around it, like the
+//! function and impl block it's in. Which nodes count is given by the `context.scm` query of
+//! a language, with an `@context` capture per kind of node.
+
+use crate::{
+    fold::{end_line, HiddenLines},
+    syntax, RopeSlice, Syntax,
+};
+use std::fmt;
+use tree_sitter::{Language, Node, Query, QueryCursor};
+
+/// The compiled `context.scm` query of a language.
+pub struct ContextQuery {
+    query: Query,
+    context_capture_index: Option<u32>,
+}
+
+impl fmt::Debug for ContextQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContextQuery")
+            .field("context_capture_index", &self.context_capture_index)
+            .finish()
+    }
+}
+
+impl ContextQuery {
+    pub fn new(language: Language, context_query: &str) -> Result<Self, tree_sitter::QueryError> {
+        let query = Query::new(language, context_query)?;
+        let context_capture_index = query
+            .capture_names()
+            .iter()
+            .position(|name| name == "context")
+            .map(|index| index as u32);
+        Ok(Self {
+            query,
+            context_capture_index,
+        })
+    }
+
+    /// The first lines of the context nodes that start above `line` and still contain it,
+    /// outermost first.
+    pub fn context_lines(&self, syntax: &Syntax, text: RopeSlice, line: usize) -> Vec<usize> {
+        let context_capture_index = match self.context_capture_index {
+            Some(index) => index,
+            None => return Vec::new(),
+        };
+        if line >= text.len_lines() {
+            return Vec::new();
+        }
+
+        // only the matches of nodes around the start of the line
+        let byte = text.line_to_byte(line);
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(byte, byte + 1);
+        let matches = cursor.matches(&self.query, syntax.tree().root_node(), |node: Node| {
+            syntax::node_to_bytes(node, text)
+        });
+
+        let mut lines = Vec::new();
+        for mat in matches {
+            for capture in mat.captures {
+                let start = capture.node.start_position().row;
+                if capture.index == context_capture_index
+                    && start < line
+                    && line <= end_line(capture.node)
+                {
+                    lines.push(start);
+                }
+            }
+        }
+        // nodes can start on the same line, like a closure passed to a call
+        lines.sort_unstable();
+        lines.dedup();
+        lines
+    }
+
+    /// The context lines to pin over the top rows of a view whose first line is `top`, at most
+    /// `max_rows` of them, innermost first to go. The pinned lines cover the rows below `top`,
+    /// so the context is that of the first line left visible under them, which can pin more
+    /// lines in turn. The rows are added until that settles.
+    pub fn sticky_lines(
+        &self,
+        syntax: &Syntax,
+        text: RopeSlice,
+        top: usize,
+        hidden: &HiddenLines,
+        max_rows: usize,
+    ) -> Vec<usize> {
+        let mut rows = 0;
+        loop {
+            let line = hidden.from_visual(hidden.to_visual(top) + rows);
+            let mut lines = self.context_lines(syntax, text, line);
+            // keep the innermost nodes
+            lines.drain(..lines.len().saturating_sub(max_rows));
+            // the rows only grow, up to `max_rows`, so this ends
+            if lines.len() <= rows {
+                return lines;
+            }
+            rows = lines.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{syntax::HighlightConfiguration, Rope};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_context_lines() {
+        let source = Rope::from(
+            "impl Foo {\n    fn new() -> Self {\n        let a = 1;\n\n        Foo\n    }\n}\n\nfn main() {}\n",
+        );
+        let language = syntax::get_language(&crate::RUNTIME_DIR, "Rust").unwrap();
+        let config = HighlightConfiguration::new(language, "", "", "").unwrap();
+        let syntax = Syntax::new(&source, Arc::new(config));
+        let query = ContextQuery::new(language, "[(impl_item) (function_item)] @context").unwrap();
+
+        let text = source.slice(..);
+        assert_eq!(query.context_lines(&syntax, text, 0), Vec::<usize>::new());
+        assert_eq!(query.context_lines(&syntax, text, 1), vec![0]);
+        assert_eq!(query.context_lines(&syntax, text, 2), vec![0, 1]);
+        assert_eq!(query.context_lines(&syntax, text, 3), vec![0, 1]);
+        assert_eq!(query.context_lines(&syntax, text, 5), vec![0, 1]);
+        assert_eq!(query.context_lines(&syntax, text, 6), vec![0]);
+        assert_eq!(query.context_lines(&syntax, text, 8), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_sticky_lines() {
+        let source = Rope::from(
+            "impl Foo {\n    fn new() -> Self {\n        let a = 1;\n\n        Foo\n    }\n}\n\nfn main() {}\n",
+        );
+        let language = syntax::get_language(&crate::RUNTIME_DIR, "Rust").unwrap();
+        let config = HighlightConfiguration::new(language, "", "", "").unwrap();
+        let syntax = Syntax::new(&source, Arc::new(config));
+        let query = ContextQuery::new(language, "[(impl_item) (function_item)] @context").unwrap();
+        let text = source.slice(..);
+        let hidden = HiddenLines::default();
+
+        // scrolled to the signature of `new`, which the pinned `impl` line then hides
+        assert_eq!(
+            query.sticky_lines(&syntax, text, 1, &hidden, 10),
+            vec![0, 1]
+        );
+        // only the innermost fits
+        assert_eq!(query.sticky_lines(&syntax, text, 1, &hidden, 1), vec![1]);
+        // the lines below the pinned ones are past the end of `new`
+        assert_eq!(query.sticky_lines(&syntax, text, 4, &hidden, 10), vec![0]);
+        assert_eq!(
+            query.sticky_lines(&syntax, text, 0, &hidden, 10),
+            Vec::<usize>::new()
+        );
+    }
+}
@@ -53,10 +53,31 @@ pub fn compare_ropes(old: &Rope, new: &Rope) -> Transaction {
     )
 }
 
+/// A unified diff of the lines of `old` and `new`, with `old_name` and `new_name` in its
+/// header.
+pub fn unified_diff(old: &Rope, new: &Rope, old_name: &str, new_name: &str) -> String {
+    let old = old.to_string();
+    let new = new.to_string();
+    similar::TextDiff::from_lines(&old, &new)
+        .unified_diff()
+        .header(old_name, new_name)
+        .to_string()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_unified_diff() {
+        let old = Rope::from("a\nb\nc\n");
+        let new = Rope::from("a\nB\nc\n");
+        assert_eq!(
+            unified_diff(&old, &new, "old", "new"),
+            "--- old\n+++ new\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n"
+        );
+    }
+
     quickcheck::quickcheck! {
         fn test_compare_ropes(a: String, b: String) -> bool {
             let mut old = Rope::from(a);
@@ -0,0 +1,403 @@
+//! Folds collapse syntax nodes spanning several lines to their first line: the rest of the lines
+//! of the node are hidden until it's unfolded.
+
+use crate::{Assoc, ChangeSet, RopeSlice, Syntax};
+use std::ops;
+use tree_sitter::Node;
+
+/// The folded ranges of a document in a view, as char ranges of the nodes that were folded.
+/// Folds may be nested, unfolding the outer one of two leaves the inner one folded.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Folds {
+    /// Sorted by their start, longer ones first.
+    folds: Vec<ops::Range<usize>>,
+}
+
+impl Folds {
+    pub fn is_empty(&self) -> bool {
+        self.folds.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ops::Range<usize>> {
+        self.folds.iter()
+    }
+
+    pub fn contains(&self, range: &ops::Range<usize>) -> bool {
+        self.folds.contains(range)
+    }
+
+    pub fn fold(&mut self, range: ops::Range<usize>) {
+        if let Err(i) = self.folds.binary_search_by(|fold| order(fold, &range)) {
+            self.folds.insert(i, range);
+        }
+    }
+
+    pub fn unfold(&mut self, range: &ops::Range<usize>) {
+        self.folds.retain(|fold| fold != range);
+    }
+
+    pub fn clear(&mut self) {
+        self.folds.clear();
+    }
+
+    /// Unfolds the folds hiding `line`, leaving the ones it's the first line of.
+    pub fn reveal_line(&mut self, text: RopeSlice, line: usize) {
+        self.folds.retain(|fold| {
+            let (start, end) = fold_lines(text, fold);
+            !(start < line && line <= end)
+        });
+    }
+
+    /// The outermost fold containing `line`: the one whose first line is shown in place of
+    /// the others.
+    pub fn fold_at_line(&self, text: RopeSlice, line: usize) -> Option<&ops::Range<usize>> {
+        self.folds.iter().find(|fold| {
+            let (start, end) = fold_lines(text, fold);
+            start <= line && line <= end
+        })
+    }
+
+    /// Maps the folds through `changes`, dropping the ones that don't span several lines
+    /// anymore. `text` is the text after the changes.
+    pub fn map(&mut self, changes: &ChangeSet, text: RopeSlice) {
+        for fold in &mut self.folds {
+            let start = changes.map_pos(fold.start, Assoc::After);
+            let end = changes.map_pos(fold.end, Assoc::Before);
+            *fold = start..end.max(start);
+        }
+        self.folds.retain(|fold| {
+            let (start, end) = fold_lines(text, fold);
+            start < end
+        });
+        self.folds.sort_by(order);
+        self.folds.dedup();
+    }
+
+    /// The lines hidden by the folds.
+    pub fn hidden_lines(&self, text: RopeSlice) -> HiddenLines {
+        let mut ranges: Vec<ops::Range<usize>> = Vec::new();
+        for fold in &self.folds {
+            let (start, end) = fold_lines(text, fold);
+            let hidden = start + 1..end + 1;
+            match ranges.last_mut() {
+                Some(last) if hidden.start <= last.end => last.end = last.end.max(hidden.end),
+                _ => ranges.push(hidden),
+            }
+        }
+        HiddenLines { ranges }
+    }
+}
+
+fn order(a: &ops::Range<usize>, b: &ops::Range<usize>) -> std::cmp::Ordering {
+    a.start.cmp(&b.start).then(b.end.cmp(&a.end))
+}
+
+/// The first and last line of a fold. A fold ending at the start of a line, after a line
+/// ending, doesn't hide that line.
+fn fold_lines(text: RopeSlice, fold: &ops::Range<usize>) -> (usize, usize) {
+    let start = text.char_to_line(fold.start.min(text.len_chars()));
+    let end = fold.end.min(text.len_chars());
+    let mut end_line = text.char_to_line(end);
+    if end_line > start && text.line_to_char(end_line) == end {
+        end_line -= 1;
+    }
+    (start, end_line)
+}
+
+/// The lines hidden by folds, as sorted and disjoint ranges of lines. Lines are mapped to
+/// "visual" rows, the rows they would be shown on if the whole document fit on screen.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HiddenLines {
+    ranges: Vec<ops::Range<usize>>,
+}
+
+impl HiddenLines {
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn is_hidden(&self, line: usize) -> bool {
+        self.range_containing(line).is_some()
+    }
+
+    fn range_containing(&self, line: usize) -> Option<&ops::Range<usize>> {
+        self.ranges.iter().find(|range| range.contains(&line))
+    }
+
+    /// The number of lines hidden right after `line`.
+    pub fn hidden_after(&self, line: usize) -> usize {
+        self.ranges
+            .iter()
+            .find(|range| range.start == line + 1)
+            .map_or(0, |range| range.len())
+    }
+
+    /// The visual row of `line`. Hidden lines are on the row of the line they're folded into.
+    pub fn to_visual(&self, line: usize) -> usize {
+        let mut row = line;
+        for range in &self.ranges {
+            if range.end <= line {
+                row -= range.len();
+            } else if range.start <= line {
+                row -= line - range.start + 1;
+            } else {
+                break;
+            }
+        }
+        row
+    }
+
+    /// The line shown on visual row `row`.
+    pub fn from_visual(&self, row: usize) -> usize {
+        let mut line = row;
+        for range in &self.ranges {
+            if range.start <= line {
+                line += range.len();
+            } else {
+                break;
+            }
+        }
+        line
+    }
+
+    /// The visible lines from `line` on, starting with the line it's folded into if it's
+    /// hidden.
+    pub fn visible_lines(&self, line: usize) -> impl Iterator<Item = usize> + '_ {
+        (self.to_visual(line)..).map(move |row| self.from_visual(row))
+    }
+}
+
+/// The foldable range under `pos` that isn't folded yet, the innermost one first: a run of
+/// comments on consecutive lines, or the node of a foldable syntax node. Nodes are foldable
+/// when they span several lines and don't start on the line of a foldable ancestor, which
+/// would hide the same lines.
+pub fn foldable_range(
+    syntax: &Syntax,
+    text: RopeSlice,
+    pos: usize,
+    folds: &Folds,
+) -> Option<ops::Range<usize>> {
+    let byte = text.char_to_byte(pos);
+    let root = syntax.tree().root_node();
+    let node = root.named_descendant_for_byte_range(byte, byte)?;
+
+    let comments = comment_run(node).map(|(first, last)| first.start_byte()..last.end_byte());
+
+    let mut ancestors = vec![node];
+    while let Some(parent) = ancestors.last().and_then(|node| node.parent()) {
+        ancestors.push(parent);
+    }
+    // the root node can't be folded
+    ancestors.pop();
+
+    let mut nodes = Vec::new();
+    let mut fold_line = None;
+    for node in ancestors.into_iter().rev() {
+        if is_foldable(node, fold_line) {
+            fold_line = Some(node.start_position().row);
+            nodes.push(node.byte_range());
+        }
+    }
+
+    comments
+        .into_iter()
+        .chain(nodes.into_iter().rev())
+        .map(|range| text.byte_to_char(range.start)..text.byte_to_char(range.end))
+        .find(|range| !folds.contains(range))
+}
+
+/// The ranges of the foldable nodes nested in `level - 1` other foldable nodes, `1` is the
+/// outermost ones.
+pub fn foldable_ranges_at_level(
+    syntax: &Syntax,
+    text: RopeSlice,
+    level: usize,
+) -> Vec<ops::Range<usize>> {
+    fn visit(
+        node: Node,
+        fold_line: Option<usize>,
+        depth: usize,
+        level: usize,
+        ranges: &mut Vec<ops::Range<usize>>,
+    ) {
+        let mut cursor = node.walk();
+        for child in node.named_children(&mut cursor) {
+            if is_foldable(child, fold_line) {
+                if depth + 1 == level {
+                    ranges.push(child.byte_range());
+                } else {
+                    visit(
+                        child,
+                        Some(child.start_position().row),
+                        depth + 1,
+                        level,
+                        ranges,
+                    );
+                }
+            } else {
+                visit(child, fold_line, depth, level, ranges);
+            }
+        }
+    }
+
+    let mut ranges = Vec::new();
+    visit(syntax.tree().root_node(), None, 0, level, &mut ranges);
+    ranges
+        .into_iter()
+        .map(|range| text.byte_to_char(range.start)..text.byte_to_char(range.end))
+        .collect()
+}
+
+fn is_foldable(node: Node, fold_line: Option<usize>) -> bool {
+    let start = node.start_position().row;
+    start < end_line(node) && Some(start) != fold_line
+}
+
+/// The last line of a node, not counting a line ending it ends with.
+pub(crate) fn end_line(node: Node) -> usize {
+    let end = node.end_position();
+    if end.column == 0 && end.row > node.start_position().row {
+        end.row - 1
+    } else {
+        end.row
+    }
+}
+
+fn is_comment(node: Node) -> bool {
+    node.kind().contains("comment")
+}
+
+/// The first and last comment of the comments on consecutive lines around `node`, if there
+/// are several.
+fn comment_run(node: Node) -> Option<(Node, Node)> {
+    if !is_comment(node) {
+        return None;
+    }
+    let mut first = node;
+    while let Some(prev) = first.prev_named_sibling() {
+        if !is_comment(prev) || end_line(prev) + 1 < first.start_position().row {
+            break;
+        }
+        first = prev;
+    }
+    let mut last = node;
+    while let Some(next) = last.next_named_sibling() {
+        if !is_comment(next) || end_line(last) + 1 < next.start_position().row {
+            break;
+        }
+        last = next;
+    }
+    if first.start_position().row < end_line(last) {
+        Some((first, last))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{syntax, Rope, Transaction};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_hidden_lines() {
+        let hidden = HiddenLines {
+            ranges: vec![2..5, 7..8],
+        };
+        assert!(hidden.is_hidden(3));
+        assert!(!hidden.is_hidden(5));
+        assert_eq!(hidden.hidden_after(1), 3);
+        assert_eq!(hidden.hidden_after(6), 1);
+        assert_eq!(hidden.hidden_after(2), 0);
+
+        let rows: Vec<_> = (0..10).map(|line| hidden.to_visual(line)).collect();
+        assert_eq!(rows, vec![0, 1, 1, 1, 1, 2, 3, 3, 4, 5]);
+        let lines: Vec<_> = (0..6).map(|row| hidden.from_visual(row)).collect();
+        assert_eq!(lines, vec![0, 1, 5, 6, 8, 9]);
+
+        let visible: Vec<_> = hidden.visible_lines(3).take(4).collect();
+        assert_eq!(visible, vec![1, 5, 6, 8]);
+    }
+
+    #[test]
+    fn test_folds() {
+        let text = Rope::from("a {\n  b {\n    c\n  }\n}\nd\n");
+        let mut folds = Folds::default();
+        folds.fold(6..19);
+        folds.fold(2..21);
+        // folding the same range again doesn't do anything
+        folds.fold(6..19);
+        assert_eq!(
+            folds.iter().cloned().collect::<Vec<_>>(),
+            vec![2..21, 6..19]
+        );
+        assert_eq!(folds.fold_at_line(text.slice(..), 2), Some(&(2..21)));
+
+        let hidden = folds.hidden_lines(text.slice(..));
+        assert_eq!(hidden.ranges, vec![1..5]);
+
+        // revealing the first line of the inner fold only unfolds the outer one
+        let mut revealed = folds.clone();
+        revealed.reveal_line(text.slice(..), 1);
+        assert_eq!(revealed.iter().cloned().collect::<Vec<_>>(), vec![6..19]);
+        revealed.reveal_line(text.slice(..), 0);
+        assert_eq!(revealed.iter().cloned().collect::<Vec<_>>(), vec![6..19]);
+
+        folds.unfold(&(2..21));
+        let hidden = folds.hidden_lines(text.slice(..));
+        assert_eq!(hidden.ranges, vec![2..4]);
+
+        // deleting `c` leaves the inner fold spanning two lines
+        let mut text2 = text.clone();
+        let transaction = Transaction::change(&text, vec![(10, 16, None)].into_iter());
+        transaction.apply(&mut text2);
+        folds.map(transaction.changes(), text2.slice(..));
+        assert_eq!(folds.iter().cloned().collect::<Vec<_>>(), vec![6..13]);
+
+        // joining its lines drops it
+        let transaction = Transaction::change(&text2, vec![(9, 12, None)].into_iter());
+        let mut text3 = text2.clone();
+        transaction.apply(&mut text3);
+        folds.map(transaction.changes(), text3.slice(..));
+        assert!(folds.is_empty());
+    }
+
+    #[test]
+    fn test_foldable_ranges() {
+        let source = Rope::from(
+            "// a\n// b\nfn a() {\n    if x {\n        y();\n    }\n}\n\nimpl A {\n    fn b() {\n        z();\n    }\n}\n",
+        );
+        let language = syntax::get_language(&crate::RUNTIME_DIR, "Rust").unwrap();
+        let config = syntax::HighlightConfiguration::new(language, "", "", "").unwrap();
+        let syntax = Syntax::new(&source, Arc::new(config));
+        let text = source.slice(..);
+        let fragment = |range: ops::Range<usize>| text.slice(range).to_string();
+
+        // a comment run
+        let mut folds = Folds::default();
+        let range = foldable_range(&syntax, text, 1, &folds).unwrap();
+        assert_eq!(fragment(range), "// a\n// b");
+
+        // the innermost foldable node first, then its foldable ancestors
+        let pos = source.line_to_char(4) + 8;
+        let range = foldable_range(&syntax, text, pos, &folds).unwrap();
+        assert_eq!(fragment(range.clone()), "if x {\n        y();\n    }");
+        folds.fold(range);
+        let range = foldable_range(&syntax, text, pos, &folds).unwrap();
+        assert!(fragment(range.clone()).starts_with("fn a() {"));
+        folds.fold(range);
+        assert_eq!(foldable_range(&syntax, text, pos, &folds), None);
+
+        let ranges: Vec<_> = foldable_ranges_at_level(&syntax, text, 1)
+            .into_iter()
+            .map(|range| fragment(range).lines().next().unwrap().to_string())
+            .collect();
+        assert_eq!(ranges, vec!["fn a() {", "impl A {"]);
+        let ranges: Vec<_> = foldable_ranges_at_level(&syntax, text, 2)
+            .into_iter()
+            .map(|range| fragment(range).lines().next().unwrap().to_string())
+            .collect();
+        assert_eq!(ranges, vec!["if x {", "fn b() {"]);
+    }
+}
@@ -118,6 +118,34 @@ pub fn nth_next_grapheme_boundary(slice: RopeSlice, char_idx: usize, n: usize) -
     chunk_char_idx + tmp
 }
 
+/// Returns the number of printable ASCII chars in `slice` starting at `char_idx`, looking no
+/// further than `end`. Each of them is a grapheme one column wide on its own, except that
+/// the last one may start a cluster which continues past the run.
+///
+/// This only scans bytes, so it's a cheap way of skipping ahead on very long lines.
+#[must_use]
+pub fn printable_ascii_len(slice: RopeSlice, char_idx: usize, end: usize) -> usize {
+    let max = end.saturating_sub(char_idx);
+    if max == 0 {
+        return 0;
+    }
+
+    let (chunks, chunk_byte_idx, _, _) = slice.chunks_at_char(char_idx);
+    let mut skip = slice.char_to_byte(char_idx) - chunk_byte_idx;
+    let mut len = 0;
+
+    for chunk in chunks {
+        for byte in &chunk.as_bytes()[skip..] {
+            if len == max || !(b' '..=b'~').contains(byte) {
+                return len;
+            }
+            len += 1;
+        }
+        skip = 0;
+    }
+    len
+}
+
 /// Finds the next grapheme boundary after the given char position.
 #[must_use]
 #[inline(always)]
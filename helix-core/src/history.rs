@@ -1,6 +1,7 @@
-use crate::{ChangeSet, Rope, State, Transaction};
+use crate::{ChangeSet, Rope, Selection, State, Transaction};
 use once_cell::sync::Lazy;
 use regex::Regex;
+use std::collections::VecDeque;
 use std::num::NonZeroUsize;
 use std::time::{Duration, Instant};
 
@@ -33,7 +34,8 @@ use std::time::{Duration, Instant};
 //
 // Limitations:
 //  * Changes in selections currently don't commit history changes. The selection
-//    will only be updated to the state after a commited buffer change.
+//    will only be updated to the state after a commited buffer change. Selection
+//    changes are undone on their own with a [SelectionHistory] instead.
 //  * The vector of history revisions is currently unbounded. This might
 //    cause the memory consumption to grow significantly large during long
 //    editing sessions.
@@ -240,6 +242,61 @@ impl History {
     }
 }
 
+/// The number of earlier selections a [SelectionHistory] keeps.
+const MAX_SELECTIONS: usize = 100;
+
+/// The earlier selections of a view, so that selection changes can be undone and redone apart
+/// from edits. The selections are mapped through later edits to stay valid.
+#[derive(Debug, Default, Clone)]
+pub struct SelectionHistory {
+    selections: VecDeque<Selection>,
+    /// The index of the selection last recorded or stepped to.
+    current: usize,
+}
+
+impl SelectionHistory {
+    /// Records `selection` after the current selection, dropping the selections that were
+    /// undone.
+    pub fn record(&mut self, selection: &Selection) {
+        if self.selections.get(self.current) == Some(selection) {
+            return;
+        }
+        self.selections.truncate(self.current + 1);
+        self.selections.push_back(selection.clone());
+        if self.selections.len() > MAX_SELECTIONS {
+            self.selections.pop_front();
+        }
+        self.current = self.selections.len() - 1;
+    }
+
+    /// Steps back to the selection before `current`, which is recorded first.
+    pub fn undo(&mut self, current: &Selection) -> Option<&Selection> {
+        self.record(current);
+        if self.current == 0 {
+            return None;
+        }
+        self.current -= 1;
+        self.selections.get(self.current)
+    }
+
+    /// Steps forward to the selection that was undone last, unless `current` changed since.
+    pub fn redo(&mut self, current: &Selection) -> Option<&Selection> {
+        self.record(current);
+        if self.current + 1 >= self.selections.len() {
+            return None;
+        }
+        self.current += 1;
+        self.selections.get(self.current)
+    }
+
+    /// Maps all selections through `changes`.
+    pub fn map(&mut self, changes: &ChangeSet) {
+        for selection in self.selections.iter_mut() {
+            *selection = selection.clone().map(changes);
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum UndoKind {
     Steps(usize),
@@ -316,6 +373,40 @@ impl std::str::FromStr for UndoKind {
 mod test {
     use super::*;
 
+    #[test]
+    fn test_selection_history() {
+        let mut history = SelectionHistory::default();
+        let (a, b, c) = (
+            Selection::point(0),
+            Selection::single(0, 3),
+            Selection::point(5),
+        );
+
+        history.record(&a);
+        history.record(&b);
+        history.record(&b);
+        assert_eq!(history.undo(&c), Some(&b));
+        assert_eq!(history.undo(&b), Some(&a));
+        assert_eq!(history.undo(&a), None);
+        assert_eq!(history.redo(&a), Some(&b));
+        assert_eq!(history.redo(&b), Some(&c));
+        assert_eq!(history.redo(&c), None);
+
+        // a new selection drops the undone ones
+        history.undo(&c);
+        assert_eq!(history.redo(&a), None);
+        assert_eq!(history.undo(&a), Some(&b));
+
+        // selections follow edits
+        let text = Rope::from("hello");
+        let transaction = Transaction::change(&text, vec![(0, 0, Some("ab".into()))].into_iter());
+        history.map(transaction.changes());
+        assert_eq!(
+            history.undo(&Selection::single(2, 5)),
+            Some(&Selection::point(2))
+        );
+    }
+
     #[test]
     fn test_undo_redo() {
         let mut history = History::default();
@@ -1,8 +1,10 @@
+use std::sync::Arc;
+
 use crate::{
     chars::{char_is_line_ending, char_is_whitespace},
     find_first_non_whitespace_char,
-    syntax::{IndentQuery, LanguageConfiguration, Syntax},
-    tree_sitter::Node,
+    syntax::{IndentQuery, LanguageConfiguration, LanguageLayer, Syntax},
+    tree_sitter::{Node, Tree},
     Rope, RopeSlice,
 };
 
@@ -174,7 +176,6 @@ pub fn auto_detect_indent_style(document_text: &Rope) -> Option<IndentStyle> {
 
 /// To determine indentation of a newly inserted line, figure out the indentation at the last col
 /// of the previous line.
-#[allow(dead_code)]
 fn indent_level_for_line(line: RopeSlice, tab_width: usize) -> usize {
     let mut len = 0;
     for ch in line.chars() {
@@ -190,9 +191,7 @@ fn indent_level_for_line(line: RopeSlice, tab_width: usize) -> usize {
 
 /// Find the highest syntax node at position.
 /// This is to identify the column where this node (e.g., an HTML closing tag) ends.
-fn get_highest_syntax_node_at_bytepos(syntax: &Syntax, pos: usize) -> Option<Node> {
-    let tree = syntax.tree();
-
+fn get_highest_syntax_node_at_bytepos(tree: &Tree, pos: usize) -> Option<Node> {
     // named_descendant
     let mut node = match tree.root_node().descendant_for_byte_range(pos, pos) {
         Some(node) => node,
@@ -295,29 +294,65 @@ pub fn suggested_indent_for_pos(
     pos: usize,
     new_line: bool,
 ) -> usize {
-    if let (Some(query), Some(syntax)) = (
-        language_config.and_then(|config| config.indent_query()),
-        syntax,
-    ) {
-        let byte_start = text.char_to_byte(pos);
-        let node = get_highest_syntax_node_at_bytepos(syntax, byte_start);
+    let syntax = match syntax {
+        Some(syntax) => syntax,
+        // TODO: heuristics for non-tree sitter grammars
+        None => return 0,
+    };
+    let byte_start = text.char_to_byte(pos);
+
+    // Injected languages with an indent query of their own are indented with it. A language
+    // injected into itself, like Rust macro bodies, is indented as part of the document.
+    let root_config = syntax.root_layer().config();
+    let injection = syntax
+        .layers_for_byte_range(byte_start, byte_start)
+        .filter(|layer| !Arc::ptr_eq(layer.config(), root_config))
+        .find_map(|layer| Some((layer, layer.language_config()?.indent_query()?)));
+    if let Some((layer, query)) = injection {
+        let tab_width = language_config
+            .and_then(|config| config.indent.as_ref())
+            .map_or(4, |indent| indent.tab_width);
+        let node = get_highest_syntax_node_at_bytepos(layer.tree(), byte_start);
+        return injection_indent(layer, text, tab_width)
+            + calculate_indentation(query, node, new_line);
+    }
 
-        // let config = load indentation query config from Syntax(should contain language_config)
+    if let Some(query) = language_config.and_then(|config| config.indent_query()) {
+        let node = get_highest_syntax_node_at_bytepos(syntax.tree(), byte_start);
 
         // TODO: special case for comments
         // TODO: if preserve_leading_whitespace
         calculate_indentation(query, node, new_line)
     } else {
-        // TODO: heuristics for non-tree sitter grammars
         0
     }
 }
 
+/// The indentation that injected code starts at: the indentation of the line the injection
+/// starts on, one level deeper if other text comes before it on that line (like the contents
+/// of a `<script>` tag).
+fn injection_indent(layer: &LanguageLayer, text: RopeSlice, tab_width: usize) -> usize {
+    let start = match layer.ranges().first() {
+        Some(range) => text.byte_to_char(range.start_byte.min(text.len_bytes())),
+        None => return 0,
+    };
+    let line_idx = text.char_to_line(start);
+    let line = text.line(line_idx);
+    let indent = indent_level_for_line(line, tab_width);
+
+    let line_start = text.line_to_char(line_idx);
+    let starts_after_text = text
+        .slice(line_start..start)
+        .chars()
+        .any(|ch| !ch.is_whitespace());
+    indent + starts_after_text as usize
+}
+
 pub fn get_scopes(syntax: Option<&Syntax>, text: RopeSlice, pos: usize) -> Vec<&'static str> {
     let mut scopes = Vec::new();
     if let Some(syntax) = syntax {
         let byte_start = text.char_to_byte(pos);
-        let node = match get_highest_syntax_node_at_bytepos(syntax, byte_start) {
+        let node = match get_highest_syntax_node_at_bytepos(syntax.tree(), byte_start) {
             Some(node) => node,
             None => return scopes,
         };
@@ -445,6 +480,9 @@ where
             language: vec![LanguageConfiguration {
                 scope: "source.rust".to_string(),
                 file_types: vec!["rs".to_string()],
+                file_globs: vec![],
+                shebangs: vec![],
+                first_line_regex: None,
                 language_id: "Rust".to_string(),
                 highlight_config: OnceCell::new(),
                 config: None,
@@ -457,7 +495,11 @@ where
                     tab_width: 4,
                     unit: String::from("    "),
                 }),
+                rulers: None,
+                grammar: None,
                 indent_query: OnceCell::new(),
+                tags_config: OnceCell::new(),
+                context_query: OnceCell::new(),
             }],
         });
 
@@ -1,8 +1,11 @@
 pub mod auto_pairs;
 pub mod chars;
 pub mod comment;
+pub mod config;
+pub mod context;
 pub mod diagnostic;
 pub mod diff;
+pub mod fold;
 pub mod graphemes;
 pub mod history;
 pub mod indent;
@@ -19,6 +22,7 @@ pub mod selection;
 mod state;
 pub mod surround;
 pub mod syntax;
+pub mod tags;
 pub mod textobject;
 mod transaction;
 
@@ -193,7 +197,10 @@ pub use tendril::StrTendril as Tendril;
 pub use {regex, tree_sitter};
 
 pub use graphemes::RopeGraphemes;
-pub use position::{coords_at_pos, pos_at_coords, Position};
+pub use position::{
+    coords_at_pos, line_column_at_pos, pos_at_coords, pos_at_visual_col, visual_col_at_pos,
+    LineColumn, Position,
+};
 pub use selection::{Range, Selection};
 pub use smallvec::SmallVec;
 pub use syntax::Syntax;
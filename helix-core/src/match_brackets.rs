@@ -1,4 +1,5 @@
-use crate::{Rope, Syntax};
+use crate::{Rope, RopeSlice, Syntax};
+use tree_sitter::Node;
 
 const PAIRS: &[(char, char)] = &[('(', ')'), ('{', '}'), ('[', ']'), ('<', '>')];
 // limit matching pairs to only ( ) { } [ ] < >
@@ -46,3 +47,94 @@ pub fn find(syntax: &Syntax, doc: &Rope, pos: usize) -> Option<usize> {
 
     None
 }
+
+/// Returns the nesting depth and char position of every bracket inside the char `range`.
+///
+/// Brackets are taken from the syntax tree: a node counts as bracketed if it has both
+/// the opening and the closing bracket of a pair as anonymous children. This keeps
+/// brackets inside strings and comments, and comparison operators, out of the result.
+pub fn bracket_depths(
+    syntax: &Syntax,
+    text: RopeSlice,
+    range: std::ops::Range<usize>,
+) -> Vec<(usize, usize)> {
+    let start = text.char_to_byte(range.start);
+    let end = text.char_to_byte(range.end);
+
+    let mut brackets = Vec::new();
+    // (node, number of bracketed ancestors)
+    let mut stack = vec![(syntax.tree().root_node(), 0)];
+
+    while let Some((node, depth)) = stack.pop() {
+        let mut cursor = node.walk();
+        let children: Vec<Node> = node.children(&mut cursor).collect();
+
+        let pairs: Vec<(char, char)> = PAIRS
+            .iter()
+            .copied()
+            .filter(|&(open, close)| {
+                children.iter().any(|child| is_bracket(child, open))
+                    && children.iter().any(|child| is_bracket(child, close))
+            })
+            .collect();
+        let child_depth = if pairs.is_empty() { depth } else { depth + 1 };
+
+        for child in children {
+            if child.end_byte() <= start || child.start_byte() >= end {
+                continue;
+            }
+            if pairs
+                .iter()
+                .any(|&(open, close)| is_bracket(&child, open) || is_bracket(&child, close))
+            {
+                brackets.push((depth, text.byte_to_char(child.start_byte())));
+            } else if child.child_count() > 0 {
+                stack.push((child, child_depth));
+            }
+        }
+    }
+
+    brackets.sort_unstable_by_key(|&(_, pos)| pos);
+    brackets
+}
+
+fn is_bracket(node: &Node, bracket: char) -> bool {
+    let kind = node.kind();
+    !node.is_named() && kind.len() == bracket.len_utf8() && kind.starts_with(bracket)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::syntax::{get_language, HighlightConfiguration};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_bracket_depths() {
+        let language = get_language(&crate::RUNTIME_DIR, "Rust").unwrap();
+        let config = HighlightConfiguration::new(language, "", "", "").unwrap();
+
+        let source = Rope::from("fn main() { let s = \"(\"; foo(bar[0] < 1); }");
+        let syntax = Syntax::new(&source, Arc::new(config));
+        let text = source.slice(..);
+
+        let brackets: Vec<_> = bracket_depths(&syntax, text, 0..text.len_chars())
+            .into_iter()
+            .map(|(depth, pos)| (depth, text.char(pos)))
+            .collect();
+
+        assert_eq!(
+            brackets,
+            vec![
+                (0, '('),
+                (0, ')'),
+                (0, '{'),
+                (1, '('),
+                (2, '['),
+                (2, ']'),
+                (1, ')'),
+                (0, '}'),
+            ]
+        );
+    }
+}
@@ -52,16 +52,22 @@ pub fn move_vertically(
 ) -> Range {
     let pos = range.cursor(slice);
 
-    // Compute the current position's 2d coordinates.
-    let Position { row, col } = coords_at_pos(slice, pos);
-    let horiz = range.horiz.unwrap_or(col as u32);
+    // Compute the current position's 2d coordinates. The column only needs counting when
+    // there's no column kept from the previous vertical move, which the cursor can't be past.
+    let (row, horiz) = match range.horiz {
+        Some(horiz) => (slice.char_to_line(pos), horiz),
+        None => {
+            let Position { row, col } = coords_at_pos(slice, pos);
+            (row, col as u32)
+        }
+    };
 
     // Compute the new position.
     let new_row = match dir {
         Direction::Forward => (row + count).min(slice.len_lines().saturating_sub(1)),
         Direction::Backward => row.saturating_sub(count),
     };
-    let new_col = col.max(horiz as usize);
+    let new_col = horiz as usize;
     let new_pos = pos_at_coords(slice, Position::new(new_row, new_col), true);
 
     // Special-case to avoid moving to the end of the last non-empty line.
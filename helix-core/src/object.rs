@@ -3,18 +3,19 @@ use crate::{Range, RopeSlice, Selection, Syntax};
 // TODO: to contract_selection we'd need to store the previous ranges before expand.
 // Maybe just contract to the first child node?
 pub fn expand_selection(syntax: &Syntax, text: RopeSlice, selection: &Selection) -> Selection {
-    let tree = syntax.tree();
-
     selection.clone().transform(|range| {
         let from = text.char_to_byte(range.from());
         let to = text.char_to_byte(range.to());
 
-        // find parent of a descendant that matches the range
-        let parent = match tree
-            .root_node()
-            .descendant_for_byte_range(from, to)
-            .and_then(|node| node.parent())
-        {
+        // find parent of a descendant that matches the range, in the innermost layer that has
+        // one so that selections expand out of injected code last
+        let parent = match syntax.layers_for_byte_range(from, to).find_map(|layer| {
+            layer
+                .tree()
+                .root_node()
+                .descendant_for_byte_range(from, to)
+                .and_then(|node| node.parent())
+        }) {
             Some(parent) => parent,
             None => return range,
         };
@@ -1,6 +1,11 @@
+use std::borrow::Cow;
+
 use crate::{
     chars::char_is_line_ending,
-    graphemes::{ensure_grapheme_boundary_prev, RopeGraphemes},
+    graphemes::{
+        ensure_grapheme_boundary_prev, grapheme_width, next_grapheme_boundary,
+        prev_grapheme_boundary, printable_ascii_len,
+    },
     line_ending::line_end_char_index,
     RopeSlice,
 };
@@ -64,11 +69,172 @@ pub fn coords_at_pos(text: RopeSlice, pos: usize) -> Position {
 
     let line_start = text.line_to_char(line);
     let pos = ensure_grapheme_boundary_prev(text, pos);
-    let col = RopeGraphemes::new(text.slice(line_start..pos)).count();
+
+    let mut col = 0;
+    let mut idx = line_start;
+    while idx < pos {
+        let (next, graphemes, _) = grapheme_step(text, idx, pos, 1);
+        col += graphemes;
+        idx = next;
+    }
 
     Position::new(line, col)
 }
 
+/// Returns the visual column of `pos` on its line, with tabs `tab_width` columns wide.
+pub fn visual_col_at_pos(text: RopeSlice, pos: usize, tab_width: usize) -> usize {
+    let line_start = text.line_to_char(text.char_to_line(pos));
+    let pos = ensure_grapheme_boundary_prev(text, pos);
+
+    let mut col = 0;
+    let mut idx = line_start;
+    while idx < pos {
+        let (next, _, width) = grapheme_step(text, idx, pos, tab_width);
+        col += width;
+        idx = next;
+    }
+    col
+}
+
+/// The grapheme and visual columns of a position on its line.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LineColumn {
+    pub pos: usize,
+    pub col: usize,
+    pub visual_col: usize,
+}
+
+/// Returns the grapheme and visual columns of `pos`, like [`coords_at_pos`] and
+/// [`visual_col_at_pos`]. If the columns of another position on the same line are `known`,
+/// they are counted from there when that's closer than the start of the line, so that moving
+/// along a long line doesn't walk all of it on every step.
+pub fn line_column_at_pos(
+    text: RopeSlice,
+    pos: usize,
+    tab_width: usize,
+    known: Option<LineColumn>,
+) -> LineColumn {
+    let line = text.char_to_line(pos);
+    let line_start = text.line_to_char(line);
+    let pos = ensure_grapheme_boundary_prev(text, pos);
+
+    // the columns covered by the graphemes from `start` to `end`
+    let span = |start: usize, end: usize| {
+        let (mut col, mut visual_col) = (0, 0);
+        let mut idx = start;
+        while idx < end {
+            let (next, graphemes, width) = grapheme_step(text, idx, end, tab_width);
+            col += graphemes;
+            visual_col += width;
+            idx = next;
+        }
+        (col, visual_col)
+    };
+
+    match known {
+        Some(known) if line_start <= known.pos && known.pos <= pos => {
+            let (col, visual_col) = span(known.pos, pos);
+            LineColumn {
+                pos,
+                col: known.col + col,
+                visual_col: known.visual_col + visual_col,
+            }
+        }
+        Some(known)
+            if pos < known.pos
+                && known.pos - pos < pos - line_start
+                && known.pos <= line_end_char_index(&text, line) =>
+        {
+            let (col, visual_col) = span(pos, known.pos);
+            LineColumn {
+                pos,
+                col: known.col - col,
+                visual_col: known.visual_col - visual_col,
+            }
+        }
+        _ => {
+            let (col, visual_col) = span(line_start, pos);
+            LineColumn {
+                pos,
+                col,
+                visual_col,
+            }
+        }
+    }
+}
+
+/// Returns the char index of the first grapheme on `line` that starts at or after the visual
+/// column `col`, together with the column it starts at. If the line is shorter than that,
+/// the position of its line ending is returned instead. If the visual column of another
+/// position on the line is `known`, like the result of seeking the same line before, the
+/// graphemes are counted from there when that's closer than the start of the line.
+pub fn pos_at_visual_col(
+    text: RopeSlice,
+    line: usize,
+    col: usize,
+    tab_width: usize,
+    known: Option<(usize, usize)>,
+) -> (usize, usize) {
+    let line_start = text.line_to_char(line);
+    let line_end = line_end_char_index(&text, line);
+
+    let (mut idx, mut x) = match known {
+        Some((pos, _)) if pos < line_start || pos > line_end => (line_start, 0),
+        Some((pos, x)) if x <= col => (pos, x),
+        Some((mut pos, mut x)) if x - col < col => {
+            // step back over the graphemes that start at or after `col`
+            while pos > line_start {
+                let prev = prev_grapheme_boundary(text, pos);
+                let (_, _, width) = grapheme_step(text, prev, pos, tab_width);
+                if x - width < col {
+                    break;
+                }
+                x -= width;
+                pos = prev;
+            }
+            return (pos, x);
+        }
+        _ => (line_start, 0),
+    };
+    while idx < line_end && x < col {
+        let (next, graphemes, width) = grapheme_step(text, idx, line_end, tab_width);
+        // runs of ascii are one column per char, so they can be split
+        if graphemes > 1 && x + width > col {
+            return (idx + col - x, col);
+        }
+        x += width;
+        idx = next;
+    }
+    (idx, x)
+}
+
+/// Advances from `pos` towards `end` by either a run of printable ASCII, or a single
+/// grapheme. Returns the char index after the step, the number of graphemes stepped over
+/// and their visual width.
+fn grapheme_step(
+    text: RopeSlice,
+    pos: usize,
+    end: usize,
+    tab_width: usize,
+) -> (usize, usize, usize) {
+    // leave the last char of the run to the grapheme segmentation below, it could be
+    // followed by a combining character
+    let ascii = printable_ascii_len(text, pos, end);
+    if ascii > 1 {
+        let len = if pos + ascii == end { ascii } else { ascii - 1 };
+        return (pos + len, len, len);
+    }
+
+    let next = next_grapheme_boundary(text, pos).min(end);
+    let grapheme = text.slice(pos..next);
+    let width = if grapheme == "\t" {
+        tab_width
+    } else {
+        grapheme_width(&Cow::from(grapheme))
+    };
+    (next, 1, width)
+}
+
 /// Convert (line, column) coordinates to a character index.
 ///
 /// If the `line` coordinate is beyond the end of the file, the EOF
@@ -97,15 +263,18 @@ pub fn pos_at_coords(text: RopeSlice, coords: Position, limit_before_line_ending
         text.line_to_char((row + 1).min(text.len_lines()))
     };
 
-    let mut col_char_offset = 0;
-    for (i, g) in RopeGraphemes::new(text.slice(line_start..line_end)).enumerate() {
-        if i == col {
-            break;
+    let mut i = 0;
+    let mut pos = line_start;
+    while pos < line_end && i < col {
+        let (next, graphemes, _) = grapheme_step(text, pos, line_end, 1);
+        // runs of ascii are one grapheme per char, so they can be split
+        if i + graphemes > col {
+            return pos + col - i;
         }
-        col_char_offset += g.chars().count();
+        i += graphemes;
+        pos = next;
     }
-
-    line_start + col_char_offset
+    pos
 }
 
 #[cfg(test)]
@@ -226,4 +395,101 @@ mod test {
         assert_eq!(pos_at_coords(slice, (0, 1).into(), false), 1);
         assert_eq!(pos_at_coords(slice, (0, 2).into(), false), 2);
     }
+
+    #[test]
+    fn test_visual_col() {
+        let text = Rope::from("\tab今日a̐c\n");
+        let slice = text.slice(..);
+        assert_eq!(visual_col_at_pos(slice, 0, 4), 0);
+        assert_eq!(visual_col_at_pos(slice, 1, 4), 4); // a
+        assert_eq!(visual_col_at_pos(slice, 3, 4), 6); // 今
+        assert_eq!(visual_col_at_pos(slice, 4, 4), 8); // 日
+        assert_eq!(visual_col_at_pos(slice, 5, 4), 10); // a̐
+        assert_eq!(visual_col_at_pos(slice, 7, 4), 11); // c
+
+        assert_eq!(pos_at_visual_col(slice, 0, 0, 4, None), (0, 0));
+        // inside the tab
+        assert_eq!(pos_at_visual_col(slice, 0, 2, 4, None), (1, 4));
+        assert_eq!(pos_at_visual_col(slice, 0, 5, 4, None), (2, 5));
+        // inside 今
+        assert_eq!(pos_at_visual_col(slice, 0, 7, 4, None), (4, 8));
+        assert_eq!(pos_at_visual_col(slice, 0, 10, 4, None), (5, 10));
+        assert_eq!(pos_at_visual_col(slice, 0, 11, 4, None), (7, 11));
+        // past the end of the line
+        assert_eq!(pos_at_visual_col(slice, 0, 20, 4, None), (8, 12));
+    }
+
+    #[test]
+    fn test_long_line() {
+        // long enough to span many chunks of the rope, with a combining character at the end
+        let line = "a".repeat(100_000);
+        let text = Rope::from(format!("{}\u{0310}b\n{}", line, line));
+        let slice = text.slice(..);
+
+        assert_eq!(coords_at_pos(slice, 50_000), (0, 50_000).into());
+        // the last `a` and the combining character are a single grapheme
+        assert_eq!(coords_at_pos(slice, 100_001), (0, 100_000).into());
+        assert_eq!(pos_at_coords(slice, (0, 100_000).into(), false), 100_001);
+        assert_eq!(coords_at_pos(slice, 100_003 + 70_000), (1, 70_000).into());
+        assert_eq!(
+            pos_at_coords(slice, (1, 70_000).into(), false),
+            100_003 + 70_000
+        );
+
+        assert_eq!(visual_col_at_pos(slice, 100_001, 4), 100_000);
+
+        // counted from a known column, in both directions
+        let known = line_column_at_pos(slice, 99_990, 4, None);
+        assert_eq!(known.visual_col, 99_990);
+        let column = line_column_at_pos(slice, 100_002, 4, Some(known));
+        assert_eq!((column.col, column.visual_col), (100_001, 100_001));
+        let column = line_column_at_pos(slice, 99_980, 4, Some(column));
+        assert_eq!((column.col, column.visual_col), (99_980, 99_980));
+        // a known column on another line is ignored
+        let column = line_column_at_pos(slice, 100_003 + 5, 4, Some(known));
+        assert_eq!((column.col, column.visual_col), (5, 5));
+        assert_eq!(
+            pos_at_visual_col(slice, 0, 99_999, 4, None),
+            (99_999, 99_999)
+        );
+        assert_eq!(
+            pos_at_visual_col(slice, 0, 100_000, 4, None),
+            (100_001, 100_000)
+        );
+    }
+
+    #[test]
+    fn test_long_line_seek() {
+        // wide, tab and combining graphemes, none of which take the ascii fast path
+        let line = "今\tа̐".repeat(20_000);
+        let text = Rope::from(format!("{}\n{}", line, line));
+        let slice = text.slice(..);
+        // each repeat is 4 chars and 2 + 4 + 1 = 7 columns wide
+        let second = 4 * 20_000 + 1;
+
+        let from_start = |line, col| pos_at_visual_col(slice, line, col, 4, None);
+        assert_eq!(from_start(0, 70_000), (40_000, 70_000));
+        // inside 今 and inside the tab
+        assert_eq!(from_start(0, 70_001), (40_001, 70_002));
+        assert_eq!(from_start(0, 70_003), (40_002, 70_006));
+
+        // seeking from a known column gives the same results in both directions
+        let known = from_start(0, 70_000);
+        for col in [69_990, 69_999, 70_000, 70_001, 70_003, 70_010, 100_000, 20] {
+            assert_eq!(
+                pos_at_visual_col(slice, 0, col, 4, Some(known)),
+                from_start(0, col)
+            );
+        }
+        // past the end of the line
+        assert_eq!(
+            pos_at_visual_col(slice, 0, 200_000, 4, Some(known)),
+            (second - 1, 140_000)
+        );
+        // a known column on another line is ignored
+        assert_eq!(
+            pos_at_visual_col(slice, 1, 7, 4, Some(known)),
+            (second + 4, 7)
+        );
+    }
 }
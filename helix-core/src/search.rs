@@ -1,4 +1,5 @@
-use crate::RopeSlice;
+use crate::{Range, RopeSlice};
+use std::ops;
 
 pub fn find_nth_next(text: RopeSlice, ch: char, mut pos: usize, n: usize) -> Option<usize> {
     if pos >= text.len_chars() || n == 0 {
@@ -43,3 +44,145 @@ pub fn find_nth_prev(text: RopeSlice, ch: char, mut pos: usize, n: usize) -> Opt
 
     Some(pos)
 }
+
+/// The next literal occurrence of `query` in `text` from the char index `start` on, wrapping
+/// around to the start of the text, that doesn't overlap one of `ranges`.
+pub fn find_next_occurrence(
+    text: RopeSlice,
+    query: &str,
+    start: usize,
+    ranges: &[Range],
+) -> Option<Range> {
+    if query.is_empty() {
+        return None;
+    }
+
+    let start = text.char_to_byte(start);
+    let occurrence = |i: usize| {
+        let range = Range::new(text.byte_to_char(i), text.byte_to_char(i + query.len()));
+        if ranges.iter().any(|other| other.overlaps(&range)) {
+            None
+        } else {
+            Some(range)
+        }
+    };
+    find_map_occurrences(text, query, start..text.len_bytes(), occurrence)
+        .or_else(|| find_map_occurrences(text, query, 0..start, occurrence))
+}
+
+/// Calls `f` with the byte index of each literal occurrence of `query` in `text` that starts
+/// within `bytes`, until it returns something. Occurrences don't overlap. The text is searched
+/// chunk by chunk instead of being copied in full, with the end of each chunk carried over to
+/// the next one for the occurrences that span both.
+fn find_map_occurrences<T>(
+    text: RopeSlice,
+    query: &str,
+    bytes: ops::Range<usize>,
+    mut f: impl FnMut(usize) -> Option<T>,
+) -> Option<T> {
+    let (mut chunks, chunk_start, _, _) = text.chunks_at_byte(bytes.start);
+    // the text from `offset` on that's searched, the rest of a chunk and the next one
+    let mut window = match chunks.next() {
+        Some(chunk) => chunk[bytes.start - chunk_start..].to_string(),
+        None => return None,
+    };
+    let mut offset = bytes.start;
+    let mut next_start = bytes.start;
+
+    while offset < bytes.end {
+        for (i, _) in window.match_indices(query) {
+            let i = offset + i;
+            if i >= bytes.end {
+                return None;
+            }
+            // skip what overlaps the previous occurrence
+            if i < next_start {
+                continue;
+            }
+            next_start = i + query.len();
+            if let Some(found) = f(i) {
+                return Some(found);
+            }
+        }
+
+        // carry over the end that could be the start of an occurrence, from a char boundary
+        let mut cut = window.len().saturating_sub(query.len() - 1);
+        while !window.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        offset += cut;
+        window.drain(..cut);
+        match chunks.next() {
+            Some(chunk) => window.push_str(chunk),
+            None => break,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Rope, Selection};
+
+    #[test]
+    fn test_find_next_occurrence() {
+        let text = Rope::from("foo bär foo bär foo");
+        let text = text.slice(..);
+
+        let selection = Selection::single(0, 3);
+        assert_eq!(
+            find_next_occurrence(text, "foo", 3, selection.ranges()),
+            Some(Range::new(8, 11))
+        );
+        assert_eq!(
+            find_next_occurrence(text, "bär", 5, selection.ranges()),
+            Some(Range::new(12, 15))
+        );
+
+        // wraps around, skipping selected occurrences
+        let selection = selection.push(Range::new(16, 19));
+        assert_eq!(
+            find_next_occurrence(text, "foo", 19, selection.ranges()),
+            Some(Range::new(8, 11))
+        );
+        let selection = selection.push(Range::new(8, 11));
+        assert_eq!(
+            find_next_occurrence(text, "foo", 11, selection.ranges()),
+            None
+        );
+
+        assert_eq!(find_next_occurrence(text, "", 0, &[]), None);
+    }
+
+    #[test]
+    fn test_find_next_occurrence_across_chunks() {
+        // many chunks, so that some of the occurrences span two of them
+        let source = "äöü foo ".repeat(2000);
+        let text = Rope::from(source.as_str());
+        let text = text.slice(..);
+
+        let mut start = 0;
+        let mut selection = Selection::single(0, 0);
+        let mut found = 0;
+        while let Some(range) = find_next_occurrence(text, "ü foo ä", start, selection.ranges()) {
+            assert_eq!(text.slice(range.from()..range.to()), "ü foo ä");
+            start = range.to();
+            selection = selection.push(range);
+            found += 1;
+        }
+        assert_eq!(found, 1999);
+
+        // occurrences don't overlap, but they're searched for from `start` on
+        let text = Rope::from("aaa");
+        let text = text.slice(..);
+        assert_eq!(
+            find_next_occurrence(text, "aa", 1, &[]),
+            Some(Range::new(1, 3))
+        );
+        assert_eq!(
+            find_next_occurrence(text, "aa", 2, &[]),
+            Some(Range::new(0, 2))
+        );
+    }
+}
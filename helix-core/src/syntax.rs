@@ -1,6 +1,8 @@
 use crate::{
     chars::char_is_line_ending,
+    context::ContextQuery,
     regex::Regex,
+    tags::TagsConfiguration,
     transaction::{ChangeSet, Operation},
     Rope, RopeSlice, Tendril,
 };
@@ -12,14 +14,15 @@ use arc_swap::ArcSwap;
 use std::{
     borrow::Cow,
     cell::RefCell,
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     fmt,
-    path::Path,
+    path::{Path, PathBuf},
     sync::Arc,
 };
 
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
 use once_cell::sync::{Lazy, OnceCell};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Configuration {
@@ -33,30 +36,93 @@ pub struct LanguageConfiguration {
     #[serde(rename = "name")]
     pub(crate) language_id: String,
     pub scope: String,           // source.rust
-    pub file_types: Vec<String>, // filename ends_with? <Gemfile, rb, etc>
-    pub roots: Vec<String>,      // these indicate project roots <.git, Cargo.toml>
+    pub file_types: Vec<String>, // file extensions or exact file names <Gemfile, rb, etc>
+    /// Glob patterns matched against the whole path, `*.env.*` matches in any directory.
+    #[serde(default, deserialize_with = "deserialize_globs")]
+    pub file_globs: Vec<String>,
+    /// Interpreters in the shebang line of a script, without version numbers (`python`
+    /// matches `#!/usr/bin/env python3`).
+    #[serde(default)]
+    pub shebangs: Vec<String>,
+    /// A regex matched against the first line of files that aren't recognized otherwise.
+    #[serde(default, skip_serializing, deserialize_with = "deserialize_regex")]
+    pub first_line_regex: Option<Regex>,
+    pub roots: Vec<String>, // these indicate project roots <.git, Cargo.toml>
     pub comment_token: Option<String>,
     pub config: Option<String>,
 
     #[serde(default)]
     pub auto_format: bool,
 
-    // content_regex
     // injection_regex
-    // first_line_regex
     //
     #[serde(skip)]
     pub(crate) highlight_config: OnceCell<Option<Arc<HighlightConfiguration>>>,
-    // tags_config OnceCell<> https://github.com/tree-sitter/tree-sitter/pull/583
+    #[serde(skip)]
+    pub(crate) tags_config: OnceCell<Option<TagsConfiguration>>,
+    #[serde(skip)]
+    pub(crate) context_query: OnceCell<Option<ContextQuery>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub language_server: Option<LanguageServerConfiguration>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub indent: Option<IndentationConfiguration>,
+    /// Where `hx --grammar build` gets the grammar of the language from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grammar: Option<GrammarSource>,
+    /// Columns at which to draw rulers, overriding the editor-wide `rulers`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rulers: Option<Vec<u16>>,
 
     #[serde(skip)]
     pub(crate) indent_query: OnceCell<Option<IndentQuery>>,
 }
 
+fn deserialize_globs<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let globs = Vec::<String>::deserialize(deserializer)?;
+    for glob in &globs {
+        file_glob(glob).map_err(serde::de::Error::custom)?;
+    }
+    Ok(globs)
+}
+
+fn deserialize_regex<'de, D>(deserializer: D) -> Result<Option<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|regex| Regex::new(&regex).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Builds the glob of a `file-globs` pattern, relative patterns match in any directory.
+fn file_glob(pattern: &str) -> Result<Glob, globset::Error> {
+    let pattern = if pattern.starts_with('/') || pattern.starts_with("**") {
+        Cow::Borrowed(pattern)
+    } else {
+        Cow::Owned(format!("**/{}", pattern))
+    };
+    GlobBuilder::new(&pattern).literal_separator(true).build()
+}
+
+/// Returns the name of the interpreter of a shebang line without its version number, looking
+/// through `env`: both `#!/bin/python3` and `#!/usr/bin/env -S python3.9 -u` give `python`.
+fn shebang_interpreter(line: &str) -> Option<&str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|word| !word.starts_with('-') && !word.contains('='))?;
+    }
+    let name = program.trim_end_matches(|ch: char| ch.is_ascii_digit() || ch == '.');
+    if name.is_empty() {
+        Some(program)
+    } else {
+        Some(name)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct LanguageServerConfiguration {
@@ -66,6 +132,21 @@ pub struct LanguageServerConfiguration {
     pub args: Vec<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum GrammarSource {
+    /// A grammar directory on disk, containing `src/parser.c` or `parser.c` itself.
+    Local { path: PathBuf },
+    /// A revision of a git repository, fetched into the runtime directory. `subpath` is the
+    /// directory of the grammar in repositories with several grammars.
+    Git {
+        git: String,
+        rev: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        subpath: Option<PathBuf>,
+    },
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct IndentationConfiguration {
@@ -84,7 +165,8 @@ pub struct IndentQuery {
     pub outdent: HashSet<String>,
 }
 
-fn load_runtime_file(language: &str, filename: &str) -> Result<String, std::io::Error> {
+/// Reads `filename` from the query directory of `language` in the runtime directory.
+pub fn load_runtime_file(language: &str, filename: &str) -> Result<String, std::io::Error> {
     let path = crate::RUNTIME_DIR
         .join("queries")
         .join(language)
@@ -92,7 +174,9 @@ fn load_runtime_file(language: &str, filename: &str) -> Result<String, std::io::
     std::fs::read_to_string(&path)
 }
 
-fn read_query(language: &str, filename: &str) -> String {
+/// Reads the query `filename` of `language`, prepended with the queries it inherits. Missing
+/// queries are empty.
+pub fn read_query(language: &str, filename: &str) -> String {
     static INHERITS_REGEX: Lazy<Regex> =
         Lazy::new(|| Regex::new(r";+\s*inherits\s*:?\s*([a-z_,()]+)\s*").unwrap());
 
@@ -178,16 +262,57 @@ impl LanguageConfiguration {
             .as_ref()
     }
 
+    /// The compiled `tags.scm` query of the language, if it has one.
+    pub fn tags_config(&self) -> Option<&TagsConfiguration> {
+        self.tags_config
+            .get_or_init(|| {
+                let tags_query = read_query(&self.language_id.to_ascii_lowercase(), "tags.scm");
+                if tags_query.is_empty() {
+                    return None;
+                }
+                let language = get_language(&crate::RUNTIME_DIR, &self.language_id).ok()?;
+                TagsConfiguration::new(language, &tags_query).ok()
+            })
+            .as_ref()
+    }
+
+    /// The compiled `context.scm` query of the language, if it has one.
+    pub fn context_query(&self) -> Option<&ContextQuery> {
+        self.context_query
+            .get_or_init(|| {
+                let context_query =
+                    read_query(&self.language_id.to_ascii_lowercase(), "context.scm");
+                if context_query.is_empty() {
+                    return None;
+                }
+                let language = get_language(&crate::RUNTIME_DIR, &self.language_id).ok()?;
+                ContextQuery::new(language, &context_query).ok()
+            })
+            .as_ref()
+    }
+
+    /// The `name` of the language in `languages.toml`.
+    pub fn language_id(&self) -> &str {
+        &self.language_id
+    }
+
     pub fn scope(&self) -> &str {
         &self.scope
     }
 }
 
+/// Extensions of compressed files which are transparently decompressed when opened.
+const COMPRESSED_EXTENSIONS: &[&str] = &["gz", "zst", "xz"];
+
 #[derive(Debug)]
 pub struct Loader {
     // highlight_names ?
     language_configs: Vec<Arc<LanguageConfiguration>>,
     language_config_ids_by_file_type: HashMap<String, usize>, // Vec<usize>
+    language_config_ids_by_shebang: HashMap<String, usize>,
+    file_globs: GlobSet,
+    /// The language config id of each glob in `file_globs`.
+    language_config_ids_by_glob: Vec<usize>,
 }
 
 impl Loader {
@@ -195,7 +320,11 @@ impl Loader {
         let mut loader = Self {
             language_configs: Vec::new(),
             language_config_ids_by_file_type: HashMap::new(),
+            language_config_ids_by_shebang: HashMap::new(),
+            file_globs: GlobSet::empty(),
+            language_config_ids_by_glob: Vec::new(),
         };
+        let mut file_globs = GlobSetBuilder::new();
 
         for config in config.language {
             // get the next id
@@ -207,20 +336,53 @@ impl Loader {
                     .language_config_ids_by_file_type
                     .insert(file_type.clone(), language_id);
             }
+            for shebang in &config.shebangs {
+                loader
+                    .language_config_ids_by_shebang
+                    .insert(shebang.clone(), language_id);
+            }
+            for glob in &config.file_globs {
+                // the globs were validated when the configuration was deserialized
+                if let Ok(glob) = file_glob(glob) {
+                    file_globs.add(glob);
+                    loader.language_config_ids_by_glob.push(language_id);
+                }
+            }
 
             loader.language_configs.push(Arc::new(config));
         }
 
+        if let Ok(file_globs) = file_globs.build() {
+            loader.file_globs = file_globs;
+        } else {
+            loader.language_config_ids_by_glob.clear();
+        }
+
         loader
     }
 
     pub fn language_config_for_file_name(&self, path: &Path) -> Option<Arc<LanguageConfiguration>> {
-        // Find all the language configurations that match this file name
-        // or a suffix of the file name.
+        // Compressed files are edited decompressed, so use the name of the file inside them
+        // (`foo.json.gz` is JSON).
+        let path = match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if COMPRESSED_EXTENSIONS.contains(&extension) => {
+                path.file_stem().map_or(path, Path::new)
+            }
+            _ => path,
+        };
+
+        // Find the language configuration that matches this file name exactly, then one with a
+        // glob matching the path and finally one matching the extension of the file name.
         let configuration_id = path
             .file_name()
             .and_then(|n| n.to_str())
             .and_then(|file_name| self.language_config_ids_by_file_type.get(file_name))
+            .or_else(|| {
+                self.file_globs
+                    .matches(path)
+                    .first()
+                    .map(|&i| &self.language_config_ids_by_glob[i])
+            })
             .or_else(|| {
                 path.extension()
                     .and_then(|extension| extension.to_str())
@@ -228,8 +390,43 @@ impl Loader {
             });
 
         configuration_id.and_then(|&id| self.language_configs.get(id).cloned())
+    }
+
+    /// Finds the language of a document from its contents: the interpreter of a shebang line
+    /// or else the `first-line-regex` matching the first line.
+    pub fn language_config_for_content(
+        &self,
+        text: RopeSlice,
+    ) -> Option<Arc<LanguageConfiguration>> {
+        // don't read all of a long line, like in minified files
+        const MAX_FIRST_LINE_LEN: usize = 256;
+        let first_line: String = text
+            .lines()
+            .next()?
+            .chars()
+            .take(MAX_FIRST_LINE_LEN)
+            .collect();
+        let first_line = first_line.trim_end();
+
+        shebang_interpreter(first_line)
+            .and_then(|interpreter| self.language_config_ids_by_shebang.get(interpreter))
+            .and_then(|&id| self.language_configs.get(id).cloned())
+            .or_else(|| {
+                self.language_configs
+                    .iter()
+                    .find(|config| {
+                        matches!(&config.first_line_regex, Some(regex) if regex.is_match(first_line))
+                    })
+                    .cloned()
+            })
+    }
 
-        // TODO: content_regex handling conflict resolution
+    /// Finds a language by its `name` in `languages.toml`.
+    pub fn language_config_for_name(&self, name: &str) -> Option<Arc<LanguageConfiguration>> {
+        self.language_configs
+            .iter()
+            .find(|config| config.language_id == name)
+            .cloned()
     }
 
     pub fn language_config_for_scope(&self, scope: &str) -> Option<Arc<LanguageConfiguration>> {
@@ -239,9 +436,33 @@ impl Loader {
             .cloned()
     }
 
+    /// The language injected as `name`, like `rust` in a markdown code fence: the one with the
+    /// scope `source.<name>`, or else the one named `name`.
+    pub fn language_config_for_injection(&self, name: &str) -> Option<Arc<LanguageConfiguration>> {
+        self.language_config_for_scope(&format!("source.{}", name))
+            .or_else(|| {
+                self.language_configs
+                    .iter()
+                    .find(|config| config.language_id == name)
+                    .cloned()
+            })
+    }
+
     pub fn language_configs_iter(&self) -> impl Iterator<Item = &Arc<LanguageConfiguration>> {
         self.language_configs.iter()
     }
+
+    /// Returns a callback resolving the languages injected into a document by name, for
+    /// [`Syntax::new_with_injections`].
+    pub fn injection_callback(self: &Arc<Self>, scopes: &[String]) -> InjectionCallback {
+        let loader = self.clone();
+        let scopes = scopes.to_vec();
+        Arc::new(move |language| {
+            let language_config = loader.language_config_for_injection(language)?;
+            let config = language_config.highlight_config(&scopes)?;
+            Some((language_config, config))
+        })
+    }
 }
 
 pub struct TsParser {
@@ -263,20 +484,50 @@ thread_local! {
     })
 }
 
-#[derive(Debug)]
-pub struct Syntax {
-    config: Arc<HighlightConfiguration>,
+/// Resolves the name of a language injected into a document, like `rust` in a markdown code
+/// fence, to its configuration.
+pub type InjectionCallback = Arc<
+    dyn Fn(&str) -> Option<(Arc<LanguageConfiguration>, Arc<HighlightConfiguration>)> + Send + Sync,
+>;
+
+/// Injected languages can inject languages of their own, up to this depth.
+const MAX_INJECTION_DEPTH: usize = 8;
+
+/// A layer of a [`Syntax`] by its index in the injection layers, `None` for the root layer.
+type LayerId = Option<usize>;
+
+/// The byte ranges of an injection layer, which tell it apart from the other layers.
+fn range_key(ranges: &[Range]) -> Vec<(usize, usize)> {
+    ranges
+        .iter()
+        .map(|range| (range.start_byte, range.end_byte))
+        .collect()
+}
 
+pub struct Syntax {
     root_layer: LanguageLayer,
+    /// The layers of the languages injected into the document, ordered by depth. They are
+    /// edited and reparsed along with the root layer.
+    injection_layers: Vec<LanguageLayer>,
+    injection_callback: Option<InjectionCallback>,
+}
+
+impl fmt::Debug for Syntax {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Syntax")
+            .field("root_layer", &self.root_layer)
+            .field("injection_layers", &self.injection_layers)
+            .finish()
+    }
 }
 
-fn byte_range_to_str(range: std::ops::Range<usize>, source: RopeSlice) -> Cow<str> {
+pub(crate) fn byte_range_to_str(range: std::ops::Range<usize>, source: RopeSlice) -> Cow<str> {
     let start_char = source.byte_to_char(range.start);
     let end_char = source.byte_to_char(range.end);
     Cow::from(source.slice(start_char..end_char))
 }
 
-fn node_to_bytes<'a>(node: Node, source: RopeSlice<'a>) -> Cow<'a, [u8]> {
+pub(crate) fn node_to_bytes<'a>(node: Node, source: RopeSlice<'a>) -> Cow<'a, [u8]> {
     let start_char = source.byte_to_char(node.start_byte());
     let end_char = source.byte_to_char(node.end_byte());
     let fragment = source.slice(start_char..end_char);
@@ -292,53 +543,186 @@ impl Syntax {
         /*language: Lang,*/ source: &Rope,
         config: Arc<HighlightConfiguration>,
     ) -> Self {
-        let root_layer = LanguageLayer { tree: None };
+        let root_layer = LanguageLayer::new(None, config, 0, vec![document_range()]);
 
         // track markers of injections
         // track scope_descriptor: a Vec of scopes for item in tree
 
         let mut syntax = Self {
             // grammar,
-            config,
             root_layer,
+            injection_layers: Vec::new(),
+            injection_callback: None,
         };
 
         // update root layer
         PARSER.with(|ts_parser| {
             // TODO: handle the returned `Result` properly.
-            let _ = syntax.root_layer.parse(
-                &mut ts_parser.borrow_mut(),
-                &syntax.config,
-                source,
-                0,
-                vec![Range {
-                    start_byte: 0,
-                    end_byte: usize::MAX,
-                    start_point: Point::new(0, 0),
-                    end_point: Point::new(usize::MAX, usize::MAX),
-                }],
-            );
+            let _ = syntax.root_layer.parse(&mut ts_parser.borrow_mut(), source);
         });
         syntax
     }
 
+    /// Like [`Syntax::new`], but the languages injected into the document are parsed into
+    /// layers of their own. `injection_callback` resolves them by name.
+    pub fn new_with_injections(
+        source: &Rope,
+        config: Arc<HighlightConfiguration>,
+        injection_callback: InjectionCallback,
+    ) -> Self {
+        let mut syntax = Self::new(source, config);
+        syntax.injection_callback = Some(injection_callback);
+        PARSER
+            .with(|ts_parser| syntax.update_injections(&mut ts_parser.borrow_mut(), source, None));
+        syntax
+    }
+
     pub fn update(
         &mut self,
         old_source: &Rope,
         source: &Rope,
         changeset: &ChangeSet,
     ) -> Result<(), Error> {
+        if changeset.is_empty() {
+            return Ok(());
+        }
+
+        let edits = LanguageLayer::generate_edits(old_source.slice(..), changeset);
+        self.root_layer.edit(&edits);
+        for layer in &mut self.injection_layers {
+            layer.edit(&edits);
+        }
+
+        // the edited byte ranges of the new text, the edits are in terms of the old text
+        let mut shift = 0isize;
+        let edited = edits.iter().map(|edit| {
+            let start = (edit.start_byte as isize + shift) as usize;
+            shift += edit.new_end_byte as isize - edit.old_end_byte as isize;
+            start..start + (edit.new_end_byte - edit.start_byte)
+        });
+
         PARSER.with(|ts_parser| {
-            self.root_layer.update(
-                &mut ts_parser.borrow_mut(),
-                &self.config,
-                old_source,
-                source,
-                changeset,
-            )
+            let ts_parser = &mut ts_parser.borrow_mut();
+            let mut changed = self.root_layer.parse(ts_parser, source)?;
+            changed.extend(edited);
+            self.update_injections(ts_parser, source, Some(changed));
+            Ok(())
         })
+    }
+
+    /// Finds the injections in every layer and parses them into injection layers. Without
+    /// `changed` byte ranges, all of them are found. Otherwise the layers that were already there
+    /// are only searched where they changed, the injections elsewhere are kept as they are. The
+    /// layers of injections that are found again are reused and reparsed incrementally.
+    fn update_injections(
+        &mut self,
+        ts_parser: &mut TsParser,
+        source: &Rope,
+        mut changed: Option<Vec<ops::Range<usize>>>,
+    ) {
+        let injection_callback = match &self.injection_callback {
+            Some(injection_callback) => injection_callback.clone(),
+            None => return,
+        };
+
+        let mut old_layers: Vec<_> = mem::take(&mut self.injection_layers)
+            .into_iter()
+            .map(Some)
+            .collect();
+        let mut old_children: HashMap<LayerId, Vec<usize>> = HashMap::new();
+        let mut old_by_ranges = HashMap::new();
+        for (i, layer) in old_layers.iter().flatten().enumerate() {
+            old_children.entry(layer.parent).or_default().push(i);
+            old_by_ranges.insert(range_key(&layer.ranges), i);
+        }
+
+        // each layer in the new layers, with the same layer in the old ones if it was there
+        let mut queue = VecDeque::new();
+        queue.push_back((None, Some(None)));
+        while let Some((parent_id, old_id)) = queue.pop_front() {
+            let parent = match parent_id {
+                Some(i) => &self.injection_layers[i],
+                None => &self.root_layer,
+            };
+            if parent.depth >= MAX_INJECTION_DEPTH {
+                continue;
+            }
+            let changed_ranges = changed.as_deref().filter(|_| old_id.is_some());
+            let (injections, found_combined) =
+                parent.injections(ts_parser, source, &injection_callback, changed_ranges);
+
+            for injection in injections {
+                let reused = old_by_ranges
+                    .get(&range_key(&injection.ranges))
+                    .copied()
+                    .filter(|&i| {
+                        matches!(&old_layers[i], Some(layer) if Arc::ptr_eq(&layer.config, &injection.config))
+                    });
+                let (mut layer, old_id) = match reused {
+                    Some(i) => (old_layers[i].take().unwrap(), Some(Some(i))),
+                    None => (
+                        LanguageLayer::new(
+                            Some(injection.language_config),
+                            injection.config,
+                            injection.depth,
+                            injection.ranges,
+                        ),
+                        None,
+                    ),
+                };
+                layer.parent = parent_id;
+                layer.combined = injection.combined;
+                if let Some(id) = self.push_layer(ts_parser, source, layer, &mut changed) {
+                    queue.push_back((Some(id), old_id));
+                }
+            }
+
+            // the injections that weren't searched for again are kept as they are
+            let (old_id, changed_ranges) = match (old_id, changed.as_deref()) {
+                (Some(old_id), Some(changed)) => (old_id, changed),
+                _ => continue,
+            };
+            let kept: Vec<_> = old_children
+                .get(&old_id)
+                .into_iter()
+                .flatten()
+                .copied()
+                .filter(|&i| match &old_layers[i] {
+                    Some(layer) => {
+                        !(layer.intersects(changed_ranges) || layer.combined && found_combined)
+                    }
+                    None => false,
+                })
+                .collect();
+            for i in kept {
+                let mut layer = old_layers[i].take().unwrap();
+                layer.parent = parent_id;
+                if let Some(id) = self.push_layer(ts_parser, source, layer, &mut changed) {
+                    queue.push_back((Some(id), Some(Some(i))));
+                }
+            }
+        }
+    }
 
-        // TODO: deal with injections and update them too
+    /// Adds an injection layer, parsing it if it was edited. The byte ranges of its tree that
+    /// changed are added to `changed`. Returns the index of the layer, `None` if it couldn't be
+    /// parsed.
+    fn push_layer(
+        &mut self,
+        ts_parser: &mut TsParser,
+        source: &Rope,
+        mut layer: LanguageLayer,
+        changed: &mut Option<Vec<ops::Range<usize>>>,
+    ) -> Option<usize> {
+        if layer.dirty {
+            let ranges = layer.parse(ts_parser, source).ok()?;
+            if let Some(changed) = changed {
+                changed.extend(ranges);
+            }
+        }
+        layer.tree.as_ref()?;
+        self.injection_layers.push(layer);
+        Some(self.injection_layers.len() - 1)
     }
 
     // fn buffer_changed -> call layer.update(range, new_text) on root layer and then all marker layers
@@ -350,6 +734,31 @@ impl Syntax {
     pub fn tree(&self) -> &Tree {
         self.root_layer.tree()
     }
+
+    /// Returns the layer of the language of the document.
+    pub fn root_layer(&self) -> &LanguageLayer {
+        &self.root_layer
+    }
+
+    /// Returns the layers of the document: the root layer first, then the injection layers
+    /// ordered by depth.
+    pub fn layers(&self) -> impl Iterator<Item = &LanguageLayer> {
+        iter::once(&self.root_layer).chain(&self.injection_layers)
+    }
+
+    /// Returns the layers containing the byte range `start..end`, innermost first. The root
+    /// layer contains the whole document and always comes last.
+    pub fn layers_for_byte_range(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> impl Iterator<Item = &LanguageLayer> {
+        self.layers()
+            .filter(|layer| layer.contains_byte_range(start, end))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+    }
     //
     // <!--update_for_injection(grammar)-->
 
@@ -361,68 +770,66 @@ impl Syntax {
         source: RopeSlice<'a>,
         range: Option<std::ops::Range<usize>>,
         cancellation_flag: Option<&'a AtomicUsize>,
-        injection_callback: impl FnMut(&str) -> Option<&'a HighlightConfiguration> + 'a,
     ) -> impl Iterator<Item = Result<HighlightEvent, Error>> + 'a {
-        // The `captures` iterator borrows the `Tree` and the `QueryCursor`, which
-        // prevents them from being moved. But both of these values are really just
-        // pointers, so it's actually ok to move them.
-
-        // reuse a cursor from the pool if possible
-        let mut cursor = PARSER.with(|ts_parser| {
-            let highlighter = &mut ts_parser.borrow_mut();
-            highlighter.cursors.pop().unwrap_or_else(QueryCursor::new)
-        });
-        let tree_ref = unsafe { mem::transmute::<_, &'static Tree>(self.tree()) };
-        let cursor_ref = unsafe { mem::transmute::<_, &'static mut QueryCursor>(&mut cursor) };
-        let query_ref = unsafe { mem::transmute::<_, &'static Query>(&self.config.query) };
-        let config_ref =
-            unsafe { mem::transmute::<_, &'static HighlightConfiguration>(self.config.as_ref()) };
-
-        // TODO: if reusing cursors this might need resetting
-        if let Some(range) = &range {
-            cursor_ref.set_byte_range(range.start, range.end);
-        }
+        let byte_range = range.unwrap_or(0..usize::MAX);
+
+        let layers = self
+            .layers()
+            .filter(|layer| layer.overlaps_byte_range(&byte_range))
+            .filter_map(|layer| {
+                // reuse a cursor from the pool if possible
+                let mut cursor = PARSER.with(|ts_parser| {
+                    let highlighter = &mut ts_parser.borrow_mut();
+                    highlighter.cursors.pop().unwrap_or_else(QueryCursor::new)
+                });
 
-        let captures = cursor_ref
-            .captures(query_ref, tree_ref.root_node(), move |n: Node| {
-                // &source[n.byte_range()]
-                node_to_bytes(n, source)
+                // The `captures` iterator borrows the `Tree` and the `QueryCursor`, which
+                // prevents them from being moved. But both of these values are really just
+                // pointers, so it's actually ok to move them.
+                let tree_ref = unsafe { mem::transmute::<_, &'static Tree>(layer.tree()) };
+                let cursor_ref =
+                    unsafe { mem::transmute::<_, &'static mut QueryCursor>(&mut cursor) };
+                let config_ref = unsafe {
+                    mem::transmute::<_, &'static HighlightConfiguration>(layer.config.as_ref())
+                };
+
+                cursor_ref.set_byte_range(byte_range.start, byte_range.end);
+                let captures = cursor_ref
+                    .captures(&config_ref.query, tree_ref.root_node(), move |n: Node| {
+                        // &source[n.byte_range()]
+                        node_to_bytes(n, source)
+                    })
+                    .peekable();
+
+                let mut layer = HighlightIterLayer {
+                    highlight_end_stack: Vec::new(),
+                    scope_stack: vec![LocalScope {
+                        inherits: false,
+                        range: 0..usize::MAX,
+                        local_defs: Vec::new(),
+                    }],
+                    cursor,
+                    depth: layer.depth,
+                    captures,
+                    config: config_ref,
+                };
+                Some((layer.sort_key()?, layer))
             })
-            .peekable();
-
-        // manually craft the root layer based on the existing tree
-        let layer = HighlightIterLayer {
-            highlight_end_stack: Vec::new(),
-            scope_stack: vec![LocalScope {
-                inherits: false,
-                range: 0..usize::MAX,
-                local_defs: Vec::new(),
-            }],
-            cursor,
-            depth: 0,
-            _tree: None,
-            captures,
-            config: config_ref,
-            ranges: vec![Range {
-                start_byte: 0,
-                end_byte: usize::MAX,
-                start_point: Point::new(0, 0),
-                end_point: Point::new(usize::MAX, usize::MAX),
-            }],
-        };
+            .collect::<Vec<_>>();
 
-        let mut result = HighlightIter {
+        // the iterator expects its layers ordered by their next highlight boundary
+        let mut layers = layers;
+        layers.sort_by_key(|(sort_key, _)| *sort_key);
+
+        HighlightIter {
             source,
-            byte_offset: range.map_or(0, |r| r.start), // TODO: simplify
-            injection_callback,
+            byte_offset: byte_range.start,
             cancellation_flag,
             iter_count: 0,
-            layers: vec![layer],
+            layers: layers.into_iter().map(|(_, layer)| layer).collect(),
             next_event: None,
             last_highlight_range: None,
-        };
-        result.sort_layers();
-        result
+        }
     }
     // on_tokenize
     // on_change_highlighting
@@ -445,36 +852,122 @@ impl Syntax {
     // buffer_range_for_scope_at_pos
 }
 
+/// The range of a layer that covers the whole document.
+fn document_range() -> Range {
+    Range {
+        start_byte: 0,
+        end_byte: usize::MAX,
+        start_point: Point::new(0, 0),
+        end_point: Point::new(usize::MAX, usize::MAX),
+    }
+}
+
+/// A language injected into a layer, found by [`LanguageLayer::injections`].
+struct Injection {
+    language_config: Arc<LanguageConfiguration>,
+    config: Arc<HighlightConfiguration>,
+    depth: usize,
+    ranges: Vec<Range>,
+    /// Whether the injection is a combined injection, which all its ranges are parsed into.
+    combined: bool,
+}
+
 #[derive(Debug)]
 pub struct LanguageLayer {
     // mode
     // grammar
-    // depth
+    /// The configuration of an injected language, `None` for the root layer which has the
+    /// language of the document.
+    language_config: Option<Arc<LanguageConfiguration>>,
+    config: Arc<HighlightConfiguration>,
+    depth: usize,
+    ranges: Vec<Range>,
     pub(crate) tree: Option<Tree>,
+    /// Whether the layer was edited since it was last parsed.
+    dirty: bool,
+    /// The layer the language of this one is injected into.
+    parent: LayerId,
+    /// Whether the layer is of a combined injection.
+    combined: bool,
 }
 
 impl LanguageLayer {
-    // pub fn new() -> Self {
-    //     Self { tree: None }
-    // }
+    fn new(
+        language_config: Option<Arc<LanguageConfiguration>>,
+        config: Arc<HighlightConfiguration>,
+        depth: usize,
+        ranges: Vec<Range>,
+    ) -> Self {
+        Self {
+            language_config,
+            config,
+            depth,
+            ranges,
+            tree: None,
+            dirty: true,
+            parent: None,
+            combined: false,
+        }
+    }
 
     pub fn tree(&self) -> &Tree {
         // TODO: no unwrap
         self.tree.as_ref().unwrap()
     }
 
+    /// The highlight configuration of the language of the layer.
+    pub fn config(&self) -> &Arc<HighlightConfiguration> {
+        &self.config
+    }
+
+    /// The configuration of the injected language of the layer, `None` for the root layer.
+    pub fn language_config(&self) -> Option<&Arc<LanguageConfiguration>> {
+        self.language_config.as_ref()
+    }
+
+    /// How deeply the language of the layer is injected, the root layer has a depth of 0.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The byte ranges of the document that are parsed as part of the layer.
+    pub fn ranges(&self) -> &[Range] {
+        &self.ranges
+    }
+
+    pub fn contains_byte_range(&self, start: usize, end: usize) -> bool {
+        self.ranges
+            .iter()
+            .any(|range| range.start_byte <= start && end <= range.end_byte)
+    }
+
+    fn overlaps_byte_range(&self, byte_range: &std::ops::Range<usize>) -> bool {
+        self.ranges
+            .iter()
+            .any(|range| range.start_byte < byte_range.end && byte_range.start < range.end_byte)
+    }
+
+    /// Whether any of the byte ranges `changed` touches the layer, empty ones included.
+    fn intersects(&self, changed: &[ops::Range<usize>]) -> bool {
+        changed.iter().any(|changed| {
+            self.ranges
+                .iter()
+                .any(|range| range.start_byte <= changed.end && changed.start <= range.end_byte)
+        })
+    }
+
+    /// Parses the layer, incrementally if it was parsed before. Returns the byte ranges where
+    /// the syntax tree changed.
     fn parse(
         &mut self,
         ts_parser: &mut TsParser,
-        config: &HighlightConfiguration,
         source: &Rope,
-        _depth: usize,
-        ranges: Vec<Range>,
-    ) -> Result<(), Error> {
-        if ts_parser.parser.set_included_ranges(&ranges).is_ok() {
+    ) -> Result<Vec<ops::Range<usize>>, Error> {
+        let mut changed = Vec::new();
+        if ts_parser.parser.set_included_ranges(&self.ranges).is_ok() {
             ts_parser
                 .parser
-                .set_language(config.language)
+                .set_language(self.config.language)
                 .map_err(|_| Error::InvalidLanguage)?;
 
             // unsafe { syntax.parser.set_cancellation_flag(cancellation_flag) };
@@ -494,44 +987,127 @@ impl LanguageLayer {
                 )
                 .ok_or(Error::Cancelled)?;
             // unsafe { syntax.parser.set_cancellation_flag(None) };
-            // let mut cursor = syntax.cursors.pop().unwrap_or_else(QueryCursor::new);
-
-            // Process combined injections. (ERB, EJS, etc https://github.com/tree-sitter/tree-sitter/pull/526)
-            // if let Some(combined_injections_query) = &config.combined_injections_query {
-            //     let mut injections_by_pattern_index =
-            //         vec![(None, Vec::new(), false); combined_injections_query.pattern_count()];
-            //     let matches =
-            //         cursor.matches(combined_injections_query, tree.root_node(), |n: Node| {
-            //             // &source[n.byte_range()]
-            //             node_to_bytes(n, source)
-            //         });
-            //     for mat in matches {
-            //         let entry = &mut injections_by_pattern_index[mat.pattern_index];
-            //         let (language_name, content_node, include_children) =
-            //             injection_for_match(config, combined_injections_query, &mat, source);
-            //         if language_name.is_some() {
-            //             entry.0 = language_name;
-            //         }
-            //         if let Some(content_node) = content_node {
-            //             entry.1.push(content_node);
-            //         }
-            //         entry.2 = include_children;
-            //     }
-            //     for (lang_name, content_nodes, includes_children) in injections_by_pattern_index {
-            //         if let (Some(lang_name), false) = (lang_name, content_nodes.is_empty()) {
-            //             if let Some(next_config) = (injection_callback)(lang_name) {
-            //                 let ranges =
-            //                     Self::intersect_ranges(&ranges, &content_nodes, includes_children);
-            //                 if !ranges.is_empty() {
-            //                     queue.push((next_config, depth + 1, ranges));
-            //                 }
-            //             }
-            //         }
-            //     }
-            // }
-            self.tree = Some(tree)
+
+            if let Some(old_tree) = &self.tree {
+                changed.extend(
+                    old_tree
+                        .changed_ranges(&tree)
+                        .map(|range| range.start_byte..range.end_byte),
+                );
+            }
+            self.tree = Some(tree);
+            self.dirty = false;
         }
-        Ok(())
+        Ok(changed)
+    }
+
+    /// Finds the languages injected into the layer. With `changed` byte ranges, only the
+    /// injections in those are found, and the combined injections if any of them touches the
+    /// layer, since all their ranges are needed. Also returns whether combined injections were
+    /// looked for.
+    fn injections(
+        &self,
+        ts_parser: &mut TsParser,
+        source: &Rope,
+        injection_callback: &InjectionCallback,
+        changed: Option<&[ops::Range<usize>]>,
+    ) -> (Vec<Injection>, bool) {
+        let tree = match &self.tree {
+            Some(tree) => tree,
+            None => return (Vec::new(), false),
+        };
+        let byte_ranges = match changed {
+            Some(changed) => {
+                let mut byte_ranges: Vec<_> = changed
+                    .iter()
+                    .filter(|range| self.intersects(slice::from_ref(range)))
+                    .cloned()
+                    .collect();
+                byte_ranges.sort_unstable_by_key(|range| range.start);
+                byte_ranges
+            }
+            None => {
+                let whole_layer = 0..usize::MAX;
+                vec![whole_layer]
+            }
+        };
+        if byte_ranges.is_empty() {
+            return (Vec::new(), false);
+        }
+
+        let config = self.config.as_ref();
+        let source = source.slice(..);
+        let mut cursor = ts_parser.cursors.pop().unwrap_or_else(QueryCursor::new);
+
+        let mut injections = Vec::new();
+        // a match can span more than one of the byte ranges
+        let mut found = HashSet::new();
+        let mut push_injection =
+            |language_name: &str, nodes: &[Node], includes_children, combined| {
+                if let Some((language_config, config)) = injection_callback(language_name) {
+                    let ranges = Self::intersect_ranges(&self.ranges, nodes, includes_children);
+                    if !ranges.is_empty() && found.insert(range_key(&ranges)) {
+                        injections.push(Injection {
+                            language_config,
+                            config,
+                            depth: self.depth + 1,
+                            ranges,
+                            combined,
+                        });
+                    }
+                }
+            };
+
+        if let Some(injections_query) = &config.injections_query {
+            for byte_range in &byte_ranges {
+                // an empty range is where text was deleted
+                cursor.set_byte_range(byte_range.start, byte_range.end.max(byte_range.start + 1));
+                let matches = cursor.matches(injections_query, tree.root_node(), |n: Node| {
+                    // &source[n.byte_range()]
+                    node_to_bytes(n, source)
+                });
+                for mat in matches {
+                    if let (Some(language_name), Some(content_node), include_children) =
+                        injection_for_match(config, injections_query, &mat, source)
+                    {
+                        push_injection(&language_name, &[content_node], include_children, false);
+                    }
+                }
+            }
+        }
+
+        // Process combined injections. (ERB, EJS, etc https://github.com/tree-sitter/tree-sitter/pull/526)
+        // All the disjoint ranges matched by a pattern are parsed together as one syntax tree.
+        let found_combined = config.combined_injections_query.is_some();
+        if let Some(combined_injections_query) = &config.combined_injections_query {
+            let mut injections_by_pattern_index =
+                vec![(None, Vec::new(), false); combined_injections_query.pattern_count()];
+            cursor.set_byte_range(0, usize::MAX);
+            let matches = cursor.matches(combined_injections_query, tree.root_node(), |n: Node| {
+                // &source[n.byte_range()]
+                node_to_bytes(n, source)
+            });
+            for mat in matches {
+                let entry = &mut injections_by_pattern_index[mat.pattern_index];
+                let (language_name, content_node, include_children) =
+                    injection_for_match(config, combined_injections_query, &mat, source);
+                if language_name.is_some() {
+                    entry.0 = language_name;
+                }
+                if let Some(content_node) = content_node {
+                    entry.1.push(content_node);
+                }
+                entry.2 = include_children;
+            }
+            for (lang_name, content_nodes, includes_children) in injections_by_pattern_index {
+                if let (Some(lang_name), false) = (lang_name, content_nodes.is_empty()) {
+                    push_injection(&lang_name, &content_nodes, includes_children, true);
+                }
+            }
+        }
+
+        ts_parser.cursors.push(cursor);
+        (injections, found_combined)
     }
 
     pub(crate) fn generate_edits(
@@ -641,46 +1217,156 @@ impl LanguageLayer {
         edits
     }
 
-    fn update(
-        &mut self,
-        ts_parser: &mut TsParser,
-        config: &HighlightConfiguration,
-        old_source: &Rope,
-        source: &Rope,
-        changeset: &ChangeSet,
-    ) -> Result<(), Error> {
-        if changeset.is_empty() {
-            return Ok(());
+    /// Applies `edits` to the tree and the ranges of the layer. The layer needs to be reparsed
+    /// if any of them touches its ranges.
+    fn edit(&mut self, edits: &[tree_sitter::InputEdit]) {
+        // the point `point` moves to when the text up to `old_end` is replaced with the text
+        // up to `new_end`, like tree-sitter moves the included ranges of a tree
+        fn shift(point: Point, old_end: Point, new_end: Point) -> Point {
+            if point.row > old_end.row {
+                Point::new(new_end.row + point.row - old_end.row, point.column)
+            } else {
+                Point::new(new_end.row, new_end.column + point.column - old_end.column)
+            }
         }
 
-        let edits = Self::generate_edits(old_source.slice(..), changeset);
-
-        // Notify the tree about all the changes
+        // apply the edits in reverse. If we applied them in order then edit 1 would disrupt
+        // the positioning of edit 2
         for edit in edits.iter().rev() {
-            // apply the edits in reverse. If we applied them in order then edit 1 would disrupt
-            // the positioning of edit 2
-            self.tree.as_mut().unwrap().edit(edit);
+            if let Some(tree) = &mut self.tree {
+                tree.edit(edit);
+            }
+
+            for range in &mut self.ranges {
+                if edit.start_byte > range.end_byte {
+                    continue;
+                }
+                if edit.old_end_byte < range.start_byte {
+                    // the edit is in front of the range, which only moves
+                    range.start_byte = range.start_byte - edit.old_end_byte + edit.new_end_byte;
+                    range.start_point = shift(
+                        range.start_point,
+                        edit.old_end_position,
+                        edit.new_end_position,
+                    );
+                } else {
+                    // the range grows to cover the edited text
+                    if edit.start_byte < range.start_byte {
+                        range.start_byte = edit.start_byte;
+                        range.start_point = edit.start_position;
+                    }
+                    self.dirty = true;
+                }
+                if range.end_byte == usize::MAX {
+                    // the range runs to the end of the document
+                } else if range.end_byte >= edit.old_end_byte {
+                    range.end_byte = range.end_byte - edit.old_end_byte + edit.new_end_byte;
+                    range.end_point = shift(
+                        range.end_point,
+                        edit.old_end_position,
+                        edit.new_end_position,
+                    );
+                } else {
+                    range.end_byte = edit.new_end_byte;
+                    range.end_point = edit.new_end_position;
+                }
+            }
         }
+    }
 
-        self.parse(
-            ts_parser,
-            config,
-            source,
-            0,
-            // TODO: what to do about this range on update
-            vec![Range {
+    // Compute the ranges that should be included when parsing an injection.
+    // This takes into account three things:
+    // * `parent_ranges` - The ranges must all fall within the *current* layer's ranges.
+    // * `nodes` - Every injection takes place within a set of nodes. The injection ranges
+    //   are the ranges of those nodes.
+    // * `includes_children` - For some injections, the content nodes' children should be
+    //   excluded from the nested document, so that only the content nodes' *own* content
+    //   is reparsed. For other injections, the content nodes' entire ranges should be
+    //   reparsed, including the ranges of their children.
+    fn intersect_ranges(
+        parent_ranges: &[Range],
+        nodes: &[Node],
+        includes_children: bool,
+    ) -> Vec<Range> {
+        let mut cursor = nodes[0].walk();
+        let mut result = Vec::new();
+        let mut parent_range_iter = parent_ranges.iter();
+        let mut parent_range = parent_range_iter
+            .next()
+            .expect("Layers should only be constructed with non-empty ranges vectors");
+        for node in nodes.iter() {
+            let mut preceding_range = Range {
                 start_byte: 0,
-                end_byte: usize::MAX,
                 start_point: Point::new(0, 0),
+                end_byte: node.start_byte(),
+                end_point: node.start_position(),
+            };
+            let following_range = Range {
+                start_byte: node.end_byte(),
+                start_point: node.end_position(),
+                end_byte: usize::MAX,
                 end_point: Point::new(usize::MAX, usize::MAX),
-            }],
-        )
-    }
+            };
+
+            for excluded_range in node
+                .children(&mut cursor)
+                .filter_map(|child| {
+                    if includes_children {
+                        None
+                    } else {
+                        Some(child.range())
+                    }
+                })
+                .chain([following_range].iter().cloned())
+            {
+                let mut range = Range {
+                    start_byte: preceding_range.end_byte,
+                    start_point: preceding_range.end_point,
+                    end_byte: excluded_range.start_byte,
+                    end_point: excluded_range.start_point,
+                };
+                preceding_range = excluded_range;
+
+                if range.end_byte < parent_range.start_byte {
+                    continue;
+                }
+
+                while parent_range.start_byte <= range.end_byte {
+                    if parent_range.end_byte > range.start_byte {
+                        if range.start_byte < parent_range.start_byte {
+                            range.start_byte = parent_range.start_byte;
+                            range.start_point = parent_range.start_point;
+                        }
 
-    // fn highlight_iter() -> same as Mode but for this layer. Mode composits these
-    // fn buffer_changed
-    // fn update(range)
-    // fn update_injections()
+                        if parent_range.end_byte < range.end_byte {
+                            if range.start_byte < parent_range.end_byte {
+                                result.push(Range {
+                                    start_byte: range.start_byte,
+                                    start_point: range.start_point,
+                                    end_byte: parent_range.end_byte,
+                                    end_point: parent_range.end_point,
+                                });
+                            }
+                            range.start_byte = parent_range.end_byte;
+                            range.start_point = parent_range.end_point;
+                        } else {
+                            if range.start_byte < range.end_byte {
+                                result.push(range);
+                            }
+                            break;
+                        }
+                    }
+
+                    if let Some(next_range) = parent_range_iter.next() {
+                        parent_range = next_range;
+                    } else {
+                        return result;
+                    }
+                }
+            }
+        }
+        result
+    }
 }
 
 // -- refactored from tree-sitter-highlight to be able to retain state
@@ -713,7 +1399,7 @@ impl LanguageLayer {
 // parsing in majority of cases.
 
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::{iter, mem, ops, str, usize};
+use std::{iter, mem, ops, slice, str, usize};
 use tree_sitter::{
     Language as Grammar, Node, Parser, Point, Query, QueryCaptures, QueryCursor, QueryError,
     QueryMatch, Range, Tree,
@@ -748,6 +1434,7 @@ pub enum HighlightEvent {
 pub struct HighlightConfiguration {
     pub language: Grammar,
     pub query: Query,
+    injections_query: Option<Query>,
     combined_injections_query: Option<Query>,
     locals_pattern_index: usize,
     highlights_pattern_index: usize,
@@ -776,13 +1463,9 @@ struct LocalScope<'a> {
 }
 
 #[derive(Debug)]
-struct HighlightIter<'a, 'tree: 'a, F>
-where
-    F: FnMut(&str) -> Option<&'a HighlightConfiguration> + 'a,
-{
+struct HighlightIter<'a, 'tree: 'a> {
     source: RopeSlice<'a>,
     byte_offset: usize,
-    injection_callback: F,
     cancellation_flag: Option<&'a AtomicUsize>,
     layers: Vec<HighlightIterLayer<'a, 'tree>>,
     iter_count: usize,
@@ -791,13 +1474,11 @@ where
 }
 
 struct HighlightIterLayer<'a, 'tree: 'a> {
-    _tree: Option<Tree>,
     cursor: QueryCursor,
     captures: iter::Peekable<QueryCaptures<'a, 'tree, Cow<'a, [u8]>>>,
     config: &'a HighlightConfiguration,
     highlight_end_stack: Vec<usize>,
     scope_stack: Vec<LocalScope<'a>>,
-    ranges: Vec<Range>,
     depth: usize,
 }
 
@@ -853,19 +1534,28 @@ impl HighlightConfiguration {
             }
         }
 
-        // Construct a separate query just for dealing with the 'combined injections'.
+        // Construct separate queries for finding the injections and the 'combined injections'.
         // Disable the combined injection patterns in the main query.
+        let mut injections_query = Query::new(language, injection_query)?;
         let mut combined_injections_query = Query::new(language, injection_query)?;
+        let mut has_injection_queries = false;
         let mut has_combined_queries = false;
         for pattern_index in 0..locals_pattern_index {
             let settings = query.property_settings(pattern_index);
             if settings.iter().any(|s| &*s.key == "injection.combined") {
                 has_combined_queries = true;
                 query.disable_pattern(pattern_index);
+                injections_query.disable_pattern(pattern_index);
             } else {
+                has_injection_queries = true;
                 combined_injections_query.disable_pattern(pattern_index);
             }
         }
+        let injections_query = if has_injection_queries {
+            Some(injections_query)
+        } else {
+            None
+        };
         let combined_injections_query = if has_combined_queries {
             Some(combined_injections_query)
         } else {
@@ -886,331 +1576,94 @@ impl HighlightConfiguration {
         // Store the numeric ids for all of the special captures.
         let mut injection_content_capture_index = None;
         let mut injection_language_capture_index = None;
-        let mut local_def_capture_index = None;
-        let mut local_def_value_capture_index = None;
-        let mut local_ref_capture_index = None;
-        let mut local_scope_capture_index = None;
-        for (i, name) in query.capture_names().iter().enumerate() {
-            let i = Some(i as u32);
-            match name.as_str() {
-                "injection.content" => injection_content_capture_index = i,
-                "injection.language" => injection_language_capture_index = i,
-                "local.definition" => local_def_capture_index = i,
-                "local.definition-value" => local_def_value_capture_index = i,
-                "local.reference" => local_ref_capture_index = i,
-                "local.scope" => local_scope_capture_index = i,
-                _ => {}
-            }
-        }
-
-        let highlight_indices = ArcSwap::from_pointee(vec![None; query.capture_names().len()]);
-        Ok(Self {
-            language,
-            query,
-            combined_injections_query,
-            locals_pattern_index,
-            highlights_pattern_index,
-            highlight_indices,
-            non_local_variable_patterns,
-            injection_content_capture_index,
-            injection_language_capture_index,
-            local_scope_capture_index,
-            local_def_capture_index,
-            local_def_value_capture_index,
-            local_ref_capture_index,
-        })
-    }
-
-    /// Get a slice containing all of the highlight names used in the configuration.
-    pub fn names(&self) -> &[String] {
-        self.query.capture_names()
-    }
-
-    /// Set the list of recognized highlight names.
-    ///
-    /// Tree-sitter syntax-highlighting queries specify highlights in the form of dot-separated
-    /// highlight names like `punctuation.bracket` and `function.method.builtin`. Consumers of
-    /// these queries can choose to recognize highlights with different levels of specificity.
-    /// For example, the string `function.builtin` will match against `function.method.builtin`
-    /// and `function.builtin.constructor`, but will not match `function.method`.
-    ///
-    /// When highlighting, results are returned as `Highlight` values, which contain the index
-    /// of the matched highlight this list of highlight names.
-    pub fn configure(&self, recognized_names: &[String]) {
-        let mut capture_parts = Vec::new();
-        let indices: Vec<_> = self
-            .query
-            .capture_names()
-            .iter()
-            .map(move |capture_name| {
-                capture_parts.clear();
-                capture_parts.extend(capture_name.split('.'));
-
-                let mut best_index = None;
-                let mut best_match_len = 0;
-                for (i, recognized_name) in recognized_names.iter().enumerate() {
-                    let recognized_name = recognized_name;
-                    let mut len = 0;
-                    let mut matches = true;
-                    for part in recognized_name.split('.') {
-                        len += 1;
-                        if !capture_parts.contains(&part) {
-                            matches = false;
-                            break;
-                        }
-                    }
-                    if matches && len > best_match_len {
-                        best_index = Some(i);
-                        best_match_len = len;
-                    }
-                }
-                best_index.map(Highlight)
-            })
-            .collect();
-
-        self.highlight_indices.store(Arc::new(indices));
-    }
-}
-
-impl<'a, 'tree: 'a> HighlightIterLayer<'a, 'tree> {
-    /// Create a new 'layer' of highlighting for this document.
-    ///
-    /// In the even that the new layer contains "combined injections" (injections where multiple
-    /// disjoint ranges are parsed as one syntax tree), these will be eagerly processed and
-    /// added to the returned vector.
-    fn new<F: FnMut(&str) -> Option<&'a HighlightConfiguration> + 'a>(
-        source: RopeSlice<'a>,
-        cancellation_flag: Option<&'a AtomicUsize>,
-        injection_callback: &mut F,
-        mut config: &'a HighlightConfiguration,
-        mut depth: usize,
-        mut ranges: Vec<Range>,
-    ) -> Result<Vec<Self>, Error> {
-        let mut result = Vec::with_capacity(1);
-        let mut queue = Vec::new();
-        loop {
-            // --> Tree parsing part
-
-            PARSER.with(|ts_parser| {
-                let highlighter = &mut ts_parser.borrow_mut();
-
-                if highlighter.parser.set_included_ranges(&ranges).is_ok() {
-                    highlighter
-                        .parser
-                        .set_language(config.language)
-                        .map_err(|_| Error::InvalidLanguage)?;
-
-                    unsafe { highlighter.parser.set_cancellation_flag(cancellation_flag) };
-                    let tree = highlighter
-                        .parser
-                        .parse_with(
-                            &mut |byte, _| {
-                                if byte <= source.len_bytes() {
-                                    let (chunk, start_byte, _, _) = source.chunk_at_byte(byte);
-                                    chunk[byte - start_byte..].as_bytes()
-                                } else {
-                                    // out of range
-                                    &[]
-                                }
-                            },
-                            None,
-                        )
-                        .ok_or(Error::Cancelled)?;
-                    unsafe { highlighter.parser.set_cancellation_flag(None) };
-                    let mut cursor = highlighter.cursors.pop().unwrap_or_else(QueryCursor::new);
-
-                    // Process combined injections.
-                    if let Some(combined_injections_query) = &config.combined_injections_query {
-                        let mut injections_by_pattern_index = vec![
-                            (None, Vec::new(), false);
-                            combined_injections_query
-                                .pattern_count()
-                        ];
-                        let matches = cursor.matches(
-                            combined_injections_query,
-                            tree.root_node(),
-                            |n: Node| {
-                                // &source[n.byte_range()]
-                                node_to_bytes(n, source)
-                            },
-                        );
-                        for mat in matches {
-                            let entry = &mut injections_by_pattern_index[mat.pattern_index];
-                            let (language_name, content_node, include_children) =
-                                injection_for_match(
-                                    config,
-                                    combined_injections_query,
-                                    &mat,
-                                    source,
-                                );
-                            if language_name.is_some() {
-                                entry.0 = language_name;
-                            }
-                            if let Some(content_node) = content_node {
-                                entry.1.push(content_node);
-                            }
-                            entry.2 = include_children;
-                        }
-                        for (lang_name, content_nodes, includes_children) in
-                            injections_by_pattern_index
-                        {
-                            if let (Some(lang_name), false) = (lang_name, content_nodes.is_empty())
-                            {
-                                if let Some(next_config) = (injection_callback)(&lang_name) {
-                                    let ranges = Self::intersect_ranges(
-                                        &ranges,
-                                        &content_nodes,
-                                        includes_children,
-                                    );
-                                    if !ranges.is_empty() {
-                                        queue.push((next_config, depth + 1, ranges));
-                                    }
-                                }
-                            }
-                        }
-                    }
-
-                    // --> Highlighting query part
-
-                    // The `captures` iterator borrows the `Tree` and the `QueryCursor`, which
-                    // prevents them from being moved. But both of these values are really just
-                    // pointers, so it's actually ok to move them.
-                    let tree_ref = unsafe { mem::transmute::<_, &'static Tree>(&tree) };
-                    let cursor_ref =
-                        unsafe { mem::transmute::<_, &'static mut QueryCursor>(&mut cursor) };
-                    let captures = cursor_ref
-                        .captures(&config.query, tree_ref.root_node(), move |n: Node| {
-                            // &source[n.byte_range()]
-                            node_to_bytes(n, source)
-                        })
-                        .peekable();
-
-                    result.push(HighlightIterLayer {
-                        highlight_end_stack: Vec::new(),
-                        scope_stack: vec![LocalScope {
-                            inherits: false,
-                            range: 0..usize::MAX,
-                            local_defs: Vec::new(),
-                        }],
-                        cursor,
-                        depth,
-                        _tree: Some(tree),
-                        captures,
-                        config,
-                        ranges,
-                    });
-                }
-
-                Ok(()) // so we can use the try operator
-            })?;
-
-            if queue.is_empty() {
-                break;
-            }
-
-            let (next_config, next_depth, next_ranges) = queue.remove(0);
-            config = next_config;
-            depth = next_depth;
-            ranges = next_ranges;
-        }
-
-        Ok(result)
-    }
-
-    // Compute the ranges that should be included when parsing an injection.
-    // This takes into account three things:
-    // * `parent_ranges` - The ranges must all fall within the *current* layer's ranges.
-    // * `nodes` - Every injection takes place within a set of nodes. The injection ranges
-    //   are the ranges of those nodes.
-    // * `includes_children` - For some injections, the content nodes' children should be
-    //   excluded from the nested document, so that only the content nodes' *own* content
-    //   is reparsed. For other injections, the content nodes' entire ranges should be
-    //   reparsed, including the ranges of their children.
-    fn intersect_ranges(
-        parent_ranges: &[Range],
-        nodes: &[Node],
-        includes_children: bool,
-    ) -> Vec<Range> {
-        let mut cursor = nodes[0].walk();
-        let mut result = Vec::new();
-        let mut parent_range_iter = parent_ranges.iter();
-        let mut parent_range = parent_range_iter
-            .next()
-            .expect("Layers should only be constructed with non-empty ranges vectors");
-        for node in nodes.iter() {
-            let mut preceding_range = Range {
-                start_byte: 0,
-                start_point: Point::new(0, 0),
-                end_byte: node.start_byte(),
-                end_point: node.start_position(),
-            };
-            let following_range = Range {
-                start_byte: node.end_byte(),
-                start_point: node.end_position(),
-                end_byte: usize::MAX,
-                end_point: Point::new(usize::MAX, usize::MAX),
-            };
-
-            for excluded_range in node
-                .children(&mut cursor)
-                .filter_map(|child| {
-                    if includes_children {
-                        None
-                    } else {
-                        Some(child.range())
-                    }
-                })
-                .chain([following_range].iter().cloned())
-            {
-                let mut range = Range {
-                    start_byte: preceding_range.end_byte,
-                    start_point: preceding_range.end_point,
-                    end_byte: excluded_range.start_byte,
-                    end_point: excluded_range.start_point,
-                };
-                preceding_range = excluded_range;
+        let mut local_def_capture_index = None;
+        let mut local_def_value_capture_index = None;
+        let mut local_ref_capture_index = None;
+        let mut local_scope_capture_index = None;
+        for (i, name) in query.capture_names().iter().enumerate() {
+            let i = Some(i as u32);
+            match name.as_str() {
+                "injection.content" => injection_content_capture_index = i,
+                "injection.language" => injection_language_capture_index = i,
+                "local.definition" => local_def_capture_index = i,
+                "local.definition-value" => local_def_value_capture_index = i,
+                "local.reference" => local_ref_capture_index = i,
+                "local.scope" => local_scope_capture_index = i,
+                _ => {}
+            }
+        }
 
-                if range.end_byte < parent_range.start_byte {
-                    continue;
-                }
+        let highlight_indices = ArcSwap::from_pointee(vec![None; query.capture_names().len()]);
+        Ok(Self {
+            language,
+            query,
+            injections_query,
+            combined_injections_query,
+            locals_pattern_index,
+            highlights_pattern_index,
+            highlight_indices,
+            non_local_variable_patterns,
+            injection_content_capture_index,
+            injection_language_capture_index,
+            local_scope_capture_index,
+            local_def_capture_index,
+            local_def_value_capture_index,
+            local_ref_capture_index,
+        })
+    }
 
-                while parent_range.start_byte <= range.end_byte {
-                    if parent_range.end_byte > range.start_byte {
-                        if range.start_byte < parent_range.start_byte {
-                            range.start_byte = parent_range.start_byte;
-                            range.start_point = parent_range.start_point;
-                        }
+    /// Get a slice containing all of the highlight names used in the configuration.
+    pub fn names(&self) -> &[String] {
+        self.query.capture_names()
+    }
 
-                        if parent_range.end_byte < range.end_byte {
-                            if range.start_byte < parent_range.end_byte {
-                                result.push(Range {
-                                    start_byte: range.start_byte,
-                                    start_point: range.start_point,
-                                    end_byte: parent_range.end_byte,
-                                    end_point: parent_range.end_point,
-                                });
-                            }
-                            range.start_byte = parent_range.end_byte;
-                            range.start_point = parent_range.end_point;
-                        } else {
-                            if range.start_byte < range.end_byte {
-                                result.push(range);
-                            }
+    /// Set the list of recognized highlight names.
+    ///
+    /// Tree-sitter syntax-highlighting queries specify highlights in the form of dot-separated
+    /// highlight names like `punctuation.bracket` and `function.method.builtin`. Consumers of
+    /// these queries can choose to recognize highlights with different levels of specificity.
+    /// For example, the string `function.builtin` will match against `function.method.builtin`
+    /// and `function.builtin.constructor`, but will not match `function.method`.
+    ///
+    /// When highlighting, results are returned as `Highlight` values, which contain the index
+    /// of the matched highlight this list of highlight names.
+    pub fn configure(&self, recognized_names: &[String]) {
+        let mut capture_parts = Vec::new();
+        let indices: Vec<_> = self
+            .query
+            .capture_names()
+            .iter()
+            .map(move |capture_name| {
+                capture_parts.clear();
+                capture_parts.extend(capture_name.split('.'));
+
+                let mut best_index = None;
+                let mut best_match_len = 0;
+                for (i, recognized_name) in recognized_names.iter().enumerate() {
+                    let recognized_name = recognized_name;
+                    let mut len = 0;
+                    let mut matches = true;
+                    for part in recognized_name.split('.') {
+                        len += 1;
+                        if !capture_parts.contains(&part) {
+                            matches = false;
                             break;
                         }
                     }
-
-                    if let Some(next_range) = parent_range_iter.next() {
-                        parent_range = next_range;
-                    } else {
-                        return result;
+                    if matches && len > best_match_len {
+                        best_index = Some(i);
+                        best_match_len = len;
                     }
                 }
-            }
-        }
-        result
+                best_index.map(Highlight)
+            })
+            .collect();
+
+        self.highlight_indices.store(Arc::new(indices));
     }
+}
 
+impl<'a, 'tree: 'a> HighlightIterLayer<'a, 'tree> {
     // First, sort scope boundaries by their byte offset in the document. At a
     // given position, emit scope endings before scope beginnings. Finally, emit
     // scope boundaries from deeper layers first.
@@ -1236,10 +1689,7 @@ impl<'a, 'tree: 'a> HighlightIterLayer<'a, 'tree> {
     }
 }
 
-impl<'a, 'tree: 'a, F> HighlightIter<'a, 'tree, F>
-where
-    F: FnMut(&str) -> Option<&'a HighlightConfiguration> + 'a,
-{
+impl<'a, 'tree: 'a> HighlightIter<'a, 'tree> {
     fn emit_event(
         &mut self,
         offset: usize,
@@ -1286,30 +1736,9 @@ where
             }
         }
     }
-
-    fn insert_layer(&mut self, mut layer: HighlightIterLayer<'a, 'tree>) {
-        if let Some(sort_key) = layer.sort_key() {
-            let mut i = 1;
-            while i < self.layers.len() {
-                if let Some(sort_key_i) = self.layers[i].sort_key() {
-                    if sort_key_i > sort_key {
-                        self.layers.insert(i, layer);
-                        return;
-                    }
-                    i += 1;
-                } else {
-                    self.layers.remove(i);
-                }
-            }
-            self.layers.push(layer);
-        }
-    }
 }
 
-impl<'a, 'tree: 'a, F> Iterator for HighlightIter<'a, 'tree, F>
-where
-    F: FnMut(&str) -> Option<&'a HighlightConfiguration> + 'a,
-{
+impl<'a, 'tree: 'a> Iterator for HighlightIter<'a, 'tree> {
     type Item = Result<HighlightEvent, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -1376,44 +1805,11 @@ where
             let (mut match_, capture_index) = layer.captures.next().unwrap();
             let mut capture = match_.captures[capture_index];
 
-            // If this capture represents an injection, then process the injection.
+            // Injections are parsed into layers of their own by `Syntax`, so skip them here.
             if match_.pattern_index < layer.config.locals_pattern_index {
-                let (language_name, content_node, include_children) =
-                    injection_for_match(layer.config, &layer.config.query, &match_, self.source);
-
                 // Explicitly remove this match so that none of its other captures will remain
                 // in the stream of captures.
                 match_.remove();
-
-                // If a language is found with the given name, then add a new language layer
-                // to the highlighted document.
-                if let (Some(language_name), Some(content_node)) = (language_name, content_node) {
-                    if let Some(config) = (self.injection_callback)(&language_name) {
-                        let ranges = HighlightIterLayer::intersect_ranges(
-                            &self.layers[0].ranges,
-                            &[content_node],
-                            include_children,
-                        );
-                        if !ranges.is_empty() {
-                            match HighlightIterLayer::new(
-                                self.source,
-                                self.cancellation_flag,
-                                &mut self.injection_callback,
-                                config,
-                                self.layers[0].depth + 1,
-                                ranges,
-                            ) {
-                                Ok(layers) => {
-                                    for layer in layers {
-                                        self.insert_layer(layer);
-                                    }
-                                }
-                                Err(e) => return Some(Err(e)),
-                            }
-                        }
-                    }
-                }
-
                 self.sort_layers();
                 continue 'main;
             }
@@ -1754,6 +2150,60 @@ impl<I: Iterator<Item = HighlightEvent>> Iterator for Merge<I> {
     }
 }
 
+/// Pretty prints the named nodes under `node` as an s-expression, a node per line with the
+/// field names of the nodes: `(function_item name: (identifier) ...)`.
+pub fn pretty_print_tree(out: &mut String, node: Node) {
+    pretty_print_tree_with(out, node, &mut |_, _| {})
+}
+
+/// Like [`pretty_print_tree`], calling `on_node` with every printed node and the byte range of
+/// its s-expression in `out`.
+pub fn pretty_print_tree_with(
+    out: &mut String,
+    node: Node,
+    on_node: &mut dyn FnMut(Node, ops::Range<usize>),
+) {
+    pretty_print_tree_impl(out, &mut node.walk(), 0, on_node)
+}
+
+fn pretty_print_tree_impl(
+    out: &mut String,
+    cursor: &mut tree_sitter::TreeCursor,
+    depth: usize,
+    on_node: &mut dyn FnMut(Node, ops::Range<usize>),
+) {
+    let node = cursor.node();
+    out.push_str(&" ".repeat(depth * 2));
+    if let Some(field_name) = cursor.field_name() {
+        out.push_str(field_name);
+        out.push_str(": ");
+    }
+    let start = out.len();
+    if node.is_missing() {
+        out.push_str("(MISSING ");
+    } else {
+        out.push('(');
+    }
+    out.push_str(node.kind());
+
+    if cursor.goto_first_child() {
+        loop {
+            let child = cursor.node();
+            if child.is_named() || child.is_missing() {
+                out.push('\n');
+                pretty_print_tree_impl(out, cursor, depth + 1, on_node);
+            }
+            if !cursor.goto_next_sibling() {
+                break;
+            }
+        }
+        cursor.goto_parent();
+    }
+
+    out.push(')');
+    on_node(node, start..out.len());
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -1826,6 +2276,331 @@ mod test {
         assert_eq!(struct_node.kind(), "struct_item");
     }
 
+    #[test]
+    fn test_combined_injection_edits() {
+        let highlight_names: Vec<String> = vec!["keyword".into(), "number".into(), "tag".into()];
+
+        let javascript = HighlightConfiguration::new(
+            get_language(&crate::RUNTIME_DIR, "javascript").unwrap(),
+            r#""let" @keyword (number) @number"#,
+            "",
+            "",
+        )
+        .unwrap();
+        javascript.configure(&highlight_names);
+        let javascript = Arc::new(javascript);
+        let language_config: Arc<LanguageConfiguration> = Arc::new(
+            toml::from_str(
+                r#"
+                name = "javascript"
+                scope = "source.js"
+                file-types = ["js"]
+                roots = []
+                "#,
+            )
+            .unwrap(),
+        );
+
+        // the text of every element is parsed as one javascript document
+        let html = HighlightConfiguration::new(
+            get_language(&crate::RUNTIME_DIR, "html").unwrap(),
+            "(tag_name) @tag",
+            r#"((text) @injection.content
+                (#set! injection.language "javascript")
+                (#set! injection.combined))"#,
+            "",
+        )
+        .unwrap();
+        html.configure(&highlight_names);
+
+        let mut source = Rope::from_str("<p>let x =</p><p>1;</p>");
+        let mut syntax = Syntax::new_with_injections(
+            &source,
+            Arc::new(html),
+            Arc::new(move |language| {
+                if language == "javascript" {
+                    Some((language_config.clone(), javascript.clone()))
+                } else {
+                    None
+                }
+            }),
+        );
+        assert_eq!(syntax.layers().count(), 2);
+
+        let highlights = |syntax: &Syntax, source: &Rope| {
+            let mut highlights = Vec::new();
+            let mut stack = Vec::new();
+            for event in syntax.highlight_iter(source.slice(..), None, None) {
+                match event.unwrap() {
+                    HighlightEvent::HighlightStart(Highlight(i)) => stack.push(i),
+                    HighlightEvent::HighlightEnd => {
+                        stack.pop();
+                    }
+                    HighlightEvent::Source { start, end } => {
+                        if let Some(&i) = stack.last() {
+                            highlights.push((
+                                highlight_names[i].clone(),
+                                source.slice(start..end).to_string(),
+                            ));
+                        }
+                    }
+                }
+            }
+            highlights
+        };
+
+        let found = highlights(&syntax, &source);
+        assert!(found.contains(&("keyword".into(), "let".into())));
+        assert!(found.contains(&("number".into(), "1".into())));
+        assert!(found.contains(&("tag".into(), "p".into())));
+
+        // the injection layer is edited along with the document
+        let old_source = source.clone();
+        let transaction =
+            Transaction::change(&source, vec![(17, 18, Some("2".into()))].into_iter());
+        transaction.apply(&mut source);
+        syntax
+            .update(&old_source, &source, transaction.changes())
+            .unwrap();
+
+        let found = highlights(&syntax, &source);
+        assert_eq!(syntax.layers().count(), 2);
+        assert!(found.contains(&("keyword".into(), "let".into())));
+        assert!(found.contains(&("number".into(), "2".into())));
+    }
+
+    #[test]
+    fn test_injection_layers_reused() {
+        let javascript = Arc::new(
+            HighlightConfiguration::new(
+                get_language(&crate::RUNTIME_DIR, "javascript").unwrap(),
+                "",
+                "",
+                "",
+            )
+            .unwrap(),
+        );
+        let language_config: Arc<LanguageConfiguration> = Arc::new(
+            toml::from_str(
+                r#"
+                name = "javascript"
+                scope = "source.js"
+                file-types = ["js"]
+                roots = []
+                "#,
+            )
+            .unwrap(),
+        );
+        let html = HighlightConfiguration::new(
+            get_language(&crate::RUNTIME_DIR, "html").unwrap(),
+            "",
+            r#"((script_element (raw_text) @injection.content)
+                (#set! injection.language "javascript"))"#,
+            "",
+        )
+        .unwrap();
+
+        let mut source = Rope::from_str("<script>a;</script><p>x</p><script>b;</script>");
+        let mut syntax = Syntax::new_with_injections(
+            &source,
+            Arc::new(html),
+            Arc::new(move |language| {
+                if language == "javascript" {
+                    Some((language_config.clone(), javascript.clone()))
+                } else {
+                    None
+                }
+            }),
+        );
+        let root_ids = |syntax: &Syntax| -> Vec<usize> {
+            syntax
+                .layers()
+                .skip(1)
+                .map(|layer| layer.tree().root_node().id())
+                .collect()
+        };
+        let ids = root_ids(&syntax);
+        assert_eq!(ids.len(), 2);
+
+        let mut edit = |syntax: &mut Syntax, from: usize, to: usize, text: &str| {
+            let old_source = source.clone();
+            let transaction =
+                Transaction::change(&source, vec![(from, to, Some(text.into()))].into_iter());
+            transaction.apply(&mut source);
+            syntax
+                .update(&old_source, &source, transaction.changes())
+                .unwrap();
+        };
+
+        // only the script that was edited is reparsed
+        edit(&mut syntax, 36, 37, "c");
+        let new_ids = root_ids(&syntax);
+        assert_eq!(new_ids.len(), 2);
+        assert!(new_ids.contains(&ids[0]));
+        assert!(!new_ids.contains(&ids[1]));
+
+        // a script added between the others is found, and they're kept
+        edit(&mut syntax, 27, 27, "<script>d;</script>");
+        let ids = root_ids(&syntax);
+        assert_eq!(ids.len(), 3);
+        assert!(new_ids.iter().all(|id| ids.contains(id)));
+        let ranges: Vec<_> = syntax
+            .layers()
+            .skip(1)
+            .map(|layer| range_key(layer.ranges()))
+            .collect();
+        assert!(ranges.contains(&vec![(35, 37)]));
+    }
+
+    #[test]
+    fn test_injection_layer_points() {
+        let javascript = Arc::new(
+            HighlightConfiguration::new(
+                get_language(&crate::RUNTIME_DIR, "javascript").unwrap(),
+                "",
+                "",
+                "",
+            )
+            .unwrap(),
+        );
+        let language_config: Arc<LanguageConfiguration> = Arc::new(
+            toml::from_str(
+                r#"
+                name = "javascript"
+                scope = "source.js"
+                file-types = ["js"]
+                roots = []
+                "#,
+            )
+            .unwrap(),
+        );
+        let html = HighlightConfiguration::new(
+            get_language(&crate::RUNTIME_DIR, "html").unwrap(),
+            "",
+            r#"((script_element (raw_text) @injection.content)
+                (#set! injection.language "javascript"))"#,
+            "",
+        )
+        .unwrap();
+
+        let mut source = Rope::from_str("<p>x</p><script>a;</script>");
+        let mut syntax = Syntax::new_with_injections(
+            &source,
+            Arc::new(html),
+            Arc::new(move |language| {
+                if language == "javascript" {
+                    Some((language_config.clone(), javascript.clone()))
+                } else {
+                    None
+                }
+            }),
+        );
+        let layer = |syntax: &Syntax| {
+            let layer = syntax.layers().nth(1).unwrap();
+            (layer.tree().root_node().id(), layer.ranges()[0])
+        };
+        let (id, range) = layer(&syntax);
+        assert_eq!(
+            (range.start_point, range.end_point),
+            (Point::new(0, 16), Point::new(0, 18))
+        );
+
+        // a newline in front of the script moves its range to the next line, without reparsing it
+        let old_source = source.clone();
+        let transaction =
+            Transaction::change(&source, vec![(8, 8, Some("\n  ".into()))].into_iter());
+        transaction.apply(&mut source);
+        syntax
+            .update(&old_source, &source, transaction.changes())
+            .unwrap();
+
+        let (new_id, range) = layer(&syntax);
+        assert_eq!(new_id, id);
+        assert_eq!((range.start_byte, range.end_byte), (19, 21));
+        assert_eq!(
+            (range.start_point, range.end_point),
+            (Point::new(1, 10), Point::new(1, 12))
+        );
+    }
+
+    #[test]
+    fn test_combined_injections() {
+        let highlight_names: Vec<String> = vec!["keyword".into(), "tag".into()];
+
+        // the start and end tag of an element are only matched together when the html around
+        // the code of the template is parsed as one document
+        let html = HighlightConfiguration::new(
+            get_language(&crate::RUNTIME_DIR, "html").unwrap(),
+            "(element (start_tag (tag_name) @tag) (end_tag (tag_name) @tag))",
+            "",
+            "",
+        )
+        .unwrap();
+        html.configure(&highlight_names);
+        let html = Arc::new(html);
+        let language_config: Arc<LanguageConfiguration> = Arc::new(
+            toml::from_str(
+                r#"
+                name = "html"
+                scope = "text.html.basic"
+                file-types = ["html"]
+                roots = []
+                "#,
+            )
+            .unwrap(),
+        );
+        let template = HighlightConfiguration::new(
+            get_language(&crate::RUNTIME_DIR, "embedded-template").unwrap(),
+            &std::fs::read_to_string("../runtime/queries/embedded-template/highlights.scm")
+                .unwrap(),
+            &std::fs::read_to_string("../runtime/queries/embedded-template/injections.scm")
+                .unwrap(),
+            "",
+        )
+        .unwrap();
+        template.configure(&highlight_names);
+
+        let source =
+            Rope::from_str("<ul><% items.each do |item| %><li><%= item %></li><% end %></ul>");
+        let syntax = Syntax::new_with_injections(
+            &source,
+            Arc::new(template),
+            Arc::new(move |language| {
+                if language == "html" {
+                    Some((language_config.clone(), html.clone()))
+                } else {
+                    None
+                }
+            }),
+        );
+        // all the html is in one layer
+        assert_eq!(syntax.layers().count(), 2);
+
+        let mut highlights = Vec::new();
+        let mut stack = Vec::new();
+        for event in syntax.highlight_iter(source.slice(..), None, None) {
+            match event.unwrap() {
+                HighlightEvent::HighlightStart(Highlight(i)) => stack.push(i),
+                HighlightEvent::HighlightEnd => {
+                    stack.pop();
+                }
+                HighlightEvent::Source { start, end } => {
+                    if let Some(&i) = stack.last() {
+                        highlights.push((highlight_names[i].as_str(), source.slice(start..end)));
+                    }
+                }
+            }
+        }
+
+        let tags: Vec<_> = highlights
+            .iter()
+            .filter(|(name, _)| *name == "tag")
+            .map(|(_, text)| text.to_string())
+            .collect();
+        assert_eq!(tags, ["ul", "li", "li", "ul"]);
+        assert!(highlights.contains(&("keyword", "<%=".into())));
+    }
+
     #[test]
     fn test_input_edits() {
         use tree_sitter::InputEdit;
@@ -1881,6 +2656,28 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_pretty_print() {
+        let language = get_language(&crate::RUNTIME_DIR, "Rust").unwrap();
+        let mut parser = Parser::new();
+        parser.set_language(language).unwrap();
+        let tree = parser.parse("fn main() { x }", None).unwrap();
+
+        let mut output = String::new();
+        let mut printed = Vec::new();
+        pretty_print_tree_with(&mut output, tree.root_node(), &mut |node, range| {
+            printed.push((node.kind(), range))
+        });
+        assert_eq!(
+            output,
+            "(source_file\n  (function_item\n    name: (identifier)\n    parameters: (parameters)\n    body: (block\n      (identifier))))"
+        );
+        // children are reported before their parents
+        assert_eq!(printed[0], ("identifier", 40..52));
+        assert_eq!(&output[printed[0].1.clone()], "(identifier)");
+        assert_eq!(printed.last().unwrap(), &("source_file", 0..output.len()));
+    }
+
     #[test]
     fn test_load_runtime_file() {
         // Test to make sure we can load some data from the runtime directory.
@@ -1890,4 +2687,72 @@ mod test {
         let results = load_runtime_file("rust", "does-not-exist");
         assert!(results.is_err());
     }
+
+    #[test]
+    fn test_language_detection() {
+        let config: Configuration = toml::from_str(
+            r#"
+            [[language]]
+            name = "bash"
+            scope = "source.bash"
+            file-types = ["sh", ".envrc"]
+            file-globs = [".env.*"]
+            shebangs = ["sh", "bash"]
+            roots = []
+
+            [[language]]
+            name = "python"
+            scope = "source.python"
+            file-types = ["py"]
+            shebangs = ["python"]
+            roots = []
+
+            [[language]]
+            name = "html"
+            scope = "text.html.basic"
+            file-types = ["html"]
+            first-line-regex = "^(?i)<!doctype html"
+            roots = []
+            "#,
+        )
+        .unwrap();
+        let loader = Loader::new(config);
+
+        let name_for_path = |path: &str| {
+            loader
+                .language_config_for_file_name(Path::new(path))
+                .map(|config| config.language_id.clone())
+        };
+        assert_eq!(name_for_path("/home/user/script.sh").unwrap(), "bash");
+        assert_eq!(name_for_path("/home/user/.envrc").unwrap(), "bash");
+        assert_eq!(name_for_path("/home/user/.env.local").unwrap(), "bash");
+        assert_eq!(name_for_path("/home/user/main.py.gz").unwrap(), "python");
+        assert_eq!(name_for_path("/home/user/script"), None);
+
+        let name_for_content = |text: &str| {
+            loader
+                .language_config_for_content(Rope::from(text).slice(..))
+                .map(|config| config.language_id.clone())
+        };
+        assert_eq!(name_for_content("#!/bin/sh\n").unwrap(), "bash");
+        assert_eq!(
+            name_for_content("#!/usr/bin/env -S python3.9 -u\nimport sys\n").unwrap(),
+            "python"
+        );
+        assert_eq!(name_for_content("<!DOCTYPE html>\n").unwrap(), "html");
+        assert_eq!(name_for_content("#!/usr/bin/env perl\n"), None);
+        assert_eq!(name_for_content(""), None);
+
+        assert!(toml::from_str::<Configuration>(
+            r#"
+            [[language]]
+            name = "broken"
+            scope = "source.broken"
+            file-types = []
+            file-globs = ["[a-"]
+            roots = []
+            "#
+        )
+        .is_err());
+    }
 }
@@ -0,0 +1,203 @@
+//! Definitions found with the `tags.scm` query of a language. They stand in for the document
+//! and workspace symbols of a language server in languages that don't have one.
+
+use crate::{syntax, Rope, RopeSlice};
+use std::fmt;
+use tree_sitter::{Language, Node, Parser, Query, QueryCursor, Tree};
+
+/// The compiled `tags.scm` query of a language.
+pub struct TagsConfiguration {
+    language: Language,
+    query: Query,
+    name_capture_index: Option<u32>,
+    /// The kind of definition of each capture: `function` for `@definition.function`.
+    definition_kinds: Vec<Option<String>>,
+}
+
+impl fmt::Debug for TagsConfiguration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TagsConfiguration")
+            .field("definition_kinds", &self.definition_kinds)
+            .finish()
+    }
+}
+
+/// A definition found by a tags query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub name: String,
+    /// What is defined, like `function` or `class`.
+    pub kind: String,
+    /// The line and char column the name starts at.
+    pub position: (usize, usize),
+    /// The first and last line of the whole definition.
+    pub lines: (usize, usize),
+}
+
+impl Tag {
+    /// The char index of the start of the name in `text`, as long as the text still has that
+    /// position.
+    pub fn char_index(&self, text: RopeSlice) -> Option<usize> {
+        let (line, column) = self.position;
+        if line >= text.len_lines() {
+            return None;
+        }
+        let index = text.line_to_char(line) + column;
+        if index <= text.len_chars() {
+            Some(index)
+        } else {
+            None
+        }
+    }
+}
+
+impl TagsConfiguration {
+    pub fn new(language: Language, tags_query: &str) -> Result<Self, tree_sitter::QueryError> {
+        let query = Query::new(language, tags_query)?;
+
+        let mut name_capture_index = None;
+        let definition_kinds = query
+            .capture_names()
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                if name == "name" {
+                    name_capture_index = Some(i as u32);
+                }
+                name.strip_prefix("definition.").map(str::to_owned)
+            })
+            .collect();
+
+        Ok(Self {
+            language,
+            query,
+            name_capture_index,
+            definition_kinds,
+        })
+    }
+
+    /// The definitions in `tree`, the syntax tree of `source`, in the order they appear in.
+    pub fn definitions(&self, tree: &Tree, source: RopeSlice) -> Vec<Tag> {
+        let name_capture_index = match self.name_capture_index {
+            Some(index) => index,
+            None => return Vec::new(),
+        };
+
+        let mut cursor = QueryCursor::new();
+        let matches = cursor.matches(&self.query, tree.root_node(), |node: Node| {
+            syntax::node_to_bytes(node, source)
+        });
+
+        let mut tags = Vec::new();
+        for mat in matches {
+            let mut name = None;
+            let mut definition = None;
+            for capture in mat.captures {
+                if capture.index == name_capture_index {
+                    name = Some(capture.node);
+                } else if let Some(kind) = &self.definition_kinds[capture.index as usize] {
+                    definition = Some((capture.node, kind));
+                }
+            }
+
+            if let (Some(name), Some((node, kind))) = (name, definition) {
+                let start = source.byte_to_char(name.start_byte());
+                let line = source.char_to_line(start);
+                tags.push(Tag {
+                    name: syntax::byte_range_to_str(name.byte_range(), source).into_owned(),
+                    kind: kind.clone(),
+                    position: (line, start - source.line_to_char(line)),
+                    lines: (node.start_position().row, node.end_position().row),
+                });
+            }
+        }
+
+        // a definition can be matched by more than one pattern
+        tags.sort_by_key(|tag| tag.position);
+        tags.dedup_by(|a, b| a.position == b.position);
+        tags
+    }
+
+    /// Parses `source` and returns its definitions.
+    pub fn parse_definitions(&self, source: &Rope) -> Vec<Tag> {
+        let mut parser = Parser::new();
+        if parser.set_language(self.language).is_err() {
+            return Vec::new();
+        }
+        let tree = parser.parse_with(
+            &mut |byte, _| {
+                if byte <= source.len_bytes() {
+                    let (chunk, start_byte, _, _) = source.chunk_at_byte(byte);
+                    &chunk.as_bytes()[byte - start_byte..]
+                } else {
+                    // out of range
+                    &[]
+                }
+            },
+            None,
+        );
+        match tree {
+            Some(tree) => self.definitions(&tree, source.slice(..)),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tag_char_index() {
+        let text = Rope::from("fn a() {}\n  fn über() {}\n");
+        let tag = Tag {
+            name: "über".to_string(),
+            kind: "function".to_string(),
+            position: (1, 5),
+            lines: (1, 1),
+        };
+        assert_eq!(tag.char_index(text.slice(..)), Some(15));
+
+        let tag = Tag {
+            position: (3, 0),
+            ..tag
+        };
+        assert_eq!(tag.char_index(text.slice(..)), None);
+    }
+
+    #[test]
+    fn test_definitions() {
+        let source = Rope::from(
+            "struct Foo;\n\nimpl Foo {\n    fn new() -> Self {\n        Foo\n    }\n}\n\nfn main() {}\n",
+        );
+        let language = syntax::get_language(&crate::RUNTIME_DIR, "Rust").unwrap();
+        let config = TagsConfiguration::new(
+            language,
+            "(function_item name: (identifier) @name) @definition.function
+             (struct_item name: (type_identifier) @name) @definition.class
+             (call_expression function: (identifier) @name) @reference.call",
+        )
+        .unwrap();
+
+        let tags = config.parse_definitions(&source);
+        let tags: Vec<_> = tags
+            .iter()
+            .map(|tag| {
+                (
+                    tag.name.as_str(),
+                    tag.kind.as_str(),
+                    tag.position,
+                    tag.lines,
+                )
+            })
+            .collect();
+        assert_eq!(
+            tags,
+            vec![
+                ("Foo", "class", (0, 7), (0, 0)),
+                ("new", "function", (3, 7), (3, 5)),
+                ("main", "function", (8, 3), (8, 8)),
+            ]
+        );
+    }
+}
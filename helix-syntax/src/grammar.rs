@@ -0,0 +1,203 @@
+//! Fetching and compiling tree-sitter grammars into dynamic libraries. Shared between the build
+//! script, which compiles the bundled grammars, and `hx --grammar build`.
+
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::time::SystemTime;
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+#[cfg(unix)]
+pub const DYLIB_EXTENSION: &str = "so";
+
+#[cfg(windows)]
+pub const DYLIB_EXTENSION: &str = "dll";
+
+/// The path of the compiled grammar of `language` in `dir`.
+pub fn library_path(dir: &Path, language: &str) -> PathBuf {
+    let mut library_path = dir.join(language);
+    library_path.set_extension(DYLIB_EXTENSION);
+    library_path
+}
+
+/// Compiles the grammar in `src_path`, the directory containing its `parser.c`, into a library
+/// for `language` in `out_dir`. Returns whether the library had to be (re)compiled, it is left
+/// alone when it is newer than the sources.
+pub fn build_grammar(
+    src_path: &Path,
+    language: &str,
+    out_dir: &Path,
+    target: &str,
+) -> Result<bool> {
+    let header_path = src_path;
+    // let grammar_path = src_path.join("grammar.json");
+    let parser_path = src_path.join("parser.c");
+    let mut scanner_path = src_path.join("scanner.c");
+
+    if !parser_path.exists() {
+        return Err(anyhow!("{:?} doesn't exist", parser_path));
+    }
+
+    let scanner_path = if scanner_path.exists() {
+        Some(scanner_path)
+    } else {
+        scanner_path.set_extension("cc");
+        if scanner_path.exists() {
+            Some(scanner_path)
+        } else {
+            None
+        }
+    };
+    fs::create_dir_all(out_dir)
+        .with_context(|| format!("Failed to create directory {:?}", out_dir))?;
+    let library_path = library_path(out_dir, language);
+
+    let recompile = needs_recompile(&library_path, &parser_path, &scanner_path)
+        .with_context(|| "Failed to compare source and binary timestamps")?;
+
+    if !recompile {
+        return Ok(false);
+    }
+    let mut config = cc::Build::new();
+    config
+        .cpp(true)
+        .opt_level(2)
+        .cargo_metadata(false)
+        .target(target)
+        .host(target);
+    let compiler = config.get_compiler();
+    let mut command = Command::new(compiler.path());
+    command.current_dir(src_path);
+    // later versions of cc deprecate `env` for `get_envs`, which cc 1.0 doesn't have
+    #[allow(deprecated)]
+    for (key, value) in compiler.env() {
+        command.env(key, value);
+    }
+
+    if cfg!(windows) {
+        command
+            .args(["/nologo", "/LD", "/I"].iter())
+            .arg(header_path)
+            .arg("/Od")
+            .arg("/utf-8");
+        if let Some(scanner_path) = scanner_path.as_ref() {
+            command.arg(scanner_path);
+        }
+
+        command
+            .arg(parser_path)
+            .arg("/link")
+            .arg(format!("/out:{}", library_path.to_str().unwrap()));
+    } else {
+        command
+            .arg("-shared")
+            .arg("-fPIC")
+            .arg("-fno-exceptions")
+            .arg("-g")
+            .arg("-I")
+            .arg(header_path)
+            .arg("-o")
+            .arg(&library_path)
+            .arg("-O2");
+        if let Some(scanner_path) = scanner_path.as_ref() {
+            if scanner_path.extension() == Some("c".as_ref()) {
+                command.arg("-xc").arg("-std=c99").arg(scanner_path);
+            } else {
+                command.arg(scanner_path);
+            }
+        }
+        command.arg("-xc").arg(parser_path);
+        if cfg!(all(unix, not(target_os = "macos"))) {
+            command.arg("-Wl,-z,relro,-z,now");
+        }
+    }
+
+    let output = command
+        .output()
+        .with_context(|| "Failed to execute C compiler")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Parser compilation failed.\nStdout: {}\nStderr: {}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    Ok(true)
+}
+
+fn needs_recompile(
+    lib_path: &Path,
+    parser_c_path: &Path,
+    scanner_path: &Option<PathBuf>,
+) -> Result<bool> {
+    if !lib_path.exists() {
+        return Ok(true);
+    }
+    let lib_mtime = mtime(lib_path)?;
+    if mtime(parser_c_path)? > lib_mtime {
+        return Ok(true);
+    }
+    if let Some(scanner_path) = scanner_path {
+        if mtime(scanner_path)? > lib_mtime {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn mtime(path: &Path) -> Result<SystemTime> {
+    Ok(fs::metadata(path)?.modified()?)
+}
+
+/// Checks out `revision` of the git repository at `remote` into `dir`, fetching only that
+/// revision. Returns whether anything had to be fetched.
+pub fn fetch_grammar(remote: &str, revision: &str, dir: &Path) -> Result<bool> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create directory {:?}", dir))?;
+
+    if !dir.join(".git").exists() {
+        git(dir, &["init", "--quiet"])?;
+        git(dir, &["remote", "add", "origin", remote])?;
+    } else {
+        git(dir, &["remote", "set-url", "origin", remote])?;
+    }
+
+    if git(dir, &["rev-parse", "HEAD"]).ok().as_deref() == Some(revision) {
+        return Ok(false);
+    }
+
+    git(
+        dir,
+        &["fetch", "--quiet", "--depth", "1", "origin", revision],
+    )?;
+    git(
+        dir,
+        &[
+            "-c",
+            "advice.detachedHead=false",
+            "checkout",
+            "--quiet",
+            "FETCH_HEAD",
+        ],
+    )?;
+    Ok(true)
+}
+
+/// Runs git in `dir`, returning its trimmed output.
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .with_context(|| "Failed to execute git")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -1,21 +1,19 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use libloading::{Library, Symbol};
 use tree_sitter::Language;
 
+pub mod grammar;
+
+/// The target helix was built for, which grammars are compiled for at runtime.
+pub const BUILD_TARGET: &str = env!("BUILD_TARGET");
+
 fn replace_dashes_with_underscores(name: &str) -> String {
     name.replace('-', "_")
 }
-#[cfg(unix)]
-const DYLIB_EXTENSION: &str = "so";
-
-#[cfg(windows)]
-const DYLIB_EXTENSION: &str = "dll";
 
 pub fn get_language(runtime_path: &std::path::Path, name: &str) -> Result<Language> {
     let name = name.to_ascii_lowercase();
-    let mut library_path = runtime_path.join("grammars").join(&name);
-    // TODO: duplicated under build
-    library_path.set_extension(DYLIB_EXTENSION);
+    let library_path = grammar::library_path(&runtime_path.join("grammars"), &name);
 
     let library = unsafe { Library::new(&library_path) }
         .with_context(|| format!("Error opening dynamic library {:?}", &library_path))?;
@@ -27,5 +25,19 @@ pub fn get_language(runtime_path: &std::path::Path, name: &str) -> Result<Langua
         language_fn()
     };
     std::mem::forget(library);
+
+    // tree-sitter panics on grammars generated for an ABI it doesn't support
+    let version = language.version();
+    if !(tree_sitter::MIN_COMPATIBLE_LANGUAGE_VERSION..=tree_sitter::LANGUAGE_VERSION)
+        .contains(&version)
+    {
+        return Err(anyhow!(
+            "Grammar {:?} has ABI version {}, but only versions {} to {} are supported",
+            library_path,
+            version,
+            tree_sitter::MIN_COMPATIBLE_LANGUAGE_VERSION,
+            tree_sitter::LANGUAGE_VERSION
+        ));
+    }
     Ok(language)
 }
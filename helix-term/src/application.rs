@@ -1,13 +1,15 @@
-use helix_core::{merge_toml_values, syntax};
+use helix_core::syntax;
 use helix_lsp::{lsp, util::lsp_pos_to_pos, LspProgressMap};
-use helix_view::{theme, Editor};
+use helix_view::{session::Session, swap, theme, DocumentId, Editor, ViewId};
 
-use crate::{args::Args, compositor::Compositor, config::Config, job::Jobs, ui};
+use crate::{args::Args, commands, compositor::Compositor, config::Config, job::Jobs, ui};
 
 use log::error;
 
 use std::{
+    collections::HashMap,
     io::{stdout, Write},
+    pin::Pin,
     sync::Arc,
     time::{Duration, Instant},
 };
@@ -15,7 +17,10 @@ use std::{
 use anyhow::Error;
 
 use crossterm::{
-    event::{DisableMouseCapture, EnableMouseCapture, Event, EventStream},
+    event::{
+        DisableFocusChange, DisableMouseCapture, EnableFocusChange, EnableMouseCapture, Event,
+        EventStream,
+    },
     execute, terminal,
 };
 #[cfg(not(windows))]
@@ -46,6 +51,13 @@ pub struct Application {
     signals: Signals,
     jobs: Jobs,
     lsp_progress: LspProgressMap,
+
+    /// Fires when there was no input for the auto-save delay, if `idle_timer_armed`.
+    idle_timer: Pin<Box<tokio::time::Sleep>>,
+    idle_timer_armed: bool,
+    /// The document of each view after the last event, to auto-save the ones switched away from.
+    view_documents: HashMap<ViewId, DocumentId>,
+    swap_timer: tokio::time::Interval,
 }
 
 impl Application {
@@ -59,17 +71,6 @@ impl Application {
         let theme_loader =
             std::sync::Arc::new(theme::Loader::new(&conf_dir, &helix_core::runtime_dir()));
 
-        // load default and user config, and merge both
-        let def_lang_conf: toml::Value = toml::from_slice(include_bytes!("../../languages.toml"))
-            .expect("Could not parse built-in languages.toml, something must be very wrong");
-        let user_lang_conf: Option<toml::Value> = std::fs::read(conf_dir.join("languages.toml"))
-            .ok()
-            .map(|raw| toml::from_slice(&raw).expect("Could not parse user languages.toml"));
-        let lang_conf = match user_lang_conf {
-            Some(value) => merge_toml_values(def_lang_conf, value),
-            None => def_lang_conf,
-        };
-
         let theme = if let Some(theme) = &config.theme {
             match theme_loader.load(theme) {
                 Ok(theme) => theme,
@@ -82,8 +83,8 @@ impl Application {
             theme_loader.default()
         };
 
-        let syn_loader_conf: helix_core::syntax::Configuration = lang_conf
-            .try_into()
+        // load default and user config, and merge both
+        let syn_loader_conf = helix_core::config::user_syntax_config()
             .expect("Could not parse merged (built-in + user) languages.toml");
         let syn_loader = std::sync::Arc::new(syntax::Loader::new(syn_loader_conf));
 
@@ -93,11 +94,18 @@ impl Application {
             syn_loader.clone(),
             config.editor.clone(),
         );
+        // before opening files so that they're opened at the selection they were left with
+        editor.load_state();
 
         let editor_view = Box::new(ui::EditorView::new(std::mem::take(&mut config.keys)));
         compositor.push(editor_view);
 
-        if !args.files.is_empty() {
+        if let Some(name) = &args.session {
+            Session::load(name)?.restore(&mut editor)?;
+            if editor.status_msg.is_none() {
+                editor.set_status(format!("Loaded session {}", name));
+            }
+        } else if !args.files.is_empty() {
             let first = &args.files[0]; // we know it's not empty
             if first.is_dir() {
                 editor.new_file(Action::VerticalSplit);
@@ -114,7 +122,10 @@ impl Application {
                         editor.open(file.to_path_buf(), Action::Load)?;
                     }
                 }
-                editor.set_status(format!("Loaded {} files.", nr_of_files));
+                // keep warnings about swap files
+                if editor.status_msg.is_none() {
+                    editor.set_status(format!("Loaded {} files.", nr_of_files));
+                }
             }
         } else {
             editor.new_file(Action::VerticalSplit);
@@ -139,6 +150,11 @@ impl Application {
             signals,
             jobs: Jobs::new(),
             lsp_progress: LspProgressMap::new(),
+
+            idle_timer: Box::pin(tokio::time::sleep(Duration::ZERO)),
+            idle_timer_armed: false,
+            view_documents: HashMap::new(),
+            swap_timer: tokio::time::interval(swap::INTERVAL),
         };
 
         Ok(app)
@@ -166,6 +182,8 @@ impl Application {
         self.render();
 
         loop {
+            self.save_switched_documents();
+
             if self.editor.should_close() {
                 self.jobs.finish();
                 break;
@@ -199,10 +217,43 @@ impl Application {
                     self.jobs.handle_callback(&mut self.editor, &mut self.compositor, callback);
                     self.render();
                 }
+                _ = self.swap_timer.tick() => {
+                    self.editor.write_swap_files();
+                }
+                _ = &mut self.idle_timer, if self.idle_timer_armed => {
+                    self.idle_timer_armed = false;
+                    let docs: Vec<_> = self.editor.documents().map(|doc| doc.id()).collect();
+                    commands::auto_save(&mut self.editor, &mut self.jobs, &docs);
+                    self.render();
+                }
             }
         }
     }
 
+    /// Auto-saves the documents that views switched away from since the last event.
+    fn save_switched_documents(&mut self) {
+        let view_documents: HashMap<_, _> = self
+            .editor
+            .tree
+            .views()
+            .map(|(view, _)| (view.id, view.doc))
+            .collect();
+        let left: Vec<_> = view_documents
+            .iter()
+            .filter_map(|(view_id, doc)| {
+                self.view_documents
+                    .get(view_id)
+                    .filter(|&previous| previous != doc)
+                    .copied()
+            })
+            .collect();
+        self.view_documents = view_documents;
+
+        if self.editor.config.auto_save.buffer_switch {
+            commands::auto_save(&mut self.editor, &mut self.jobs, &left);
+        }
+    }
+
     #[cfg(windows)]
     // no signal handling available on windows
     pub async fn handle_signals(&mut self, _signal: ()) {}
@@ -242,11 +293,33 @@ impl Application {
                 self.compositor
                     .handle_event(Event::Resize(width, height), &mut cx)
             }
+            Some(Ok(Event::FocusLost)) => {
+                if cx.editor.config.auto_save.focus_lost {
+                    let docs: Vec<_> = cx.editor.documents().map(|doc| doc.id()).collect();
+                    commands::auto_save(cx.editor, cx.jobs, &docs);
+                }
+                self.compositor.handle_event(Event::FocusLost, &mut cx)
+            }
             Some(Ok(event)) => self.compositor.handle_event(event, &mut cx),
             Some(Err(x)) => panic!("{}", x),
             None => panic!(),
         };
 
+        // the playground catches up with the edits and the cursor once input pauses
+        if let Some(editor_view) = self
+            .compositor
+            .find(std::any::type_name::<ui::EditorView>())
+            .and_then(|component| component.as_any_mut().downcast_mut::<ui::EditorView>())
+        {
+            editor_view.schedule_playground_update(&mut self.jobs);
+        }
+
+        if let Some(delay) = self.editor.config.auto_save.after_delay {
+            let deadline = tokio::time::Instant::now() + Duration::from_millis(delay);
+            self.idle_timer.as_mut().reset(deadline);
+            self.idle_timer_armed = true;
+        }
+
         if should_redraw && !self.editor.should_close() {
             self.render();
         }
@@ -500,7 +573,7 @@ impl Application {
     async fn claim_term(&mut self) -> Result<(), Error> {
         terminal::enable_raw_mode()?;
         let mut stdout = stdout();
-        execute!(stdout, terminal::EnterAlternateScreen)?;
+        execute!(stdout, terminal::EnterAlternateScreen, EnableFocusChange)?;
         if self.config.editor.mouse {
             execute!(stdout, EnableMouseCapture)?;
         }
@@ -511,7 +584,7 @@ impl Application {
         let mut stdout = stdout();
         // reset cursor shape
         write!(stdout, "\x1B[2 q")?;
-        execute!(stdout, DisableMouseCapture)?;
+        execute!(stdout, DisableMouseCapture, DisableFocusChange)?;
         execute!(stdout, terminal::LeaveAlternateScreen)?;
         terminal::disable_raw_mode()?;
         Ok(())
@@ -526,7 +599,7 @@ impl Application {
             // We can't handle errors properly inside this closure.  And it's
             // probably not a good idea to `unwrap()` inside a panic handler.
             // So we just ignore the `Result`s.
-            let _ = execute!(std::io::stdout(), DisableMouseCapture);
+            let _ = execute!(std::io::stdout(), DisableMouseCapture, DisableFocusChange);
             let _ = execute!(std::io::stdout(), terminal::LeaveAlternateScreen);
             let _ = terminal::disable_raw_mode();
             hook(info);
@@ -534,6 +607,12 @@ impl Application {
 
         self.event_loop().await;
 
+        if let Err(err) = self.editor.save_state() {
+            log::error!("failed to save state: {:#}", err);
+        }
+        // only an instance that crashed leaves its swap files behind
+        self.editor.swap_files.remove_all();
+
         if self.editor.close_language_servers(None).await.is_err() {
             log::error!("Timed out waiting for language servers to shutdown");
         };
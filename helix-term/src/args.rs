@@ -1,11 +1,24 @@
 use anyhow::{Error, Result};
 use std::path::PathBuf;
 
+/// What `hx --grammar` does instead of starting the editor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrammarAction {
+    Build,
+    List,
+}
+
 #[derive(Default)]
 pub struct Args {
     pub display_help: bool,
     pub display_version: bool,
+    pub grammar: Option<GrammarAction>,
+    pub health: bool,
+    /// The language to check the health of, `None` checks all of them.
+    pub health_language: Option<String>,
     pub verbosity: u64,
+    /// The session to start with instead of the files.
+    pub session: Option<String>,
     pub files: Vec<PathBuf>,
 }
 
@@ -17,11 +30,31 @@ impl Args {
 
         iter.next(); // skip the program, we don't care about that
 
-        for arg in &mut iter {
+        while let Some(arg) = iter.next() {
             match arg.as_str() {
                 "--" => break, // stop parsing at this point treat the remaining as files
                 "--version" => args.display_version = true,
                 "--help" => args.display_help = true,
+                "--health" => {
+                    args.health = true;
+                    // the language is optional
+                    if let Some(language) = iter.as_slice().first() {
+                        if !language.starts_with('-') {
+                            args.health_language = iter.next().cloned();
+                        }
+                    }
+                }
+                "--grammar" => {
+                    args.grammar = match iter.next().map(String::as_str) {
+                        Some("build") => Some(GrammarAction::Build),
+                        Some("list") => Some(GrammarAction::List),
+                        _ => return Err(Error::msg("--grammar must be followed by build or list")),
+                    }
+                }
+                "--session" => match iter.next() {
+                    Some(name) => args.session = Some(name.clone()),
+                    None => return Err(Error::msg("--session must be followed by a name")),
+                },
                 arg if arg.starts_with("--") => {
                     return Err(Error::msg(format!(
                         "unexpected double dash argument: {}",
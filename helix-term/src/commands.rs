@@ -1,5 +1,7 @@
 use helix_core::{
-    comment, coords_at_pos, find_first_non_whitespace_char, find_root, graphemes, indent,
+    comment, coords_at_pos, find_first_non_whitespace_char, find_root,
+    fold::{self, HiddenLines},
+    graphemes, indent,
     indent::IndentStyle,
     line_ending::{get_line_ending_of_str, line_end_char_index, str_is_line_ending},
     match_brackets,
@@ -7,13 +9,20 @@ use helix_core::{
     object, pos_at_coords,
     regex::{self, Regex},
     register::Register,
-    search, selection, surround, textobject, LineEnding, Position, Range, Rope, RopeGraphemes,
-    RopeSlice, Selection, SmallVec, Tendril, Transaction,
+    search, selection, surround,
+    tags::Tag,
+    textobject, LineEnding, Position, Range, Rope, RopeGraphemes, RopeSlice, Selection, SmallVec,
+    Tendril, Transaction,
 };
 
 use helix_view::{
-    clipboard::ClipboardType, document::Mode, editor::Action, input::KeyEvent, keyboard::KeyCode,
-    view::View, Document, DocumentId, Editor, ViewId,
+    clipboard::ClipboardType,
+    document::{binary_byte_to_char, binary_char_to_byte, Mode},
+    editor::Action,
+    input::KeyEvent,
+    keyboard::KeyCode,
+    view::{Jump, View, HEX_ROW_LEN},
+    Document, DocumentId, Editor, ViewId,
 };
 
 use anyhow::{anyhow, bail, Context as _};
@@ -109,6 +118,7 @@ fn align_view(doc: &Document, view: &mut View, align: Align) {
         .primary()
         .cursor(doc.text().slice(..));
     let line = doc.text().char_to_line(pos);
+    let hidden = doc.hidden_lines(view.id);
 
     let height = view.inner_area().height as usize;
 
@@ -118,7 +128,7 @@ fn align_view(doc: &Document, view: &mut View, align: Align) {
         Align::Bottom => height,
     };
 
-    view.offset.row = line.saturating_sub(relative);
+    view.offset.row = hidden.from_visual(hidden.to_visual(line).saturating_sub(relative));
 }
 
 /// A command is composed of a static name, and a function that takes the current state plus a count,
@@ -205,6 +215,8 @@ impl Command {
         search_next, "Select next search match",
         extend_search_next, "Add next search match to selection",
         search_selection, "Use current selection as search pattern",
+        select_next_occurrence, "Add next occurrence of primary selection to selection",
+        skip_occurrence, "Replace primary selection with its next occurrence",
         extend_line, "Select current line, if already selected, extend to next line",
         extend_to_line_bounds, "Extend selection to line bounds (line-wise selection)",
         delete_selection, "Delete selection",
@@ -218,6 +230,8 @@ impl Command {
         code_action, "Perform code action",
         buffer_picker, "Open buffer picker",
         symbol_picker, "Open symbol picker",
+        workspace_symbol_picker, "Open workspace symbol picker",
+        jumplist_picker, "Open jumplist picker",
         last_picker, "Open last picker",
         prepend_to_line, "Insert at start of line",
         append_to_line, "Insert at end of line",
@@ -257,6 +271,8 @@ impl Command {
         delete_word_backward, "Delete previous word",
         undo, "Undo change",
         redo, "Redo change",
+        undo_selection, "Undo selection change",
+        redo_selection, "Redo selection change",
         yank, "Yank selection",
         yank_joined_to_clipboard, "Join and yank selections to clipboard",
         yank_main_selection_to_clipboard, "Yank main selection to clipboard",
@@ -287,6 +303,7 @@ impl Command {
         expand_selection, "Expand selection to parent syntax node",
         jump_forward, "Jump forward on jumplist",
         jump_backward, "Jump backward on jumplist",
+        save_selection, "Save current selection to jumplist",
         rotate_view, "Goto next window",
         hsplit, "Horizontal bottom split",
         vsplit, "Vertical right split",
@@ -298,6 +315,11 @@ impl Command {
         align_view_bottom, "Align view bottom",
         scroll_up, "Scroll view up",
         scroll_down, "Scroll view down",
+        fold, "Fold the syntax node under the cursor",
+        unfold, "Unfold the fold under the cursor",
+        toggle_fold, "Toggle the fold under the cursor",
+        fold_all, "Fold all syntax nodes at a nesting level (count)",
+        unfold_all, "Unfold all folds",
         match_brackets, "Goto matching bracket",
         surround_add, "Surround add",
         surround_replace, "Surround replace",
@@ -380,10 +402,17 @@ fn move_char_right(cx: &mut Context) {
 fn move_line_up(cx: &mut Context) {
     let count = cx.count();
     let (view, doc) = current!(cx.editor);
-    let text = doc.text().slice(..);
+    let hidden = doc.hidden_lines(view.id);
 
     let selection = doc.selection(view.id).clone().transform(|range| {
-        movement::move_vertically(text, range, Direction::Backward, count, Movement::Move)
+        move_vertically(
+            doc,
+            &hidden,
+            range,
+            Direction::Backward,
+            count,
+            Movement::Move,
+        )
     });
     doc.set_selection(view.id, selection);
 }
@@ -391,14 +420,58 @@ fn move_line_up(cx: &mut Context) {
 fn move_line_down(cx: &mut Context) {
     let count = cx.count();
     let (view, doc) = current!(cx.editor);
-    let text = doc.text().slice(..);
+    let hidden = doc.hidden_lines(view.id);
 
     let selection = doc.selection(view.id).clone().transform(|range| {
-        movement::move_vertically(text, range, Direction::Forward, count, Movement::Move)
+        move_vertically(
+            doc,
+            &hidden,
+            range,
+            Direction::Forward,
+            count,
+            Movement::Move,
+        )
     });
     doc.set_selection(view.id, selection);
 }
 
+/// Moves a range vertically by `count` visible lines, skipping the lines hidden by folds.
+/// Binary documents are a single line shown as rows of `HEX_ROW_LEN` bytes, so they are
+/// moved through by that many chars instead.
+fn move_vertically(
+    doc: &Document,
+    hidden: &HiddenLines,
+    range: Range,
+    dir: Direction,
+    count: usize,
+    behaviour: Movement,
+) -> Range {
+    let text = doc.text().slice(..);
+    if !doc.is_binary() {
+        let line = range.cursor_line(text);
+        let row = hidden.to_visual(line);
+        let last_row = hidden.to_visual(text.len_lines().saturating_sub(1));
+        let target = match dir {
+            Direction::Forward => hidden.from_visual((row + count).min(last_row)),
+            Direction::Backward => hidden.from_visual(row.saturating_sub(count)),
+        };
+        let count = match dir {
+            Direction::Forward => target.saturating_sub(line),
+            Direction::Backward => line.saturating_sub(target),
+        };
+        return movement::move_vertically(text, range, dir, count, behaviour);
+    }
+
+    let pos = range.cursor(text);
+    let offset = count * HEX_ROW_LEN;
+    let new_pos = match dir {
+        Direction::Forward if pos + offset < text.len_chars() => pos + offset,
+        Direction::Backward if pos >= offset => pos - offset,
+        _ => pos,
+    };
+    range.put_cursor(text, new_pos, behaviour == Movement::Extend)
+}
+
 fn goto_line_end(cx: &mut Context) {
     let (view, doc) = current!(cx.editor);
     let text = doc.text().slice(..);
@@ -469,16 +542,19 @@ fn goto_window(cx: &mut Context, align: Align) {
     // as we type
     let scrolloff = cx.editor.config.scrolloff.min(height.saturating_sub(1) / 2);
 
-    let last_line = view.last_line(doc);
+    // count visual rows, which folded lines don't take up
+    let hidden = doc.hidden_lines(view.id);
+    let first_row = hidden.to_visual(view.offset.row);
+    let last_row = hidden.to_visual(view.last_line(doc));
 
-    let line = match align {
-        Align::Top => (view.offset.row + scrolloff),
-        Align::Center => (view.offset.row + (height / 2)),
-        Align::Bottom => last_line.saturating_sub(scrolloff),
+    let row = match align {
+        Align::Top => (first_row + scrolloff),
+        Align::Center => (first_row + (height / 2)),
+        Align::Bottom => last_row.saturating_sub(scrolloff),
     }
-    .min(last_line.saturating_sub(scrolloff));
+    .min(last_row.saturating_sub(scrolloff));
 
-    let pos = doc.text().line_to_char(line);
+    let pos = doc.text().line_to_char(hidden.from_visual(row));
 
     doc.set_selection(view.id, Selection::point(pos));
 }
@@ -893,6 +969,25 @@ pub fn scroll(cx: &mut Context, offset: usize, direction: Direction) {
     let range = doc.selection(view.id).primary();
     let text = doc.text().slice(..);
 
+    // binary documents have no lines to scroll by, move the cursor by rows and let the view follow
+    if doc.is_binary() {
+        let behaviour = if doc.mode == Mode::Select {
+            Movement::Extend
+        } else {
+            Movement::Move
+        };
+        let range = move_vertically(
+            doc,
+            &HiddenLines::default(),
+            range,
+            direction,
+            offset,
+            behaviour,
+        );
+        doc.set_selection(view.id, Selection::single(range.anchor, range.head));
+        return;
+    }
+
     let cursor = coords_at_pos(text, range.cursor(text));
     let doc_last_line = doc.text().len_lines().saturating_sub(1);
 
@@ -908,20 +1003,25 @@ pub fn scroll(cx: &mut Context, offset: usize, direction: Direction) {
 
     let scrolloff = cx.editor.config.scrolloff.min(height as usize / 2);
 
-    view.offset.row = match direction {
-        Forward => view.offset.row + offset,
-        Backward => view.offset.row.saturating_sub(offset),
+    // scroll by visual rows, which folded lines don't take up
+    let hidden = doc.hidden_lines(view.id);
+    let offset_row = match direction {
+        Forward => hidden.to_visual(view.offset.row) + offset,
+        Backward => hidden.to_visual(view.offset.row).saturating_sub(offset),
     }
-    .min(doc_last_line);
+    .min(hidden.to_visual(doc_last_line));
+    view.offset.row = hidden.from_visual(offset_row);
 
     // recalculate last line
     let last_line = view.last_line(doc);
 
     // clamp into viewport
-    let line = cursor
-        .row
-        .max(view.offset.row + scrolloff)
-        .min(last_line.saturating_sub(scrolloff));
+    let line = hidden.from_visual(
+        hidden
+            .to_visual(cursor.row)
+            .max(offset_row + scrolloff)
+            .min(hidden.to_visual(last_line).saturating_sub(scrolloff)),
+    );
 
     let head = pos_at_coords(text, Position::new(line, cursor.col), true); // this func will properly truncate to line end
 
@@ -1054,10 +1154,18 @@ fn copy_selection_on_next_line(cx: &mut Context) {
 fn extend_line_up(cx: &mut Context) {
     let count = cx.count();
     let (view, doc) = current!(cx.editor);
-    let text = doc.text().slice(..);
+
+    let hidden = doc.hidden_lines(view.id);
 
     let selection = doc.selection(view.id).clone().transform(|range| {
-        movement::move_vertically(text, range, Direction::Backward, count, Movement::Extend)
+        move_vertically(
+            doc,
+            &hidden,
+            range,
+            Direction::Backward,
+            count,
+            Movement::Extend,
+        )
     });
     doc.set_selection(view.id, selection);
 }
@@ -1065,10 +1173,18 @@ fn extend_line_up(cx: &mut Context) {
 fn extend_line_down(cx: &mut Context) {
     let count = cx.count();
     let (view, doc) = current!(cx.editor);
-    let text = doc.text().slice(..);
+
+    let hidden = doc.hidden_lines(view.id);
 
     let selection = doc.selection(view.id).clone().transform(|range| {
-        movement::move_vertically(text, range, Direction::Forward, count, Movement::Extend)
+        move_vertically(
+            doc,
+            &hidden,
+            range,
+            Direction::Forward,
+            count,
+            Movement::Extend,
+        )
     });
     doc.set_selection(view.id, selection);
 }
@@ -1081,23 +1197,34 @@ fn select_all(cx: &mut Context) {
 }
 
 fn select_regex(cx: &mut Context) {
-    let prompt = ui::regex_prompt(cx, "select:".into(), move |view, doc, _, regex| {
-        let text = doc.text().slice(..);
-        if let Some(selection) = selection::select_on_matches(text, doc.selection(view.id), &regex)
-        {
-            doc.set_selection(view.id, selection);
-        }
-    });
+    let prompt = ui::regex_prompt(
+        cx,
+        "select:".into(),
+        Some('\\'),
+        move |view, doc, _, regex| {
+            let text = doc.text().slice(..);
+            if let Some(selection) =
+                selection::select_on_matches(text, doc.selection(view.id), &regex)
+            {
+                doc.set_selection(view.id, selection);
+            }
+        },
+    );
 
     cx.push_layer(Box::new(prompt));
 }
 
 fn split_selection(cx: &mut Context) {
-    let prompt = ui::regex_prompt(cx, "split:".into(), move |view, doc, _, regex| {
-        let text = doc.text().slice(..);
-        let selection = selection::split_on_matches(text, doc.selection(view.id), &regex);
-        doc.set_selection(view.id, selection);
-    });
+    let prompt = ui::regex_prompt(
+        cx,
+        "split:".into(),
+        Some('\\'),
+        move |view, doc, _, regex| {
+            let text = doc.text().slice(..);
+            let selection = selection::split_on_matches(text, doc.selection(view.id), &regex);
+            doc.set_selection(view.id, selection);
+        },
+    );
 
     cx.push_layer(Box::new(prompt));
 }
@@ -1159,11 +1286,15 @@ fn search(cx: &mut Context) {
     // feed chunks into the regex yet
     let contents = doc.text().slice(..).to_string();
 
-    let prompt = ui::regex_prompt(cx, "search:".into(), move |view, doc, registers, regex| {
-        search_impl(doc, view, &contents, &regex, false);
-        // TODO: only store on enter (accept), not update
-        registers.write('/', vec![regex.as_str().to_string()]);
-    });
+    // the prompt stores the query in the search register when it's accepted
+    let prompt = ui::regex_prompt(
+        cx,
+        "search:".into(),
+        Some('/'),
+        move |view, doc, _, regex| {
+            search_impl(doc, view, &contents, &regex, false);
+        },
+    );
 
     cx.push_layer(Box::new(prompt));
 }
@@ -1171,10 +1302,10 @@ fn search(cx: &mut Context) {
 fn search_next_impl(cx: &mut Context, extend: bool) {
     let (view, doc) = current!(cx.editor);
     let registers = &mut cx.editor.registers;
-    if let Some(query) = registers.read('/') {
-        let query = query.first().unwrap();
+    // the history can hold an invalid regex that was entered into a prompt
+    let query = registers.read('/').and_then(|history| history.last());
+    if let Some(Ok(regex)) = query.map(|query| Regex::new(query)) {
         let contents = doc.text().slice(..).to_string();
-        let regex = Regex::new(query).unwrap();
         search_impl(doc, view, &contents, &regex, extend);
     }
 }
@@ -1192,10 +1323,61 @@ fn search_selection(cx: &mut Context) {
     let contents = doc.text().slice(..);
     let query = doc.selection(view.id).primary().fragment(contents);
     let regex = regex::escape(&query);
-    cx.editor.registers.write('/', vec![regex]);
+    cx.editor.registers.get_mut('/').push(regex);
     search_next(cx);
 }
 
+fn select_next_occurrence(cx: &mut Context) {
+    select_next_occurrence_impl(cx, false);
+}
+
+fn skip_occurrence(cx: &mut Context) {
+    select_next_occurrence_impl(cx, true);
+}
+
+/// Adds the next literal occurrence of the primary selection after the last selection and
+/// makes it primary, dropping the primary selection with `skip`. A cursor stands for the word
+/// under it.
+fn select_next_occurrence_impl(cx: &mut Context, skip: bool) {
+    let (view, doc) = current!(cx.editor);
+    let text = doc.text().slice(..);
+    let selection = doc.selection(view.id);
+
+    let mut primary = selection.primary();
+    if primary.to() <= graphemes::next_grapheme_boundary(text, primary.from()) {
+        let word = textobject::textobject_word(text, primary, textobject::TextObject::Inside, 1);
+        if word.from() != word.to() {
+            primary = word;
+        }
+    }
+    let query = primary.fragment(text);
+    let start = selection
+        .iter()
+        .map(Range::to)
+        .chain(Some(primary.to()))
+        .max()
+        .unwrap_or(0);
+
+    let mut ranges: SmallVec<[Range; 1]> = selection
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != selection.primary_index())
+        .map(|(_, &range)| range)
+        .collect();
+    if !skip {
+        ranges.push(primary);
+    }
+
+    match search::find_next_occurrence(text, &query, start, &ranges) {
+        Some(range) => {
+            ranges.push(range);
+            let primary_index = ranges.len() - 1;
+            doc.set_selection(view.id, Selection::new(ranges, primary_index));
+        }
+        None => cx.editor.set_status("No more occurrences".to_string()),
+    }
+}
+
 fn extend_line(cx: &mut Context) {
     let count = cx.count();
     let (view, doc) = current!(cx.editor);
@@ -1341,7 +1523,7 @@ mod cmd {
     use super::*;
     use std::collections::HashMap;
 
-    use helix_view::editor::Action;
+    use helix_view::{editor::Action, session::Session};
     use ui::completers::{self, Completer};
 
     #[derive(Clone)]
@@ -1404,19 +1586,7 @@ mod cmd {
         if doc.path().is_none() {
             bail!("cannot write a buffer without a filename");
         }
-        let fmt = doc.auto_format().map(|fmt| {
-            let shared = fmt.shared();
-            let callback = make_format_callback(
-                doc.id(),
-                doc.version(),
-                Modified::SetUnmodified,
-                shared.clone(),
-            );
-            jobs.callback(callback);
-            shared
-        });
-        let future = doc.format_and_save(fmt);
-        cx.jobs.add(Job::new(future).wait_before_exiting());
+        write_document(jobs, doc);
         Ok(())
     }
 
@@ -1567,6 +1737,15 @@ mod cmd {
         Ok(())
     }
 
+    fn jumplist_clear(
+        cx: &mut compositor::Context,
+        _args: &[&str],
+        _event: PromptEvent,
+    ) -> anyhow::Result<()> {
+        view_mut!(cx.editor).jumps.clear();
+        Ok(())
+    }
+
     fn write_quit(
         cx: &mut compositor::Context,
         args: &[&str],
@@ -1625,7 +1804,7 @@ mod cmd {
 
             // TODO: handle error.
             let handle = doc.save();
-            cx.jobs.add(Job::new(handle).wait_before_exiting());
+            cx.jobs.add(save_job(doc.id(), handle));
         }
 
         if quit {
@@ -1852,6 +2031,31 @@ mod cmd {
         Ok(())
     }
 
+    fn session_save(
+        cx: &mut compositor::Context,
+        args: &[&str],
+        _event: PromptEvent,
+    ) -> anyhow::Result<()> {
+        let name = args.first().context("session name not provided")?;
+        Session::new(cx.editor)?.save(name)?;
+        cx.editor.set_status(format!("Saved session {}", name));
+        Ok(())
+    }
+
+    fn session_load(
+        cx: &mut compositor::Context,
+        args: &[&str],
+        _event: PromptEvent,
+    ) -> anyhow::Result<()> {
+        let name = args.first().context("session name not provided")?;
+        Session::load(name)?.restore(cx.editor)?;
+        // keep warnings about swap files
+        if cx.editor.status_msg.is_none() {
+            cx.editor.set_status(format!("Loaded session {}", name));
+        }
+        Ok(())
+    }
+
     fn show_current_directory(
         cx: &mut compositor::Context,
         _args: &[&str],
@@ -1870,13 +2074,80 @@ mod cmd {
         _event: PromptEvent,
     ) -> anyhow::Result<()> {
         let (_, doc) = current!(cx.editor);
-        if let Some(label) = args.first() {
-            doc.set_encoding(label)
-        } else {
-            let encoding = doc.encoding().name().to_string();
-            cx.editor.set_status(encoding);
-            Ok(())
+        match args.first() {
+            // toggle the byte order mark
+            Some(&"bom") => doc.set_bom(!doc.has_bom()),
+            Some(label) => doc.set_encoding(label),
+            None => {
+                let encoding = doc.encoding().name();
+                let status = if doc.has_bom() {
+                    format!("{} with BOM", encoding)
+                } else {
+                    encoding.to_string()
+                };
+                cx.editor.set_status(status);
+                Ok(())
+            }
+        }
+    }
+
+    /// Turn large file mode off for the current document.
+    fn full_features(
+        cx: &mut compositor::Context,
+        _args: &[&str],
+        _event: PromptEvent,
+    ) -> anyhow::Result<()> {
+        let (_, doc) = current!(cx.editor);
+        if !doc.is_large_file() {
+            cx.editor
+                .set_status("document is not in large file mode".to_string());
+            return Ok(());
         }
+        let id = doc.id();
+        cx.editor.enable_full_features(id);
+        Ok(())
+    }
+
+    /// Override the language of the current document, `text` turns it off.
+    fn set_language(
+        cx: &mut compositor::Context,
+        args: &[&str],
+        _event: PromptEvent,
+    ) -> anyhow::Result<()> {
+        let (_, doc) = current!(cx.editor);
+        let name = match args.first() {
+            Some(name) => *name,
+            None => {
+                let language = doc
+                    .language_config()
+                    .map_or("text", |config| config.language_id())
+                    .to_string();
+                cx.editor.set_status(language);
+                return Ok(());
+            }
+        };
+
+        let language_config = if name == "text" {
+            None
+        } else {
+            let language_config = cx.editor.syn_loader.language_config_for_name(name);
+            Some(language_config.ok_or_else(|| anyhow!("unknown language: {}", name))?)
+        };
+        let id = doc.id();
+        cx.editor.set_doc_language(id, language_config);
+        Ok(())
+    }
+
+    /// Open the health report of all languages, or of the language given, in a scratch buffer.
+    fn health(
+        cx: &mut compositor::Context,
+        args: &[&str],
+        _event: PromptEvent,
+    ) -> anyhow::Result<()> {
+        let report = crate::health::report(&cx.editor.syn_loader, args.first().copied())?;
+        let doc = Document::from(Rope::from(report), None);
+        cx.editor.new_file_from_document(Action::VerticalSplit, doc);
+        Ok(())
     }
 
     /// Reload the [`Document`] from its source file.
@@ -1889,6 +2160,65 @@ mod cmd {
         doc.reload(view.id)
     }
 
+    /// The snapshot in the swap file with unsaved changes found for the current document.
+    fn found_swap_text(cx: &mut compositor::Context) -> anyhow::Result<Rope> {
+        let (_, doc) = current!(cx.editor);
+        let swap = cx
+            .editor
+            .swap_files
+            .found(doc.id())
+            .context("no swap file with unsaved changes was found for this document")?;
+        swap.read()?
+            .context("the swap file doesn't have unsaved changes anymore")
+    }
+
+    fn recover(
+        cx: &mut compositor::Context,
+        _args: &[&str],
+        _event: PromptEvent,
+    ) -> anyhow::Result<()> {
+        let text = found_swap_text(cx)?;
+        let (view, doc) = current!(cx.editor);
+        let transaction = helix_core::diff::compare_ropes(doc.text(), &text);
+        doc.apply(&transaction, view.id);
+        doc.append_changes_to_history(view.id);
+        let doc_id = doc.id();
+        cx.editor.swap_files.discard(doc_id);
+        cx.editor
+            .set_status("Recovered the unsaved changes, :write to keep them".to_string());
+        Ok(())
+    }
+
+    fn recover_diff(
+        cx: &mut compositor::Context,
+        _args: &[&str],
+        _event: PromptEvent,
+    ) -> anyhow::Result<()> {
+        let text = found_swap_text(cx)?;
+        let (_, doc) = current!(cx.editor);
+        let name = doc.relative_path().unwrap_or_default();
+        let diff =
+            helix_core::diff::unified_diff(doc.text(), &text, &name.to_string_lossy(), "swap file");
+        let diff = Document::from(Rope::from(diff), None);
+        cx.editor
+            .new_file_from_document(Action::VerticalSplit, diff);
+        Ok(())
+    }
+
+    fn recover_discard(
+        cx: &mut compositor::Context,
+        _args: &[&str],
+        _event: PromptEvent,
+    ) -> anyhow::Result<()> {
+        let (_, doc) = current!(cx.editor);
+        let doc_id = doc.id();
+        if cx.editor.swap_files.found(doc_id).is_none() {
+            bail!("no swap file with unsaved changes was found for this document");
+        }
+        cx.editor.swap_files.discard(doc_id);
+        Ok(())
+    }
+
     fn tree_sitter_scopes(
         cx: &mut compositor::Context,
         _args: &[&str],
@@ -1903,6 +2233,68 @@ mod cmd {
         Ok(())
     }
 
+    /// Show the syntax tree of the smallest named node covering the primary selection.
+    fn tree_sitter_subtree(
+        cx: &mut compositor::Context,
+        _args: &[&str],
+        _event: PromptEvent,
+    ) -> anyhow::Result<()> {
+        let (view, doc) = current!(cx.editor);
+        let syntax = doc
+            .syntax()
+            .ok_or_else(|| anyhow!("no syntax tree for the current document"))?;
+
+        let text = doc.text();
+        let range = doc.selection(view.id).primary();
+        let from = text.char_to_byte(range.from());
+        let to = text.char_to_byte(range.to());
+        // the node in the innermost injected language that has one
+        let node = syntax
+            .layers_for_byte_range(from, to)
+            .find_map(|layer| {
+                layer
+                    .tree()
+                    .root_node()
+                    .named_descendant_for_byte_range(from, to)
+            })
+            .ok_or_else(|| anyhow!("no syntax node covers the selection"))?;
+
+        let mut tree = String::new();
+        helix_core::syntax::pretty_print_tree(&mut tree, node);
+        let contents = format!("```\n{}\n```", tree);
+        let contents = ui::Markdown::new(contents, cx.editor.syn_loader.clone());
+        let popup = Popup::new(contents);
+        let callback = async move {
+            let call: job::Callback = Box::new(move |_editor, compositor| {
+                compositor.push(Box::new(popup));
+            });
+            Ok(call)
+        };
+        cx.jobs.callback(callback);
+        Ok(())
+    }
+
+    /// Toggle a split showing the whole syntax tree with the node under the cursor selected.
+    fn tree_sitter_playground(
+        cx: &mut compositor::Context,
+        _args: &[&str],
+        _event: PromptEvent,
+    ) -> anyhow::Result<()> {
+        let callback = async move {
+            let call: job::Callback = Box::new(move |editor, compositor| {
+                if let Some(editor_view) = compositor
+                    .find(std::any::type_name::<ui::EditorView>())
+                    .and_then(|component| component.as_any_mut().downcast_mut::<ui::EditorView>())
+                {
+                    editor_view.toggle_playground(editor);
+                }
+            });
+            Ok(call)
+        };
+        cx.jobs.callback(callback);
+        Ok(())
+    }
+
     fn vsplit(
         cx: &mut compositor::Context,
         args: &[&str],
@@ -2008,6 +2400,13 @@ mod cmd {
             fun: later,
             completer: None,
         },
+        TypableCommand {
+            name: "jumplist-clear",
+            alias: None,
+            doc: "Clear the jumplist of the current view.",
+            fun: jumplist_clear,
+            completer: None,
+        },
         TypableCommand {
             name: "write-quit",
             alias: Some("wq"),
@@ -2148,6 +2547,20 @@ mod cmd {
             fun: change_current_directory,
             completer: Some(completers::directory),
         },
+        TypableCommand {
+            name: "session-save",
+            alias: None,
+            doc: "Save the layout of the views, the open files and the working directory as a session (:session-save <name>).",
+            fun: session_save,
+            completer: Some(completers::session),
+        },
+        TypableCommand {
+            name: "session-load",
+            alias: None,
+            doc: "Replace the views with the ones of a saved session (:session-load <name>).",
+            fun: session_load,
+            completer: Some(completers::session),
+        },
         TypableCommand {
             name: "show-directory",
             alias: Some("pwd"),
@@ -2158,7 +2571,7 @@ mod cmd {
         TypableCommand {
             name: "encoding",
             alias: None,
-            doc: "Set encoding based on `https://encoding.spec.whatwg.org`",
+            doc: "Set encoding based on `https://encoding.spec.whatwg.org`. `:encoding bom` toggles the byte order mark.",
             fun: set_encoding,
             completer: None,
         },
@@ -2169,6 +2582,48 @@ mod cmd {
             fun: reload,
             completer: None,
         },
+        TypableCommand {
+            name: "recover",
+            alias: None,
+            doc: "Apply the unsaved changes found in a swap file of the current document.",
+            fun: recover,
+            completer: None,
+        },
+        TypableCommand {
+            name: "recover-diff",
+            alias: None,
+            doc: "Show the unsaved changes found in a swap file of the current document as a diff.",
+            fun: recover_diff,
+            completer: None,
+        },
+        TypableCommand {
+            name: "recover-discard",
+            alias: None,
+            doc: "Delete the swap file with unsaved changes found for the current document.",
+            fun: recover_discard,
+            completer: None,
+        },
+        TypableCommand {
+            name: "full-features",
+            alias: None,
+            doc: "Turn large file mode off, enabling syntax highlighting and the language server.",
+            fun: full_features,
+            completer: None,
+        },
+        TypableCommand {
+            name: "set-language",
+            alias: Some("lang"),
+            doc: "Set the language of the current document, overriding detection. `:set-language text` turns syntax highlighting off.",
+            fun: set_language,
+            completer: None,
+        },
+        TypableCommand {
+            name: "health",
+            alias: None,
+            doc: "Check the health of the grammars, queries and language servers of all languages, or of the language given.",
+            fun: health,
+            completer: None,
+        },
         TypableCommand {
             name: "tree-sitter-scopes",
             alias: None,
@@ -2176,6 +2631,20 @@ mod cmd {
             fun: tree_sitter_scopes,
             completer: None,
         },
+        TypableCommand {
+            name: "tree-sitter-subtree",
+            alias: Some("ts-subtree"),
+            doc: "Display the syntax tree of the smallest named node covering the primary selection, primarily for debugging queries.",
+            fun: tree_sitter_subtree,
+            completer: None,
+        },
+        TypableCommand {
+            name: "tree-sitter-playground",
+            alias: None,
+            doc: "Toggle a split showing the syntax tree of the focused document with the node under the cursor selected.",
+            fun: tree_sitter_playground,
+            completer: None,
+        },
         TypableCommand {
             name: "vsplit",
             alias: Some("vs"),
@@ -2345,7 +2814,23 @@ fn symbol_picker(cx: &mut Context) {
 
     let language_server = match doc.language_server() {
         Some(language_server) => language_server,
-        None => return,
+        None => {
+            // fall back to the definitions found by the tags query of the language
+            match doc.definitions() {
+                Some(definitions) => {
+                    let path = doc.path().cloned();
+                    let tags = definitions
+                        .into_iter()
+                        .map(|tag| (path.clone(), tag))
+                        .collect();
+                    cx.push_layer(Box::new(tags_picker(tags, false)));
+                }
+                None => cx
+                    .editor
+                    .set_error("No language server or tags query for this document".to_string()),
+            }
+            return;
+        }
     };
     let offset_encoding = language_server.offset_encoding();
 
@@ -2541,6 +3026,50 @@ fn append_to_line(cx: &mut Context) {
     doc.set_selection(view.id, selection);
 }
 
+/// Formats the document if auto-format is on and writes it to its path, which it must have.
+fn write_document(jobs: &mut Jobs, doc: &mut Document) {
+    let fmt = doc.auto_format().map(|fmt| {
+        let shared = fmt.shared();
+        let callback = make_format_callback(
+            doc.id(),
+            doc.version(),
+            Modified::SetUnmodified,
+            shared.clone(),
+        );
+        jobs.callback(callback);
+        shared
+    });
+    let future = doc.format_and_save(fmt);
+    jobs.add(save_job(doc.id(), future));
+}
+
+/// Waits for a document to be saved and then reindexes its definitions.
+fn save_job(
+    doc_id: DocumentId,
+    save: impl Future<Output = anyhow::Result<()>> + Send + 'static,
+) -> Job {
+    Job::with_callback(async move {
+        save.await?;
+        let call: job::Callback = Box::new(move |editor, _compositor| {
+            editor.refresh_symbol_index(doc_id);
+        });
+        Ok(call)
+    })
+    .wait_before_exiting()
+}
+
+/// Writes the documents of `doc_ids` like `:write` does, skipping scratch buffers and
+/// documents without changes.
+pub fn auto_save(editor: &mut Editor, jobs: &mut Jobs, doc_ids: &[DocumentId]) {
+    for &doc_id in doc_ids {
+        if let Some(doc) = editor.document_mut(doc_id) {
+            if doc.path().is_some() && doc.is_modified() {
+                write_document(jobs, doc);
+            }
+        }
+    }
+}
+
 /// Sometimes when applying formatting changes we want to mark the buffer as unmodified, for
 /// example because we just applied the same changes while saving.
 enum Modified {
@@ -2561,11 +3090,19 @@ async fn make_format_callback(
 ) -> anyhow::Result<job::Callback> {
     let format = format.await;
     let call: job::Callback = Box::new(move |editor: &mut Editor, _compositor: &mut Compositor| {
-        let view_id = view!(editor).id;
+        let focus = view!(editor).id;
         if let Some(doc) = editor.document_mut(doc_id) {
+            // an auto-saved document doesn't have to be shown in the focused view, or in any
+            let view_id = if doc.selections().contains_key(&focus) {
+                Some(focus)
+            } else {
+                doc.selections().keys().next().copied()
+            };
             if doc.version() == doc_version {
-                doc.apply(&Transaction::from(format), view_id);
-                doc.append_changes_to_history(view_id);
+                doc.apply(&Transaction::from(format), view_id.unwrap_or(focus));
+                if let Some(view_id) = view_id {
+                    doc.append_changes_to_history(view_id);
+                }
                 if let Modified::SetUnmodified = modified {
                     doc.reset_modified();
                 }
@@ -2684,7 +3221,7 @@ fn normal_mode(cx: &mut Context) {
 // Store a jump on the jumplist.
 fn push_jump(editor: &mut Editor) {
     let (view, doc) = current!(editor);
-    let jump = (doc.id(), doc.selection(view.id).clone());
+    let jump = Jump::new(doc.id(), doc.selection(view.id).clone());
     view.jumps.push(jump);
 }
 
@@ -2749,6 +3286,119 @@ fn select_mode(cx: &mut Context) {
     doc_mut!(cx.editor).mode = Mode::Select;
 }
 
+fn workspace_symbol_picker(cx: &mut Context) {
+    cx.editor.ensure_symbol_index();
+    let symbol_index = cx.editor.symbol_index();
+    let tags: Vec<_> = symbol_index
+        .definitions()
+        .into_iter()
+        .map(|(path, tag)| (Some(path), tag))
+        .collect();
+    if symbol_index.is_indexing() {
+        cx.editor
+            .set_status("Still indexing the workspace, some definitions are missing".to_string());
+    }
+    cx.push_layer(Box::new(tags_picker(tags, true)));
+}
+
+/// Picks one of the definitions found by tags queries. Definitions without a path are in the
+/// current document.
+fn tags_picker(
+    tags: Vec<(Option<PathBuf>, Tag)>,
+    show_path: bool,
+) -> FilePicker<(Option<PathBuf>, Tag)> {
+    let cwdir = std::env::current_dir().unwrap_or_default();
+    FilePicker::new(
+        tags,
+        move |(path, tag)| match path {
+            Some(path) if show_path => {
+                let path = path.strip_prefix(&cwdir).unwrap_or(path);
+                format!("{} {}:{}", tag.name, path.display(), tag.position.0 + 1).into()
+            }
+            _ => tag.name.as_str().into(),
+        },
+        |editor: &mut Editor, (path, tag), action| {
+            push_jump(editor);
+            jump_to_tag(editor, path.as_deref(), tag, action);
+        },
+        |editor, (path, tag)| {
+            let path = path.clone().or_else(|| {
+                let view = editor.tree.get(editor.tree.focus);
+                editor.documents[view.doc].path().cloned()
+            })?;
+            Some((path, Some(tag.lines)))
+        },
+    )
+}
+
+fn jump_to_tag(editor: &mut Editor, path: Option<&Path>, tag: &Tag, action: Action) {
+    if let Some(path) = path {
+        if let Err(err) = editor.open(path.to_path_buf(), action) {
+            editor.set_error(format!("Failed to open {}: {}", path.display(), err));
+            return;
+        }
+    }
+    let (view, doc) = current!(editor);
+    if let Some(pos) = tag.char_index(doc.text().slice(..)) {
+        doc.set_selection(view.id, Selection::point(pos));
+        align_view(doc, view, Align::Center);
+    }
+}
+
+/// Goto definition without a language server: the definitions named like the syntax node
+/// under the cursor, in the document and the rest of the workspace.
+fn goto_tag_definition(cx: &mut Context) {
+    let (view, doc) = current!(cx.editor);
+    let text = doc.text().slice(..);
+    let pos = text.char_to_byte(doc.selection(view.id).primary().cursor(text));
+    let node = doc.syntax().and_then(|syntax| {
+        syntax
+            .tree()
+            .root_node()
+            .descendant_for_byte_range(pos, pos)
+    });
+    let name = match node {
+        Some(node) if node.child_count() == 0 => text
+            .slice(text.byte_to_char(node.start_byte())..text.byte_to_char(node.end_byte()))
+            .to_string(),
+        _ => {
+            cx.editor.set_error("No definition found.".to_string());
+            return;
+        }
+    };
+
+    // the definitions in the document itself are up to date even if it isn't saved
+    let path = doc.path().cloned();
+    let mut definitions: Vec<_> = doc
+        .definitions()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|tag| tag.name == name)
+        .map(|tag| (path.clone(), tag))
+        .collect();
+    cx.editor.ensure_symbol_index();
+    let symbol_index = cx.editor.symbol_index();
+    definitions.extend(
+        symbol_index
+            .find(&name)
+            .into_iter()
+            .filter(|(other, _)| Some(other) != path.as_ref())
+            .map(|(path, tag)| (Some(path), tag)),
+    );
+
+    match definitions.as_slice() {
+        [] if symbol_index.is_indexing() => cx.editor.set_error(
+            "No definition found yet, the workspace is still being indexed.".to_string(),
+        ),
+        [] => cx.editor.set_error("No definition found.".to_string()),
+        [(path, tag)] => {
+            push_jump(cx.editor);
+            jump_to_tag(cx.editor, path.as_deref(), tag, Action::Replace);
+        }
+        _ => cx.push_layer(Box::new(tags_picker(definitions, true))),
+    }
+}
+
 fn exit_select_mode(cx: &mut Context) {
     let doc = doc_mut!(cx.editor);
     if doc.mode == Mode::Select {
@@ -2841,7 +3491,10 @@ fn goto_definition(cx: &mut Context) {
     let (view, doc) = current!(cx.editor);
     let language_server = match doc.language_server() {
         Some(language_server) => language_server,
-        None => return,
+        None => {
+            goto_tag_definition(cx);
+            return;
+        }
     };
 
     let offset_encoding = language_server.offset_encoding();
@@ -3233,6 +3886,37 @@ pub mod insert {
         }
     }
 
+    /// Overwrites the byte under each cursor of a binary document with a hex digit. The high
+    /// nibble is typed first and the cursors move on to the next byte after the low nibble.
+    /// A cursor at the end of the document appends a new byte.
+    pub fn overwrite_hex_digit(cx: &mut Context, digit: u8, low_nibble: bool) {
+        let (view, doc) = current!(cx.editor);
+        let selection = doc.selection(view.id).clone().cursors(doc.text().slice(..));
+
+        let text = doc.text().slice(..);
+        let transaction = Transaction::change_by_selection(doc.text(), &selection, |range| {
+            let pos = range.cursor(text);
+            let (end, old) = match text.get_char(pos) {
+                Some(ch) => (pos + 1, binary_char_to_byte(ch).unwrap_or(0)),
+                None => (pos, 0),
+            };
+            let byte = if low_nibble {
+                (old & 0xF0) | digit
+            } else {
+                (digit << 4) | (old & 0x0F)
+            };
+            (pos, end, Some(binary_byte_to_char(byte).to_string().into()))
+        });
+        doc.apply(&transaction, view.id);
+
+        let text = doc.text().slice(..);
+        let selection = selection.transform(|range| {
+            let pos = range.cursor(text) + low_nibble as usize;
+            range.put_cursor(text, pos, false)
+        });
+        doc.set_selection(view.id, selection);
+    }
+
     pub fn insert_tab(cx: &mut Context) {
         let (view, doc) = current!(cx.editor);
         // TODO: round out to nearest indentation level (for example a line with 3 spaces should
@@ -3378,6 +4062,30 @@ fn redo(cx: &mut Context) {
     doc.redo(view_id);
 }
 
+fn undo_selection(cx: &mut Context) {
+    let count = cx.count();
+    let (view, doc) = current!(cx.editor);
+    for _ in 0..count {
+        if !doc.undo_selection(view.id) {
+            cx.editor
+                .set_status("Already at oldest selection".to_string());
+            break;
+        }
+    }
+}
+
+fn redo_selection(cx: &mut Context) {
+    let count = cx.count();
+    let (view, doc) = current!(cx.editor);
+    for _ in 0..count {
+        if !doc.redo_selection(view.id) {
+            cx.editor
+                .set_status("Already at newest selection".to_string());
+            break;
+        }
+    }
+}
+
 // Yank / Paste
 
 fn yank(cx: &mut Context) {
@@ -3817,13 +4525,18 @@ fn join_selections(cx: &mut Context) {
 
 fn keep_selections(cx: &mut Context) {
     // keep selections matching regex
-    let prompt = ui::regex_prompt(cx, "keep:".into(), move |view, doc, _, regex| {
-        let text = doc.text().slice(..);
+    let prompt = ui::regex_prompt(
+        cx,
+        "keep:".into(),
+        Some('\\'),
+        move |view, doc, _, regex| {
+            let text = doc.text().slice(..);
 
-        if let Some(selection) = selection::keep_matches(text, doc.selection(view.id), &regex) {
-            doc.set_selection(view.id, selection);
-        }
-    });
+            if let Some(selection) = selection::keep_matches(text, doc.selection(view.id), &regex) {
+                doc.set_selection(view.id, selection);
+            }
+        },
+    );
 
     cx.push_layer(Box::new(prompt));
 }
@@ -3980,10 +4693,22 @@ fn hover(cx: &mut Context) {
 // comments
 fn toggle_comments(cx: &mut Context) {
     let (view, doc) = current!(cx.editor);
-    let token = doc
-        .language_config()
-        .and_then(|lc| lc.comment_token.as_ref())
-        .map(|tc| tc.as_ref());
+    // comment with the token of the language injected at the cursor, if there is one
+    let cursor = doc
+        .selection(view.id)
+        .primary()
+        .cursor(doc.text().slice(..));
+    let byte = doc.text().char_to_byte(cursor);
+    let injected_token = doc.syntax().and_then(|syntax| {
+        syntax
+            .layers_for_byte_range(byte, byte)
+            .find_map(|layer| layer.language_config()?.comment_token.as_deref())
+    });
+    let token = injected_token.or_else(|| {
+        doc.language_config()
+            .and_then(|lc| lc.comment_token.as_ref())
+            .map(|tc| tc.as_ref())
+    });
     let transaction = comment::toggle_line_comments(doc.text(), doc.selection(view.id), token);
 
     doc.apply(&transaction, view.id);
@@ -4086,13 +4811,9 @@ fn jump_forward(cx: &mut Context) {
     let count = cx.count();
     let (view, _doc) = current!(cx.editor);
 
-    if let Some((id, selection)) = view.jumps.forward(count) {
-        view.doc = *id;
-        let selection = selection.clone();
-        let (view, doc) = current!(cx.editor); // refetch doc
-        doc.set_selection(view.id, selection);
-
-        align_view(doc, view, Align::Center);
+    if let Some(jump) = view.jumps.forward(count) {
+        let jump = jump.clone();
+        go_to_jump(cx.editor, &jump);
     };
 }
 
@@ -4100,18 +4821,118 @@ fn jump_backward(cx: &mut Context) {
     let count = cx.count();
     let (view, doc) = current!(cx.editor);
 
-    if let Some((id, selection)) = view.jumps.backward(view.id, doc, count) {
-        // manually set the alternate_file as we cannot use the Editor::switch function here.
-        if view.doc != *id {
-            view.last_accessed_doc = Some(view.doc)
-        }
-        view.doc = *id;
-        let selection = selection.clone();
-        let (view, doc) = current!(cx.editor); // refetch doc
-        doc.set_selection(view.id, selection);
+    if let Some(jump) = view.jumps.backward(view.id, doc, count) {
+        let jump = jump.clone();
+        go_to_jump(cx.editor, &jump);
+    };
+}
 
-        align_view(doc, view, Align::Center);
+/// Shows `jump` in the current view without pushing a jump.
+fn go_to_jump(editor: &mut Editor, jump: &Jump) {
+    let id = match jump_document(editor, jump) {
+        Some(id) => id,
+        None => return,
     };
+    let view = view_mut!(editor);
+    // manually set the alternate_file as we cannot use the Editor::switch function here.
+    if view.doc != id {
+        view.last_accessed_doc = Some(view.doc)
+    }
+    view.doc = id;
+    select_jump(editor, jump);
+}
+
+/// The document of `jump`, opened again if it was closed.
+fn jump_document(editor: &mut Editor, jump: &Jump) -> Option<DocumentId> {
+    if editor.document(jump.doc_id).is_some() {
+        return Some(jump.doc_id);
+    }
+    let path = jump.path.as_ref()?;
+    match editor.open_document(path) {
+        Ok(id) => Some(id),
+        Err(err) => {
+            editor.set_error(format!("Failed to open {}: {}", path.display(), err));
+            None
+        }
+    }
+}
+
+/// Selects the selection of `jump` in the current view, which shows its document.
+fn select_jump(editor: &mut Editor, jump: &Jump) {
+    let (view, doc) = current!(editor);
+    // a reopened document can be shorter than it was
+    let len = doc.text().len_chars();
+    let selection = jump
+        .selection
+        .clone()
+        .transform(|range| Range::new(range.anchor.min(len), range.head.min(len)));
+    doc.set_selection(view.id, selection);
+
+    align_view(doc, view, Align::Center);
+}
+
+fn save_selection(cx: &mut Context) {
+    push_jump(cx.editor);
+    cx.editor
+        .set_status("Selection saved to jumplist".to_string());
+}
+
+fn jumplist_picker(cx: &mut Context) {
+    struct JumpItem {
+        jump: Jump,
+        path: Option<PathBuf>,
+        line: usize,
+        content: String,
+    }
+
+    let (view, _) = current!(cx.editor);
+    let jumps: Vec<_> = view.jumps.iter().rev().cloned().collect();
+    let items = jumps
+        .into_iter()
+        .filter_map(|jump| {
+            let (path, text) = match cx.editor.document(jump.doc_id) {
+                Some(doc) => (doc.path().cloned(), doc.text().clone()),
+                None => {
+                    let path = jump.path.clone()?;
+                    let text = std::fs::read_to_string(&path).ok()?;
+                    (Some(path), Rope::from(text))
+                }
+            };
+            let text = text.slice(..);
+            let cursor = jump.selection.primary().cursor(text).min(text.len_chars());
+            let line = text.char_to_line(cursor);
+            let content = text.line(line).to_string().trim().to_string();
+            Some(JumpItem {
+                jump,
+                path,
+                line,
+                content,
+            })
+        })
+        .collect();
+
+    let picker = FilePicker::new(
+        items,
+        |item: &JumpItem| {
+            let path = item
+                .path
+                .as_deref()
+                .map(helix_core::path::get_relative_path);
+            let path = match path.as_ref().and_then(|path| path.to_str()) {
+                Some(path) => path.to_owned(),
+                None => "[scratch buffer]".to_owned(),
+            };
+            format!("{}:{} {}", path, item.line + 1, item.content).into()
+        },
+        |editor: &mut Editor, item: &JumpItem, action| {
+            if let Some(id) = jump_document(editor, &item.jump) {
+                editor.switch(id, action);
+                select_jump(editor, &item.jump);
+            }
+        },
+        |_editor, item| Some((item.path.clone()?, Some((item.line, item.line)))),
+    );
+    cx.push_layer(Box::new(picker));
 }
 
 fn rotate_view(cx: &mut Context) {
@@ -4189,6 +5010,95 @@ fn scroll_down(cx: &mut Context) {
     scroll(cx, cx.count(), Direction::Forward);
 }
 
+fn fold(cx: &mut Context) {
+    let (view, doc) = current!(cx.editor);
+    let syntax = match doc.syntax() {
+        Some(syntax) => syntax,
+        None => {
+            cx.editor
+                .set_error("No syntax tree to fold the document by".to_string());
+            return;
+        }
+    };
+    let text = doc.text().slice(..);
+    let cursor = doc.selection(view.id).primary().cursor(text);
+    let folds = doc.folds(view.id).cloned().unwrap_or_default();
+
+    match fold::foldable_range(syntax, text, cursor, &folds) {
+        Some(range) => {
+            doc.folds_mut(view.id).fold(range);
+            reveal_cursors(doc, view.id);
+        }
+        None => cx.editor.set_status("Nothing to fold".to_string()),
+    }
+}
+
+fn unfold(cx: &mut Context) {
+    let (view, doc) = current!(cx.editor);
+    let text = doc.text().slice(..);
+    let line = doc.selection(view.id).primary().cursor_line(text);
+
+    let fold = doc
+        .folds(view.id)
+        .and_then(|folds| folds.fold_at_line(text, line))
+        .cloned();
+    if let Some(fold) = fold {
+        doc.folds_mut(view.id).unfold(&fold);
+    }
+}
+
+fn toggle_fold(cx: &mut Context) {
+    let (view, doc) = current!(cx.editor);
+    let text = doc.text().slice(..);
+    let line = doc.selection(view.id).primary().cursor_line(text);
+
+    if matches!(doc.folds(view.id), Some(folds) if folds.fold_at_line(text, line).is_some()) {
+        unfold(cx);
+    } else {
+        fold(cx);
+    }
+}
+
+fn fold_all(cx: &mut Context) {
+    let level = cx.count();
+    let (view, doc) = current!(cx.editor);
+    let ranges = match doc.syntax() {
+        Some(syntax) => fold::foldable_ranges_at_level(syntax, doc.text().slice(..), level),
+        None => {
+            cx.editor
+                .set_error("No syntax tree to fold the document by".to_string());
+            return;
+        }
+    };
+
+    let folds = doc.folds_mut(view.id);
+    for range in ranges {
+        folds.fold(range);
+    }
+    reveal_cursors(doc, view.id);
+}
+
+fn unfold_all(cx: &mut Context) {
+    let (view, doc) = current!(cx.editor);
+    doc.folds_mut(view.id).clear();
+}
+
+/// Moves the cursors that were folded away to the line they're folded into, as setting a
+/// selection with hidden cursors would unfold them again.
+fn reveal_cursors(doc: &mut Document, view_id: ViewId) {
+    let text = doc.text().slice(..);
+    let hidden = doc.hidden_lines(view_id);
+    let selection = doc.selection(view_id).clone().transform(|range| {
+        let line = range.cursor_line(text);
+        if hidden.is_hidden(line) {
+            Range::point(text.line_to_char(hidden.from_visual(hidden.to_visual(line))))
+        } else {
+            range
+        }
+    });
+    doc.set_selection(view_id, selection);
+}
+
 fn select_textobject_around(cx: &mut Context) {
     select_textobject(cx, textobject::TextObject::Around);
 }
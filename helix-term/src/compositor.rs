@@ -129,7 +129,7 @@ impl Compositor {
         // propagate events through the layers until we either find a layer that consumes it or we
         // run out of layers (event bubbling)
         for layer in self.layers.iter_mut().rev() {
-            match layer.handle_event(event, cx) {
+            match layer.handle_event(event.clone(), cx) {
                 EventResult::Consumed(Some(callback)) => {
                     callback(self);
                     return true;
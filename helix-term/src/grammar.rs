@@ -0,0 +1,102 @@
+//! `hx --grammar`: building the grammars configured in `languages.toml` into the runtime
+//! directory, so that adding a grammar doesn't require rebuilding helix.
+
+use anyhow::{anyhow, Result};
+use helix_core::syntax::{get_language, Configuration, GrammarSource};
+use helix_syntax::grammar;
+use std::path::{Path, PathBuf};
+
+fn grammar_dir() -> PathBuf {
+    helix_core::runtime_dir().join("grammars")
+}
+
+/// Fetches and compiles the grammar of every language with a `grammar` source.
+pub fn build(config: &Configuration) -> Result<()> {
+    let out_dir = grammar_dir();
+    let mut failed = 0;
+
+    for language in &config.language {
+        let source = match &language.grammar {
+            Some(source) => source,
+            None => continue,
+        };
+        let name = language.language_id().to_ascii_lowercase();
+
+        match build_grammar(&name, source, &out_dir) {
+            Ok(true) => println!("{}: built", name),
+            Ok(false) => println!("{}: up to date", name),
+            Err(err) => {
+                failed += 1;
+                eprintln!("{}: {:#}", name, err);
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow!("{} grammar(s) failed to build", failed));
+    }
+    Ok(())
+}
+
+fn build_grammar(name: &str, source: &GrammarSource, out_dir: &Path) -> Result<bool> {
+    let dir = match source {
+        // relative paths are relative to the user's `languages.toml`
+        GrammarSource::Local { path } => {
+            helix_core::config_dir().join(helix_core::path::expand_tilde(path))
+        }
+        GrammarSource::Git { git, rev, subpath } => {
+            let repository = out_dir.join("sources").join(name);
+            grammar::fetch_grammar(git, rev, &repository)?;
+            match subpath {
+                Some(subpath) => repository.join(subpath),
+                None => repository,
+            }
+        }
+    };
+    let src_dir = if dir.join("src").is_dir() {
+        dir.join("src")
+    } else {
+        dir
+    };
+
+    let built = grammar::build_grammar(&src_dir, name, out_dir, helix_syntax::BUILD_TARGET)?;
+    // catch grammars that don't export the language or were generated for another ABI, and
+    // don't leave them installed
+    if let Err(err) = get_language(&helix_core::runtime_dir(), name) {
+        let _ = std::fs::remove_file(grammar::library_path(out_dir, name));
+        return Err(err);
+    }
+    Ok(built)
+}
+
+/// Lists the compiled grammars with their ABI versions, and the configured grammars that
+/// haven't been built yet.
+pub fn list(config: &Configuration) -> Result<()> {
+    let dir = grammar_dir();
+    let mut installed: Vec<String> = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension() == Some(grammar::DYLIB_EXTENSION.as_ref()))
+            .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    installed.sort();
+
+    println!("Grammars in {}:", dir.display());
+    for name in &installed {
+        match get_language(&helix_core::runtime_dir(), name) {
+            Ok(language) => println!("  {} (ABI version {})", name, language.version()),
+            Err(err) => println!("  {}: {:#}", name, err),
+        }
+    }
+
+    for language in &config.language {
+        let name = language.language_id().to_ascii_lowercase();
+        if language.grammar.is_some() && !installed.contains(&name) {
+            println!("  {}: not built, run `hx --grammar build`", name);
+        }
+    }
+    Ok(())
+}
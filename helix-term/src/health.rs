@@ -0,0 +1,140 @@
+//! `hx --health` and `:health`: reports what helix resolved its runtime and config from and,
+//! per language, which of the grammar, queries and language server are usable.
+
+use anyhow::{anyhow, Result};
+use helix_core::syntax::{self, IndentQuery, LanguageConfiguration, Loader};
+use helix_core::tree_sitter::{Language, Query};
+use helix_syntax::grammar;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Builds the health report, of all languages or only of the one named `language`.
+pub fn report(loader: &Loader, language: Option<&str>) -> Result<String> {
+    let mut report = general();
+
+    match language {
+        Some(name) => {
+            let language = loader
+                .language_config_for_name(name)
+                .ok_or_else(|| anyhow!("unknown language: {}", name))?;
+            report.push('\n');
+            report.push_str(&language_report(&language));
+        }
+        None => {
+            for language in loader.language_configs_iter() {
+                report.push('\n');
+                report.push_str(&language_report(language));
+            }
+        }
+    }
+    Ok(report)
+}
+
+fn general() -> String {
+    let config_dir = helix_core::config_dir();
+    let file_status = |path: &Path| {
+        if path.exists() {
+            format!("{}", path.display())
+        } else {
+            format!("{} (not found, using defaults)", path.display())
+        }
+    };
+
+    let mut report = String::new();
+    let _ = writeln!(
+        report,
+        "Config file: {}",
+        file_status(&config_dir.join("config.toml"))
+    );
+    let _ = writeln!(
+        report,
+        "Language file: {}",
+        file_status(&config_dir.join("languages.toml"))
+    );
+    let _ = writeln!(
+        report,
+        "Log file: {}",
+        helix_core::cache_dir().join("helix.log").display()
+    );
+
+    let runtime_dir = helix_core::runtime_dir();
+    if runtime_dir.is_dir() {
+        let _ = writeln!(report, "Runtime directory: {}", runtime_dir.display());
+    } else {
+        let _ = writeln!(
+            report,
+            "Runtime directory: {} (not found, set HELIX_RUNTIME)",
+            runtime_dir.display()
+        );
+    }
+
+    let _ = writeln!(
+        report,
+        "Clipboard provider: {}",
+        helix_view::clipboard::get_clipboard_provider().name()
+    );
+    report
+}
+
+fn language_report(config: &LanguageConfiguration) -> String {
+    let name = config.language_id().to_ascii_lowercase();
+    let mut report = format!("{}\n", config.language_id());
+
+    let runtime_dir = helix_core::runtime_dir();
+    let library_path = grammar::library_path(&runtime_dir.join("grammars"), &name);
+    let language = syntax::get_language(&runtime_dir, &name);
+    let _ = match &language {
+        Ok(language) => writeln!(report, "  Grammar: ok (ABI version {})", language.version()),
+        Err(_) if !library_path.exists() => {
+            writeln!(report, "  Grammar: {} not found", library_path.display())
+        }
+        Err(err) => writeln!(report, "  Grammar: {:#}", err),
+    };
+
+    for (kind, filename) in &[
+        ("Highlights", "highlights.scm"),
+        ("Injections", "injections.scm"),
+        ("Locals", "locals.scm"),
+    ] {
+        let status = query_status(language.as_ref().ok(), &name, filename);
+        let _ = writeln!(report, "  {} query: {}", kind, status);
+    }
+    let _ = writeln!(report, "  Indents: {}", indents_status(&name));
+
+    let _ = match &config.language_server {
+        Some(server) => match which::which(&server.command) {
+            Ok(path) => writeln!(report, "  Language server: {}", path.display()),
+            Err(_) => writeln!(
+                report,
+                "  Language server: {} (not found in $PATH)",
+                server.command
+            ),
+        },
+        None => writeln!(report, "  Language server: none configured"),
+    };
+    report
+}
+
+fn query_status(language: Option<&Language>, name: &str, filename: &str) -> String {
+    let query = syntax::read_query(name, filename);
+    if query.is_empty() {
+        return "not found".to_string();
+    }
+    match language {
+        Some(&language) => match Query::new(language, &query) {
+            Ok(_) => "ok".to_string(),
+            Err(err) => format!("error at row {}: {}", err.row + 1, err.message),
+        },
+        None => "found, but can't be compiled without a grammar".to_string(),
+    }
+}
+
+fn indents_status(name: &str) -> String {
+    match syntax::load_runtime_file(name, "indents.toml") {
+        Ok(indents) => match toml::from_str::<IndentQuery>(&indents) {
+            Ok(_) => "ok".to_string(),
+            Err(err) => format!("error: {}", err),
+        },
+        Err(_) => "not found".to_string(),
+    }
+}
@@ -426,9 +426,13 @@ impl Default for Keymaps {
             "N" => extend_search_next,
             // N for search_prev
             "*" => search_selection,
+            "C-n" => select_next_occurrence,
+            "A-n" => skip_occurrence,
 
             "u" => undo,
             "U" => redo,
+            "A-u" => undo_selection,
+            "A-U" => redo_selection,
 
             "y" => yank,
             // yank_all
@@ -479,12 +483,14 @@ impl Default for Keymaps {
 
             "tab" => jump_forward, // tab == <C-i>
             "C-o" => jump_backward,
-            // "C-s" => save_selection,
+            "C-s" => save_selection,
 
             "space" => { "Space"
                 "f" => file_picker,
                 "b" => buffer_picker,
                 "s" => symbol_picker,
+                "S" => workspace_symbol_picker,
+                "j" => jumplist_picker,
                 "a" => code_action,
                 "'" => last_picker,
                 "w" => { "Window"
@@ -507,6 +513,11 @@ impl Default for Keymaps {
                 "m" => align_view_middle,
                 "k" => scroll_up,
                 "j" => scroll_down,
+                "f" => fold,
+                "o" => unfold,
+                "a" => toggle_fold,
+                "M" => fold_all,
+                "R" => unfold_all,
             },
 
             "\"" => select_register,
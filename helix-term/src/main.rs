@@ -1,6 +1,6 @@
 use anyhow::{Context, Error, Result};
 use helix_term::application::Application;
-use helix_term::args::Args;
+use helix_term::args::{Args, GrammarAction};
 use helix_term::config::Config;
 use helix_term::keymap::merge_keys;
 use std::path::PathBuf;
@@ -58,6 +58,12 @@ FLAGS:
     -v               Increases logging verbosity each use for up to 3 times
                      (default file: {})
     -V, --version    Prints version information
+    --health [language]
+                     Checks for potential errors in the configuration, the grammars,
+                     queries and language servers of all languages or one language
+    --grammar build  Fetches and compiles the grammars configured in languages.toml
+    --grammar list   Lists the installed grammars
+    --session <name> Starts with the views of a session saved with :session-save
 ",
         env!("CARGO_PKG_NAME"),
         env!("CARGO_PKG_VERSION"),
@@ -79,6 +85,27 @@ FLAGS:
         std::process::exit(0);
     }
 
+    if args.health {
+        let config =
+            helix_core::config::user_syntax_config().context("could not parse languages.toml")?;
+        let loader = helix_core::syntax::Loader::new(config);
+        print!(
+            "{}",
+            helix_term::health::report(&loader, args.health_language.as_deref())?
+        );
+        std::process::exit(0);
+    }
+
+    if let Some(action) = args.grammar {
+        let config =
+            helix_core::config::user_syntax_config().context("could not parse languages.toml")?;
+        match action {
+            GrammarAction::Build => helix_term::grammar::build(&config)?,
+            GrammarAction::List => helix_term::grammar::list(&config)?,
+        }
+        std::process::exit(0);
+    }
+
     let conf_dir = helix_core::config_dir();
     if !conf_dir.exists() {
         std::fs::create_dir_all(&conf_dir).ok();
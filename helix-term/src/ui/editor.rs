@@ -1,28 +1,33 @@
 use crate::{
     commands,
     compositor::{Component, Context, EventResult},
+    job::{self, Jobs},
     key,
     keymap::{KeymapResult, Keymaps},
-    ui::{Completion, ProgressSpinners},
+    ui::{Completion, Playground, ProgressSpinners},
 };
 
 use helix_core::{
-    coords_at_pos,
+    chars::char_is_line_ending,
+    fold::HiddenLines,
     graphemes::{ensure_grapheme_boundary_next, next_grapheme_boundary, prev_grapheme_boundary},
+    line_ending::line_end_char_index,
+    match_brackets,
     movement::Direction,
     syntax::{self, HighlightEvent},
     unicode::segmentation::UnicodeSegmentation,
     unicode::width::UnicodeWidthStr,
-    LineEnding, Position, Range, Selection,
+    LineEnding, Position, Range, RopeSlice, Selection,
 };
 use helix_view::{
-    document::Mode,
-    editor::LineNumber,
+    document::{binary_char_to_byte, encoding, Mode},
+    editor::{IndentGuidesConfig, LineNumber, WhitespaceConfig, WhitespaceRenderValue},
     graphics::{CursorKind, Modifier, Rect, Style},
     info::Info,
     input::KeyEvent,
     keyboard::{KeyCode, KeyModifiers},
-    Document, Editor, Theme, View,
+    view::{LineSeeks, HEX_ROW_LEN},
+    Document, DocumentId, Editor, Theme, View, ViewId,
 };
 use std::borrow::Cow;
 
@@ -36,6 +41,11 @@ pub struct EditorView {
     completion: Option<Completion>,
     spinners: ProgressSpinners,
     autoinfo: Option<Info>,
+    /// The view and document a high nibble was just typed in, in the hex view of a binary
+    /// document, with the version of the document after it. The next hex digit typed there is
+    /// the low nibble, unless the focus moved or the document changed in between.
+    hex_high_nibble: Option<(ViewId, DocumentId, i32)>,
+    playground: Option<Playground>,
 }
 
 impl Default for EditorView {
@@ -53,6 +63,8 @@ impl EditorView {
             completion: None,
             spinners: ProgressSpinners::default(),
             autoinfo: None,
+            hex_high_nibble: None,
+            playground: None,
         }
     }
 
@@ -60,6 +72,51 @@ impl EditorView {
         &mut self.spinners
     }
 
+    /// Open the syntax tree playground next to the focused view, or close it if it's open.
+    pub fn toggle_playground(&mut self, editor: &mut Editor) {
+        match self.playground.take() {
+            Some(playground) if playground.is_open(editor) => playground.close(editor),
+            _ => {
+                let mut playground = Playground::open(editor);
+                playground.update(editor);
+                self.playground = Some(playground);
+            }
+        }
+    }
+
+    /// Updates the playground once input pauses for a moment, so that printing the tree
+    /// doesn't hold up typing. Every call delays the update again.
+    pub fn schedule_playground_update(&mut self, jobs: &mut Jobs) {
+        let update = match &mut self.playground {
+            Some(playground) => playground.schedule_update(),
+            None => return,
+        };
+        let callback = async move {
+            tokio::time::sleep(Playground::DEBOUNCE).await;
+            let call: job::Callback = Box::new(move |editor, compositor| {
+                if let Some(editor_view) = compositor
+                    .find(std::any::type_name::<EditorView>())
+                    .and_then(|component| component.as_any_mut().downcast_mut::<EditorView>())
+                {
+                    editor_view.update_playground(editor, update);
+                }
+            });
+            Ok(call)
+        };
+        jobs.callback(callback);
+    }
+
+    /// Carries out the scheduled playground `update` unless another one was scheduled since.
+    fn update_playground(&mut self, editor: &mut Editor, update: usize) {
+        if let Some(playground) = &mut self.playground {
+            if !playground.is_open(editor) {
+                self.playground = None;
+            } else if playground.is_scheduled(update) {
+                playground.update(editor);
+            }
+        }
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn render_view(
         &self,
@@ -69,28 +126,68 @@ impl EditorView {
         surface: &mut Surface,
         theme: &Theme,
         is_focused: bool,
-        loader: &syntax::Loader,
         config: &helix_view::editor::Config,
     ) {
         let inner = view.inner_area();
         let area = view.area;
 
-        let highlights = Self::doc_syntax_highlights(doc, view.offset, inner.height, theme, loader);
-        let highlights = syntax::merge(highlights, Self::doc_diagnostics_highlights(doc, theme));
-        let highlights: Box<dyn Iterator<Item = HighlightEvent>> = if is_focused {
-            Box::new(syntax::merge(
-                highlights,
-                Self::doc_selection_highlights(doc, view, theme),
-            ))
+        if doc.is_binary() {
+            Self::render_hex_view(doc, view, surface, theme, is_focused);
         } else {
-            Box::new(highlights)
-        };
+            let hidden = doc.hidden_lines(view.id);
+            // start at the line a folded first line is folded into
+            let offset = Position::new(
+                hidden.from_visual(hidden.to_visual(view.offset.row)),
+                view.offset.col,
+            );
+            let highlights = Self::doc_syntax_highlights(
+                doc,
+                offset,
+                inner,
+                &hidden,
+                &view.line_seeks,
+                theme,
+                config,
+            );
+            let highlights =
+                syntax::merge(highlights, Self::doc_diagnostics_highlights(doc, theme));
+            // the playground shows the selected node without being focused
+            let is_playground =
+                matches!(&self.playground, Some(playground) if playground.view_id() == view.id);
+            let highlights: Box<dyn Iterator<Item = HighlightEvent>> =
+                if is_focused || is_playground {
+                    Box::new(syntax::merge(
+                        highlights,
+                        Self::doc_selection_highlights(doc, view, theme),
+                    ))
+                } else {
+                    Box::new(highlights)
+                };
 
-        Self::render_text_highlights(doc, view.offset, inner, surface, theme, highlights);
-        Self::render_gutter(doc, view, view.area, surface, theme, is_focused, config);
+            // large files only get the text, the gutter and the cursors
+            if !doc.is_large_file() {
+                Self::render_rulers(doc, view, inner, surface, theme, config);
+            }
+            Self::render_text_highlights(
+                doc,
+                offset,
+                inner,
+                &hidden,
+                &view.line_seeks,
+                surface,
+                theme,
+                highlights,
+                &config.whitespace,
+            );
+            Self::render_indent_guides(doc, view, inner, surface, theme, &config.indent_guides);
+            Self::render_gutter(doc, view, view.area, surface, theme, is_focused, config);
 
-        if is_focused {
-            Self::render_focused_view_elements(view, doc, inner, theme, surface);
+            if is_focused {
+                Self::render_focused_view_elements(view, doc, inner, theme, surface);
+            }
+            if config.sticky_context && !doc.is_large_file() {
+                Self::render_sticky_context(doc, view, offset, surface, theme, config);
+            }
         }
 
         // if we're not at the edge of the screen, draw a right border
@@ -122,48 +219,44 @@ impl EditorView {
     pub fn doc_syntax_highlights<'doc>(
         doc: &'doc Document,
         offset: Position,
-        height: u16,
+        viewport: Rect,
+        hidden: &HiddenLines,
+        line_seeks: &LineSeeks,
         theme: &Theme,
-        loader: &syntax::Loader,
+        config: &helix_view::editor::Config,
     ) -> Box<dyn Iterator<Item = HighlightEvent> + 'doc> {
         let text = doc.text().slice(..);
         let last_line = std::cmp::min(
             // Saturating subs to make it inclusive zero indexing.
-            (offset.row + height as usize).saturating_sub(1),
+            hidden.from_visual(
+                (hidden.to_visual(offset.row) + viewport.height as usize).saturating_sub(1),
+            ),
             doc.text().len_lines().saturating_sub(1),
         );
 
-        let range = {
-            // calculate viewport byte ranges
-            let start = text.line_to_byte(offset.row);
-            let end = text.line_to_byte(last_line + 1);
+        // calculate viewport char ranges, the first and last lines are cut down to the visible
+        // columns so that very long lines aren't highlighted in full
+        let char_range = {
+            let start = if offset.col == 0 {
+                text.line_to_char(offset.row)
+            } else {
+                line_seeks.pos_at_visual_col(doc, offset.row, offset.col).0
+            };
+            let right = offset.col + viewport.width as usize;
+            let end = match line_seeks.pos_at_visual_col(doc, last_line, right) {
+                (end, col) if col >= right => end,
+                _ => text.line_to_char(last_line + 1),
+            };
 
             start..end
         };
+        let range = text.char_to_byte(char_range.start)..text.char_to_byte(char_range.end);
 
         // TODO: range doesn't actually restrict source, just highlight range
         let highlights = match doc.syntax() {
             Some(syntax) => {
-                let scopes = theme.scopes();
                 syntax
-                    .highlight_iter(text.slice(..), Some(range), None, |language| {
-                        loader
-                            .language_config_for_scope(&format!("source.{}", language))
-                            .and_then(|language_config| {
-                                let config = language_config.highlight_config(scopes)?;
-                                let config_ref = config.as_ref();
-                                // SAFETY: the referenced `HighlightConfiguration` behind
-                                // the `Arc` is guaranteed to remain valid throughout the
-                                // duration of the highlight.
-                                let config_ref = unsafe {
-                                    std::mem::transmute::<
-                                        _,
-                                        &'static syntax::HighlightConfiguration,
-                                    >(config_ref)
-                                };
-                                Some(config_ref)
-                            })
-                    })
+                    .highlight_iter(text.slice(..), Some(range), None)
                     .map(|event| event.unwrap())
                     .collect() // TODO: we collect here to avoid holding the lock, fix later
             }
@@ -183,7 +276,37 @@ impl EditorView {
             event => event,
         });
 
-        Box::new(highlights)
+        if config.rainbow_brackets && !doc.is_large_file() {
+            let brackets = Self::doc_rainbow_highlights(doc, char_range, theme);
+            Box::new(syntax::merge(highlights, brackets))
+        } else {
+            Box::new(highlights)
+        }
+    }
+
+    /// Get highlight spans colouring brackets inside the char `range` by nesting depth,
+    /// cycling through the `ui.bracket.1`, `ui.bracket.2`, ... theme scopes.
+    pub fn doc_rainbow_highlights(
+        doc: &Document,
+        range: std::ops::Range<usize>,
+        theme: &Theme,
+    ) -> Vec<(usize, std::ops::Range<usize>)> {
+        let syntax = match doc.syntax() {
+            Some(syntax) => syntax,
+            None => return Vec::new(),
+        };
+
+        let scopes: Vec<usize> = (1..)
+            .map_while(|n| theme.find_scope_index(&format!("ui.bracket.{}", n)))
+            .collect();
+        if scopes.is_empty() {
+            return Vec::new();
+        }
+
+        match_brackets::bracket_depths(syntax, doc.text().slice(..), range)
+            .into_iter()
+            .map(|(depth, pos)| (scopes[depth % scopes.len()], pos..pos + 1))
+            .collect()
     }
 
     /// Get highlight spans for document diagnostics
@@ -272,23 +395,141 @@ impl EditorView {
         spans
     }
 
+    /// Renders a binary document as rows of `HEX_ROW_LEN` bytes: the offset of the row, the
+    /// bytes in hex and the bytes as ASCII, with `.` for anything that isn't printable.
+    pub fn render_hex_view(
+        doc: &Document,
+        view: &View,
+        surface: &mut Surface,
+        theme: &Theme,
+        is_focused: bool,
+    ) {
+        let text = doc.text().slice(..);
+        let area = view.area.clip_bottom(1); // -1 for statusline
+        let spans = if is_focused {
+            Self::doc_selection_highlights(doc, view, theme)
+        } else {
+            Vec::new()
+        };
+
+        let text_style = theme.get("ui.text");
+        let linenr = theme.get("ui.linenr");
+
+        // 8 digit offset and a gap, 3 columns for each byte in hex, a gap and the ASCII column
+        let hex_x = area.x + 10;
+        let ascii_x = hex_x + 3 * HEX_ROW_LEN as u16 + 1;
+
+        let mut put = |x: u16, y: u16, string: &str, style: Style| {
+            if x + string.len() as u16 <= area.right() {
+                surface.set_string(x, y, string, style);
+            }
+        };
+
+        for y in area.top()..area.bottom() {
+            let row_start = (view.offset.row + (y - area.y) as usize) * HEX_ROW_LEN;
+            if row_start > text.len_chars() {
+                break;
+            }
+            put(area.x, y, &format!("{:08x}", row_start), linenr);
+
+            for col in 0..HEX_ROW_LEN {
+                let pos = row_start + col;
+                let style = spans
+                    .iter()
+                    .filter(|(_, range)| range.contains(&pos))
+                    .fold(text_style, |style, (scope, _)| {
+                        style.patch(theme.get(theme.scopes()[*scope].as_str()))
+                    });
+                let (hex_x, ascii_x) = (hex_x + 3 * col as u16, ascii_x + col as u16);
+
+                match text.get_char(pos) {
+                    Some(ch) => {
+                        let byte = binary_char_to_byte(ch).unwrap_or(b'?');
+                        let ascii = match byte {
+                            b' '..=b'~' => byte as char,
+                            _ => '.',
+                        };
+                        put(hex_x, y, &format!("{:02x}", byte), style);
+                        put(ascii_x, y, ascii.encode_utf8(&mut [0; 4]), style);
+                    }
+                    // a cursor at the end of the document
+                    None if style != text_style => put(hex_x, y, "  ", style),
+                    None => break,
+                }
+            }
+        }
+    }
+
+    /// Render the text of `highlights` from the line and column `offset` on, skipping the
+    /// `hidden` lines of folds. The lines are seeked to the column of `offset` through
+    /// `line_seeks`.
+    #[allow(clippy::too_many_arguments)]
     pub fn render_text_highlights<H: Iterator<Item = HighlightEvent>>(
         doc: &Document,
         offset: Position,
         viewport: Rect,
+        hidden: &HiddenLines,
+        line_seeks: &LineSeeks,
         surface: &mut Surface,
         theme: &Theme,
         highlights: H,
+        whitespace: &WhitespaceConfig,
     ) {
         let text = doc.text().slice(..);
 
         let mut spans = Vec::new();
-        let mut visual_x = 0u16;
         let mut line = 0u16;
         let tab_width = doc.tab_width();
-        let tab = " ".repeat(tab_width);
+
+        let characters = &whitespace.characters;
+        let render_all = |value| value == WhitespaceRenderValue::All;
+        let tab = if render_all(whitespace.render.tab()) {
+            format!(
+                "{}{}",
+                characters.tab,
+                " ".repeat(tab_width.saturating_sub(1))
+            )
+        } else {
+            " ".repeat(tab_width)
+        };
+        let space = if render_all(whitespace.render.space()) {
+            characters.space.to_string()
+        } else {
+            " ".to_string()
+        };
+        let nbsp = if render_all(whitespace.render.nbsp()) {
+            characters.nbsp.to_string()
+        } else {
+            " ".to_string()
+        };
+        let newline = if render_all(whitespace.render.newline()) {
+            characters.newline.to_string()
+        } else {
+            " ".to_string()
+        };
 
         let text_style = theme.get("ui.text");
+        let whitespace_style = theme.get("ui.virtual.whitespace");
+        let trailing_style = theme.try_get("ui.virtual.whitespace.trailing");
+        let fold_style = theme.try_get("ui.virtual.fold").unwrap_or(whitespace_style);
+
+        // (line index, char index where the trailing whitespace of that line starts)
+        let mut trailing: Option<(usize, usize)> = None;
+
+        // Long lines are only walked within the visible columns: text before `skip_to` is
+        // skipped over, and lines start drawing at the first grapheme left in view. Folded
+        // lines are skipped over the same way.
+        let seek_line = |line: usize| {
+            if line >= text.len_lines() {
+                (0, 0)
+            } else if offset.col == 0 {
+                (text.line_to_char(line), 0)
+            } else {
+                line_seeks.pos_at_visual_col(doc, line, offset.col)
+            }
+        };
+        let mut doc_line = offset.row;
+        let (mut skip_to, mut visual_x) = seek_line(doc_line);
 
         'outer: for event in highlights {
             match event {
@@ -299,10 +540,11 @@ impl EditorView {
                     spans.pop();
                 }
                 HighlightEvent::Source { start, end } => {
-                    // `unwrap_or_else` part is for off-the-end indices of
-                    // the rope, to allow cursor highlighting at the end
-                    // of the rope.
-                    let text = text.get_slice(start..end).unwrap_or_else(|| " ".into());
+                    // skip text that is scrolled out of view horizontally
+                    if end <= skip_to {
+                        continue;
+                    }
+                    let mut start = start.max(skip_to);
 
                     use helix_core::graphemes::{grapheme_width, RopeGraphemes};
 
@@ -311,55 +553,244 @@ impl EditorView {
                         acc.patch(style)
                     });
 
-                    for grapheme in RopeGraphemes::new(text) {
-                        let out_of_bounds = visual_x < offset.col as u16
-                            || visual_x >= viewport.width + offset.col as u16;
-
-                        if LineEnding::from_rope_slice(&grapheme).is_some() {
-                            if !out_of_bounds {
-                                // we still want to render an empty cell with the style
-                                surface.set_string(
-                                    viewport.x + visual_x - offset.col as u16,
-                                    viewport.y + line,
-                                    " ",
-                                    style,
-                                );
-                            }
+                    'source: loop {
+                        // `unwrap_or_else` part is for off-the-end indices of
+                        // the rope, to allow cursor highlighting at the end
+                        // of the rope.
+                        let text = text.get_slice(start..end).unwrap_or_else(|| " ".into());
+                        let mut char_pos = start;
+
+                        for grapheme in RopeGraphemes::new(text) {
+                            let grapheme_start = char_pos;
+                            char_pos += grapheme.len_chars();
+
+                            let out_of_bounds = visual_x < offset.col
+                                || visual_x >= viewport.width as usize + offset.col;
+
+                            if LineEnding::from_rope_slice(&grapheme).is_some() {
+                                if !out_of_bounds {
+                                    let style = if newline == " " {
+                                        style
+                                    } else {
+                                        style.patch(whitespace_style)
+                                    };
+                                    // we still want to render an empty cell with the style
+                                    surface.set_string(
+                                        viewport.x + (visual_x - offset.col) as u16,
+                                        viewport.y + line,
+                                        &newline,
+                                        style,
+                                    );
+                                }
 
-                            visual_x = 0;
-                            line += 1;
+                                let folded = hidden.hidden_after(doc_line);
+                                if folded > 0 && visual_x + 1 >= offset.col {
+                                    let x = visual_x + 1 - offset.col;
+                                    if x < viewport.width as usize {
+                                        surface.set_stringn(
+                                            viewport.x + x as u16,
+                                            viewport.y + line,
+                                            match folded {
+                                                1 => "⋯ 1 line".to_string(),
+                                                _ => format!("⋯ {} lines", folded),
+                                            },
+                                            viewport.width as usize - x,
+                                            fold_style,
+                                        );
+                                    }
+                                }
 
-                            // TODO: with proper iter this shouldn't be necessary
-                            if line >= viewport.height {
-                                break 'outer;
-                            }
-                        } else {
-                            let grapheme = Cow::from(grapheme);
+                                line += 1;
 
-                            let (grapheme, width) = if grapheme == "\t" {
-                                // make sure we display tab as appropriate amount of spaces
-                                (tab.as_str(), tab_width)
+                                // TODO: with proper iter this shouldn't be necessary
+                                if line >= viewport.height {
+                                    break 'outer;
+                                }
+
+                                doc_line += 1 + folded;
+                                let (pos, col) = seek_line(doc_line);
+                                skip_to = pos;
+                                visual_x = col;
                             } else {
-                                // Cow will prevent allocations if span contained in a single slice
-                                // which should really be the majority case
-                                let width = grapheme_width(&grapheme);
-                                (grapheme.as_ref(), width)
-                            };
-
-                            if !out_of_bounds {
-                                // if we're offscreen just keep going until we hit a new line
-                                surface.set_string(
-                                    viewport.x + visual_x - offset.col as u16,
-                                    viewport.y + line,
-                                    grapheme,
-                                    style,
-                                );
+                                let grapheme = Cow::from(grapheme);
+
+                                let (grapheme, width, is_whitespace) = match grapheme.as_ref() {
+                                    // make sure we display tab as appropriate amount of spaces
+                                    "\t" => (tab.as_str(), tab_width, true),
+                                    " " => (space.as_str(), 1, true),
+                                    "\u{00A0}" => (nbsp.as_str(), 1, true),
+                                    // Cow will prevent allocations if span contained in a single slice
+                                    // which should really be the majority case
+                                    _ => (grapheme.as_ref(), grapheme_width(&grapheme), false),
+                                };
+
+                                if !out_of_bounds {
+                                    let mut style = style;
+                                    if is_whitespace {
+                                        if grapheme.trim() != "" {
+                                            style = style.patch(whitespace_style);
+                                        }
+                                        if let Some(trailing_style) = trailing_style {
+                                            let trailing_start = match trailing {
+                                                Some((line, start)) if line == doc_line => start,
+                                                _ => {
+                                                    let start = trailing_whitespace_start(
+                                                        doc.text().slice(..),
+                                                        doc_line,
+                                                    );
+                                                    trailing = Some((doc_line, start));
+                                                    start
+                                                }
+                                            };
+                                            if grapheme_start >= trailing_start {
+                                                style = style.patch(trailing_style);
+                                            }
+                                        }
+                                    }
+
+                                    surface.set_string(
+                                        viewport.x + (visual_x - offset.col) as u16,
+                                        viewport.y + line,
+                                        grapheme,
+                                        style,
+                                    );
+                                }
+
+                                visual_x += width;
+
+                                // past the right edge, jump to the end of the line
+                                if visual_x >= viewport.width as usize + offset.col {
+                                    skip_to = line_end_char_index(&doc.text().slice(..), doc_line);
+                                }
                             }
 
-                            visual_x = visual_x.saturating_add(width as u16);
+                            if skip_to > char_pos {
+                                if skip_to >= end {
+                                    break 'source;
+                                }
+                                start = skip_to;
+                                continue 'source;
+                            }
                         }
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Render vertical rulers at the columns configured for the document's language, falling
+    /// back to the editor-wide `rulers`.
+    pub fn render_rulers(
+        doc: &Document,
+        view: &View,
+        viewport: Rect,
+        surface: &mut Surface,
+        theme: &Theme,
+        config: &helix_view::editor::Config,
+    ) {
+        let rulers = doc
+            .language_config()
+            .and_then(|config| config.rulers.as_ref())
+            .unwrap_or(&config.rulers);
+
+        let style = theme.get("ui.virtual.ruler");
+
+        for &ruler in rulers {
+            // rulers are 1-based: `80` draws on the 80th column
+            let col = match (ruler as usize).checked_sub(1) {
+                Some(col) if col >= view.offset.col => col - view.offset.col,
+                _ => continue,
+            };
+            if col >= viewport.width as usize {
+                continue;
+            }
+            surface.set_style(
+                Rect::new(viewport.x + col as u16, viewport.y, 1, viewport.height),
+                style,
+            );
+        }
+    }
+
+    /// Render a guide at every indentation level inside the leading whitespace of each line.
+    /// Blank lines take the indentation of the surrounding block so guides aren't interrupted.
+    pub fn render_indent_guides(
+        doc: &Document,
+        view: &View,
+        viewport: Rect,
+        surface: &mut Surface,
+        theme: &Theme,
+        config: &IndentGuidesConfig,
+    ) {
+        // scanning for the surrounding block of blank lines can cover the whole document
+        if !config.render || doc.is_large_file() {
+            return;
+        }
+
+        let text = doc.text().slice(..);
+        let tab_width = doc.tab_width();
+        let indent_width = match doc.indent_unit() {
+            "\t" => tab_width,
+            unit => unit.len(),
+        }
+        .max(1);
+
+        let style = theme.get("ui.virtual.indent-guide");
+        let mut guide = [0; 4];
+        let guide = config.character.encode_utf8(&mut guide);
+
+        let first_line = view.offset.row;
+        let last_line = view.last_line(doc);
+        let hidden = doc.hidden_lines(view.id);
+
+        // indentation of the closest non-blank line above the current one
+        let mut prev_indent = (0..first_line)
+            .rev()
+            .find_map(|line| visual_indent(text.line(line), tab_width))
+            .unwrap_or(0);
+        // (line, indentation) of the closest non-blank line below the current one
+        let mut next_indent: Option<(usize, usize)> = None;
+
+        let lines = hidden
+            .visible_lines(first_line)
+            .take_while(|&line| line <= last_line);
+        for (row, line) in lines.enumerate() {
+            let (indent, skip_col) = match visual_indent(text.line(line), tab_width) {
+                Some(indent) => {
+                    prev_indent = indent;
+                    (indent, None)
+                }
+                None => {
+                    if !matches!(next_indent, Some((next_line, _)) if next_line > line) {
+                        next_indent = Some(
+                            (line + 1..text.len_lines())
+                                .find_map(|line| {
+                                    visual_indent(text.line(line), tab_width)
+                                        .map(|indent| (line, indent))
+                                })
+                                .unwrap_or((text.len_lines(), 0)),
+                        );
                     }
+                    let indent = next_indent.map_or(0, |(_, indent)| indent.min(prev_indent));
+                    // leave the cell of the line ending alone, it may hold a cursor
+                    let width = whitespace_width(text.line(line), tab_width);
+                    (indent, Some(width))
                 }
+            };
+
+            let y = viewport.y + row as u16;
+            for col in (0..indent).step_by(indent_width) {
+                if Some(col) == skip_col || col < view.offset.col {
+                    continue;
+                }
+                let x = col - view.offset.col;
+                if x >= viewport.width as usize {
+                    break;
+                }
+                surface
+                    .get_mut(viewport.x + x as u16, y)
+                    .set_symbol(guide)
+                    .set_style(style);
             }
         }
     }
@@ -372,29 +803,39 @@ impl EditorView {
         theme: &Theme,
         surface: &mut Surface,
     ) {
-        // Highlight matching braces
+        // Highlight the brackets matching the ones under each cursor
         if let Some(syntax) = doc.syntax() {
             let text = doc.text().slice(..);
             use helix_core::match_brackets;
-            let pos = doc.selection(view.id).primary().cursor(text);
 
-            let pos = match_brackets::find(syntax, doc.text(), pos)
-                .and_then(|pos| view.screen_coords_at_pos(doc, text, pos));
+            let style = theme.try_get("ui.cursor.match").unwrap_or_else(|| {
+                Style::default()
+                    .add_modifier(Modifier::REVERSED)
+                    .add_modifier(Modifier::DIM)
+            });
+            let last_line = view.last_line(doc);
 
-            if let Some(pos) = pos {
-                // ensure col is on screen
-                if (pos.col as u16) < viewport.width + view.offset.col as u16
-                    && pos.col >= view.offset.col
-                {
-                    let style = theme.try_get("ui.cursor.match").unwrap_or_else(|| {
-                        Style::default()
-                            .add_modifier(Modifier::REVERSED)
-                            .add_modifier(Modifier::DIM)
-                    });
+            for range in doc.selection(view.id).iter() {
+                let cursor = range.cursor(text);
+
+                // only look for matches of cursors that are on screen
+                let line = text.char_to_line(cursor);
+                if line < view.offset.row || line > last_line {
+                    continue;
+                }
 
-                    surface
-                        .get_mut(viewport.x + pos.col as u16, viewport.y + pos.row as u16)
-                        .set_style(style);
+                // matches scrolled off to the left would be clamped to the first column
+                let pos = match_brackets::find(syntax, doc.text(), cursor)
+                    .filter(|&pos| view.line_column(doc, pos).visual_col >= view.offset.col)
+                    .and_then(|pos| view.screen_coords_at_pos(doc, text, pos));
+
+                if let Some(pos) = pos {
+                    // ensure col is on screen
+                    if (pos.col as u16) < viewport.width {
+                        surface
+                            .get_mut(viewport.x + pos.col as u16, viewport.y + pos.row as u16)
+                            .set_style(style);
+                    }
                 }
             }
         }
@@ -412,6 +853,7 @@ impl EditorView {
     ) {
         let text = doc.text().slice(..);
         let last_line = view.last_line(doc);
+        let hidden = doc.hidden_lines(view.id);
 
         let linenr = theme.get("ui.linenr");
         let linenr_select: Style = theme.try_get("ui.linenr.selected").unwrap_or(linenr);
@@ -438,7 +880,10 @@ impl EditorView {
             .map(|range| range.cursor_line(text))
             .collect();
 
-        for (i, line) in (view.offset.row..(last_line + 1)).enumerate() {
+        let lines = hidden
+            .visible_lines(view.offset.row)
+            .take_while(|&line| line <= last_line);
+        for (i, line) in lines.enumerate() {
             use helix_core::diagnostic::Severity;
             if let Some(diagnostic) = doc.diagnostics().iter().find(|d| d.line == line) {
                 surface.set_stringn(
@@ -466,7 +911,8 @@ impl EditorView {
                         if current_line == line {
                             line + 1
                         } else {
-                            abs_diff(current_line, line)
+                            // folded lines aren't counted, like when moving by lines
+                            abs_diff(hidden.to_visual(current_line), hidden.to_visual(line))
                         }
                     }
                 };
@@ -486,6 +932,78 @@ impl EditorView {
         }
     }
 
+    /// Draws the first lines of the context nodes around the first line left visible under
+    /// them, like the signature of the function it's in, over the top rows of the view. The
+    /// rows of the primary cursor and below are left alone.
+    pub fn render_sticky_context(
+        doc: &Document,
+        view: &View,
+        offset: Position,
+        surface: &mut Surface,
+        theme: &Theme,
+        config: &helix_view::editor::Config,
+    ) {
+        let (syntax, query) = match (
+            doc.syntax(),
+            doc.language_config()
+                .and_then(|config| config.context_query()),
+        ) {
+            (Some(syntax), Some(query)) => (syntax, query),
+            _ => return,
+        };
+        let text = doc.text().slice(..);
+        let hidden = doc.hidden_lines(view.id);
+        let inner = view.inner_area();
+        let cursor_line = text.char_to_line(doc.selection(view.id).primary().cursor(text));
+        let cursor_row = hidden
+            .to_visual(cursor_line)
+            .saturating_sub(hidden.to_visual(offset.row));
+        let max_rows = std::cmp::min(cursor_row, inner.height as usize / 2);
+        let lines = query.sticky_lines(syntax, text, offset.row, &hidden, max_rows);
+
+        let style = theme
+            .try_get("ui.virtual.context")
+            .unwrap_or_else(|| theme.get("ui.popup"));
+        let linenr = theme.get("ui.linenr");
+        for (i, &line) in lines.iter().enumerate() {
+            let y = inner.y + i as u16;
+            let row = Rect::new(view.area.x, y, inner.right() - view.area.x, 1);
+            surface.clear_with(row, theme.get("ui.background"));
+
+            let number = match config.line_number {
+                LineNumber::Absolute => line + 1,
+                LineNumber::Relative => {
+                    abs_diff(hidden.to_visual(cursor_line), hidden.to_visual(line))
+                }
+            };
+            surface.set_stringn(view.area.x + 1, y, format!("{:>5}", number), 5, linenr);
+
+            let offset = Position::new(line, offset.col);
+            let area = Rect::new(inner.x, y, inner.width, 1);
+            let highlights = Self::doc_syntax_highlights(
+                doc,
+                offset,
+                area,
+                &HiddenLines::default(),
+                &view.line_seeks,
+                theme,
+                config,
+            );
+            Self::render_text_highlights(
+                doc,
+                offset,
+                area,
+                &HiddenLines::default(),
+                &view.line_seeks,
+                surface,
+                theme,
+                highlights,
+                &config.whitespace,
+            );
+            surface.set_style(row, style);
+        }
+    }
+
     pub fn render_diagnostics(
         &self,
         doc: &Document,
@@ -582,7 +1100,12 @@ impl EditorView {
         if let Some(path) = doc.relative_path() {
             let path = path.to_string_lossy();
 
-            let title = format!("{}{}", path, if doc.is_modified() { "[+]" } else { "" });
+            let title = format!(
+                "{}{}{}",
+                path,
+                if doc.is_modified() { "[+]" } else { "" },
+                if doc.is_large_file() { " [large]" } else { "" }
+            );
             surface.set_stringn(
                 viewport.x + 8,
                 viewport.y,
@@ -610,21 +1133,44 @@ impl EditorView {
         //     IndentStyle::Spaces(8) => "spaces:8",
         //     _ => "indent:ERROR",
         // };
-        let position_info = {
-            let pos = coords_at_pos(
-                doc.text().slice(..),
-                doc.selection(view.id)
-                    .primary()
-                    .cursor(doc.text().slice(..)),
-            );
-            format!("{}:{}", pos.row + 1, pos.col + 1) // convert to 1-indexing
+        let position_info = if doc.is_binary() {
+            // the byte offset of the cursor
+            let pos = doc
+                .selection(view.id)
+                .primary()
+                .cursor(doc.text().slice(..));
+            format!("{:08x}", pos)
+        } else {
+            let cursor = doc
+                .selection(view.id)
+                .primary()
+                .cursor(doc.text().slice(..));
+            let line = doc.text().char_to_line(cursor);
+            let col = view.line_column(doc, cursor).col;
+            format!("{}:{}", line + 1, col + 1) // convert to 1-indexing
+        };
+
+        // UTF-8 without a byte order mark is the norm, anything else is worth pointing out.
+        let encoding = doc.encoding();
+        let encoding_info = match (encoding == encoding::UTF_8, doc.has_bom()) {
+            _ if doc.is_binary() => "binary    ".to_string(),
+            (true, false) => String::new(),
+            (_, false) => format!("{}    ", encoding.name()),
+            (_, true) => format!("{} BOM    ", encoding.name()),
         };
 
+        let compression_info = doc
+            .compression()
+            .map(|compression| format!("{}    ", compression.name()))
+            .unwrap_or_default();
+
         // Render them to the status line together.
         let right_side_text = format!(
-            "{}    {} ",
+            "{}    {}{}{} ",
             &diag_count[..diag_count.len().min(4)],
             // indent_info,
+            compression_info,
+            encoding_info,
             position_info
         );
         let text_len = right_side_text.len() as u16;
@@ -656,6 +1202,11 @@ impl EditorView {
     }
 
     fn insert_mode(&mut self, cx: &mut commands::Context, event: KeyEvent) {
+        let (_, doc) = current!(cx.editor);
+        if doc.is_binary() {
+            return self.hex_insert_mode(cx, event);
+        }
+
         if let Some(keyresult) = self.handle_keymap_event(Mode::Insert, cx, event) {
             match keyresult {
                 KeymapResult::NotFound => {
@@ -682,6 +1233,45 @@ impl EditorView {
         }
     }
 
+    /// Insert mode of the hex view: hex digits overwrite the bytes under the cursors one nibble
+    /// at a time, only keys that move the cursors or leave insert mode go through the keymap.
+    fn hex_insert_mode(&mut self, cx: &mut commands::Context, event: KeyEvent) {
+        match event.code {
+            KeyCode::Char(ch) if ch.is_ascii_hexdigit() => {
+                let digit = ch.to_digit(16).unwrap() as u8;
+                let current = |editor: &Editor| {
+                    let view = editor.tree.get(editor.tree.focus);
+                    let doc = &editor.documents[view.doc];
+                    (view.id, doc.id(), doc.version())
+                };
+                let low_nibble = self.hex_high_nibble == Some(current(cx.editor));
+                commands::insert::overwrite_hex_digit(cx, digit, low_nibble);
+                self.hex_high_nibble = if low_nibble {
+                    None
+                } else {
+                    Some(current(cx.editor))
+                };
+            }
+            KeyCode::Esc
+            | KeyCode::Left
+            | KeyCode::Right
+            | KeyCode::Up
+            | KeyCode::Down
+            | KeyCode::Home
+            | KeyCode::End
+            | KeyCode::PageUp
+            | KeyCode::PageDown => {
+                self.hex_high_nibble = None;
+                if let KeymapResult::Matched(command) =
+                    self.keymaps.get_mut(&Mode::Insert).unwrap().get(event)
+                {
+                    command.execute(cx);
+                }
+            }
+            _ => (),
+        }
+    }
+
     fn command_mode(&mut self, mode: Mode, cxt: &mut commands::Context, event: KeyEvent) {
         match event {
             // count handling
@@ -950,7 +1540,12 @@ impl Component for EditorView {
                                 }
                             }
                         }
-                        mode => self.command_mode(mode, &mut cxt, key),
+                        mode => {
+                            // the selection before each command can be gone back to
+                            let (view, doc) = current!(cxt.editor);
+                            doc.record_selection(view.id);
+                            self.command_mode(mode, &mut cxt, key)
+                        }
                     }
                 }
 
@@ -994,6 +1589,7 @@ impl Component for EditorView {
             }
 
             Event::Mouse(event) => self.handle_mouse_event(event, &mut cxt),
+            Event::FocusGained | Event::FocusLost | Event::Paste(_) => EventResult::Ignored,
         }
     }
 
@@ -1006,7 +1602,6 @@ impl Component for EditorView {
 
         for (view, is_focused) in cx.editor.tree.views() {
             let doc = cx.editor.document(view.doc).unwrap();
-            let loader = &cx.editor.syn_loader;
             self.render_view(
                 doc,
                 view,
@@ -1014,7 +1609,6 @@ impl Component for EditorView {
                 surface,
                 &cx.editor.theme,
                 is_focused,
-                loader,
                 &cx.editor.config,
             );
         }
@@ -1090,6 +1684,38 @@ fn canonicalize_key(key: &mut KeyEvent) {
     }
 }
 
+/// Visual width of the leading whitespace of `line`, or `None` if the line is blank.
+fn visual_indent(line: RopeSlice, tab_width: usize) -> Option<usize> {
+    let mut width = 0;
+    for ch in line.chars() {
+        match ch {
+            ' ' => width += 1,
+            '\t' => width += tab_width,
+            ch if char_is_line_ending(ch) => return None,
+            _ => return Some(width),
+        }
+    }
+    None
+}
+
+/// Visual width of the whitespace of a blank `line`, not counting the line ending.
+fn whitespace_width(line: RopeSlice, tab_width: usize) -> usize {
+    line.chars()
+        .take_while(|ch| !char_is_line_ending(*ch))
+        .map(|ch| if ch == '\t' { tab_width } else { 1 })
+        .sum()
+}
+
+/// Returns the char index at which the run of whitespace at the end of `line` begins.
+fn trailing_whitespace_start(text: RopeSlice, line: usize) -> usize {
+    let line_start = text.line_to_char(line);
+    let mut pos = line_end_char_index(&text, line);
+    while pos > line_start && matches!(text.char(pos - 1), ' ' | '\t' | '\u{00A0}') {
+        pos -= 1;
+    }
+    pos
+}
+
 #[inline]
 fn abs_diff(a: usize, b: usize) -> usize {
     if a > b {
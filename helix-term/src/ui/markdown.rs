@@ -96,8 +96,7 @@ fn parse<'a>(
                             // if we have a syntax available, highlight_iter and generate spans
                             let mut highlights = Vec::new();
 
-                            for event in syntax.highlight_iter(rope.slice(..), None, None, |_| None)
-                            {
+                            for event in syntax.highlight_iter(rope.slice(..), None, None) {
                                 match event.unwrap() {
                                     HighlightEvent::HighlightStart(span) => {
                                         highlights.push(span);
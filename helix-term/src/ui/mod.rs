@@ -4,6 +4,7 @@ mod info;
 mod markdown;
 mod menu;
 mod picker;
+mod playground;
 mod popup;
 mod prompt;
 mod spinner;
@@ -14,6 +15,7 @@ pub use editor::EditorView;
 pub use markdown::Markdown;
 pub use menu::Menu;
 pub use picker::{FilePicker, Picker};
+pub use playground::Playground;
 pub use popup::Popup;
 pub use prompt::{Prompt, PromptEvent};
 pub use spinner::{ProgressSpinners, Spinner};
@@ -25,9 +27,11 @@ use helix_view::{Document, Editor, View};
 
 use std::path::PathBuf;
 
+/// A prompt for a regex. Accepted regexes are added to the history in `history_register`.
 pub fn regex_prompt(
     cx: &mut crate::commands::Context,
     prompt: std::borrow::Cow<'static, str>,
+    history_register: Option<char>,
     fun: impl Fn(&mut View, &mut Document, &mut Registers, Regex) + 'static,
 ) -> Prompt {
     let (view, doc) = current!(cx.editor);
@@ -36,7 +40,7 @@ pub fn regex_prompt(
 
     Prompt::new(
         prompt,
-        None,
+        history_register,
         |_input: &str| Vec::new(), // this is fine because Vec::new() doesn't allocate
         move |cx: &mut crate::compositor::Context, input: &str, event: PromptEvent| {
             match event {
@@ -129,7 +133,7 @@ pub mod completers {
     use crate::ui::prompt::Completion;
     use fuzzy_matcher::skim::SkimMatcherV2 as Matcher;
     use fuzzy_matcher::FuzzyMatcher;
-    use helix_view::theme;
+    use helix_view::{session::Session, theme};
     use std::borrow::Cow;
     use std::cmp::Reverse;
 
@@ -162,6 +166,21 @@ pub mod completers {
         names
     }
 
+    pub fn session(input: &str) -> Vec<Completion> {
+        let matcher = Matcher::default();
+
+        let mut matches: Vec<_> = Session::names()
+            .into_iter()
+            .filter_map(|name| matcher.fuzzy_match(&name, input).map(|score| (name, score)))
+            .collect();
+
+        matches.sort_unstable_by_key(|(_name, score)| Reverse(*score));
+        matches
+            .into_iter()
+            .map(|(name, _)| ((0..), Cow::from(name)))
+            .collect()
+    }
+
     pub fn filename(input: &str) -> Vec<Completion> {
         filename_impl(input, |entry| {
             let is_dir = entry.file_type().map_or(false, |entry| entry.is_dir());
@@ -15,10 +15,11 @@ use tui::widgets::Widget;
 use std::{borrow::Cow, collections::HashMap, path::PathBuf};
 
 use crate::ui::{Prompt, PromptEvent};
-use helix_core::Position;
+use helix_core::{fold::HiddenLines, Position};
 use helix_view::{
     editor::Action,
     graphics::{Color, CursorKind, Margin, Rect, Style},
+    view::LineSeeks,
     Document, Editor,
 };
 
@@ -64,7 +65,14 @@ impl<T> FilePicker<T> {
         if let Some((path, _line)) = self.current_file(editor) {
             if !self.preview_cache.contains_key(&path) && editor.document_by_path(&path).is_none() {
                 // TODO: enable syntax highlighting; blocked by async rendering
-                let doc = Document::open(&path, None, Some(&editor.theme), None).unwrap();
+                let doc = Document::open(
+                    &path,
+                    None,
+                    Some(&editor.theme),
+                    None,
+                    Some(editor.config.large_file_threshold),
+                )
+                .unwrap();
                 self.preview_cache.insert(path, doc);
             }
         }
@@ -132,17 +140,22 @@ impl<T: 'static> Component for FilePicker<T> {
             let highlights = EditorView::doc_syntax_highlights(
                 doc,
                 offset,
-                area.height,
+                area,
+                &HiddenLines::default(),
+                &LineSeeks::default(),
                 &cx.editor.theme,
-                &cx.editor.syn_loader,
+                &cx.editor.config,
             );
             EditorView::render_text_highlights(
                 doc,
                 offset,
                 inner,
+                &HiddenLines::default(),
+                &LineSeeks::default(),
                 surface,
                 &cx.editor.theme,
                 highlights,
+                &cx.editor.config.whitespace,
             );
 
             // highlight the line
@@ -331,6 +344,7 @@ impl<T: 'static> Component for Picker<T> {
             | KeyEvent {
                 code: KeyCode::Char('p'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             } => {
                 self.move_up();
             }
@@ -344,6 +358,7 @@ impl<T: 'static> Component for Picker<T> {
             | KeyEvent {
                 code: KeyCode::Char('n'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             } => {
                 self.move_down();
             }
@@ -353,6 +368,7 @@ impl<T: 'static> Component for Picker<T> {
             | KeyEvent {
                 code: KeyCode::Char('c'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             } => {
                 return close_fn;
             }
@@ -368,6 +384,7 @@ impl<T: 'static> Component for Picker<T> {
             KeyEvent {
                 code: KeyCode::Char('h'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             } => {
                 if let Some(option) = self.selection() {
                     (self.callback_fn)(&mut cx.editor, option, Action::HorizontalSplit);
@@ -377,6 +394,7 @@ impl<T: 'static> Component for Picker<T> {
             KeyEvent {
                 code: KeyCode::Char('v'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             } => {
                 if let Some(option) = self.selection() {
                     (self.callback_fn)(&mut cx.editor, option, Action::VerticalSplit);
@@ -386,6 +404,7 @@ impl<T: 'static> Component for Picker<T> {
             KeyEvent {
                 code: KeyCode::Char(' '),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             } => {
                 self.save_filter();
             }
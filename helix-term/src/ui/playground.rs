@@ -0,0 +1,137 @@
+use helix_core::{syntax, Rope, Selection};
+use helix_view::{editor::Action, DocumentId, Editor, ViewId};
+
+use std::{ops, time::Duration};
+
+/// A split showing the syntax tree of the document in the focused view, with the node under
+/// the cursor selected. The tree follows the focus to other documents.
+pub struct Playground {
+    doc_id: DocumentId,
+    view_id: ViewId,
+    /// The document and its version that the tree was printed from.
+    source: Option<(DocumentId, i32)>,
+    /// The byte range of every printed node in the source, with the char range of its
+    /// s-expression in the tree. Children come before their parents.
+    nodes: Vec<(ops::Range<usize>, ops::Range<usize>)>,
+    /// The number of updates scheduled so far, only the last one is carried out.
+    scheduled: usize,
+}
+
+impl Playground {
+    /// How long input has to pause before the playground is updated.
+    pub const DEBOUNCE: Duration = Duration::from_millis(100);
+
+    /// Opens the playground in a vertical split, leaving the focus where it was.
+    pub fn open(editor: &mut Editor) -> Self {
+        let focus = editor.tree.focus;
+        let doc_id = editor.new_file(Action::VerticalSplit);
+        let view_id = editor.tree.focus;
+        editor.tree.focus = focus;
+
+        Self {
+            doc_id,
+            view_id,
+            source: None,
+            nodes: Vec::new(),
+            scheduled: 0,
+        }
+    }
+
+    pub fn view_id(&self) -> ViewId {
+        self.view_id
+    }
+
+    /// Whether the split of the playground wasn't closed or used for another document.
+    pub fn is_open(&self, editor: &Editor) -> bool {
+        editor
+            .tree
+            .views()
+            .any(|(view, _)| view.id == self.view_id && view.doc == self.doc_id)
+    }
+
+    pub fn close(self, editor: &mut Editor) {
+        if self.is_open(editor) {
+            editor.close(self.view_id, true);
+        }
+    }
+
+    /// Schedules another update, which supersedes the ones scheduled before. Returns the
+    /// update to pass to [`Self::is_scheduled`] once it's due.
+    pub fn schedule_update(&mut self) -> usize {
+        self.scheduled += 1;
+        self.scheduled
+    }
+
+    /// Whether `update` is the last update that was scheduled.
+    pub fn is_scheduled(&self, update: usize) -> bool {
+        update == self.scheduled
+    }
+
+    /// Prints the tree again if the focused document changed and selects the node under its
+    /// cursor.
+    pub fn update(&mut self, editor: &mut Editor) {
+        let source_view = editor.tree.get(editor.tree.focus);
+        if source_view.doc == self.doc_id {
+            // leave the tree alone while it's being looked at
+            return;
+        }
+        let source_view_id = source_view.id;
+        let source = match editor.document(source_view.doc) {
+            Some(source) => source,
+            None => return,
+        };
+
+        let version = (source.id(), source.version());
+        let mut tree = None;
+        if self.source != Some(version) {
+            self.source = Some(version);
+            let mut output = String::new();
+            let mut nodes = Vec::new();
+            match source.syntax() {
+                Some(syntax) => syntax::pretty_print_tree_with(
+                    &mut output,
+                    syntax.tree().root_node(),
+                    &mut |node, range| nodes.push((node.byte_range(), range)),
+                ),
+                None => output.push_str("no syntax tree"),
+            }
+
+            let rope = Rope::from(output.as_str());
+            self.nodes = nodes
+                .into_iter()
+                .map(|(node, range)| {
+                    let range = rope.byte_to_char(range.start)..rope.byte_to_char(range.end);
+                    (node, range)
+                })
+                .collect();
+            tree = Some(output);
+        }
+
+        // the innermost node containing the cursor, or the root node
+        let text = source.text().slice(..);
+        let cursor = text.char_to_byte(source.selection(source_view_id).primary().cursor(text));
+        let selected = self
+            .nodes
+            .iter()
+            .find(|(node, _)| node.start <= cursor && cursor < node.end)
+            .or_else(|| self.nodes.last())
+            .map(|(_, range)| range.clone());
+
+        let doc = match editor.document_mut(self.doc_id) {
+            Some(doc) => doc,
+            None => return,
+        };
+        if let Some(tree) = tree {
+            doc.set_text(&tree, self.view_id);
+        }
+        let selection = match selected {
+            // put the cursor at the start of the node so that it's scrolled into view
+            Some(range) => Selection::single(range.end, range.start),
+            None => Selection::point(0),
+        };
+        if doc.selection(self.view_id) != &selection {
+            doc.set_selection(self.view_id, selection);
+            editor.ensure_cursor_in_view(self.view_id);
+        }
+    }
+}
@@ -72,11 +72,13 @@ impl<T: Component> Component for Popup<T> {
             | KeyEvent {
                 code: KeyCode::Char('c'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             } => close_fn,
 
             KeyEvent {
                 code: KeyCode::Char('d'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             } => {
                 self.scroll(self.size.1 as usize / 2, true);
                 EventResult::Consumed(None)
@@ -84,6 +86,7 @@ impl<T: Component> Component for Popup<T> {
             KeyEvent {
                 code: KeyCode::Char('u'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             } => {
                 self.scroll(self.size.1 as usize / 2, false);
                 EventResult::Consumed(None)
@@ -403,6 +403,7 @@ impl Component for Prompt {
             KeyEvent {
                 code: KeyCode::Char('c'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             }
             | KeyEvent {
                 code: KeyCode::Esc, ..
@@ -413,22 +414,27 @@ impl Component for Prompt {
             KeyEvent {
                 code: KeyCode::Left,
                 modifiers: KeyModifiers::ALT,
+                ..
             }
             | KeyEvent {
                 code: KeyCode::Char('b'),
                 modifiers: KeyModifiers::ALT,
+                ..
             } => self.move_cursor(Movement::BackwardWord(1)),
             KeyEvent {
                 code: KeyCode::Right,
                 modifiers: KeyModifiers::ALT,
+                ..
             }
             | KeyEvent {
                 code: KeyCode::Char('f'),
                 modifiers: KeyModifiers::ALT,
+                ..
             } => self.move_cursor(Movement::ForwardWord(1)),
             KeyEvent {
                 code: KeyCode::Char('f'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             }
             | KeyEvent {
                 code: KeyCode::Right,
@@ -437,6 +443,7 @@ impl Component for Prompt {
             KeyEvent {
                 code: KeyCode::Char('b'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             }
             | KeyEvent {
                 code: KeyCode::Left,
@@ -445,30 +452,37 @@ impl Component for Prompt {
             KeyEvent {
                 code: KeyCode::End,
                 modifiers: KeyModifiers::NONE,
+                ..
             }
             | KeyEvent {
                 code: KeyCode::Char('e'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             } => self.move_end(),
             KeyEvent {
                 code: KeyCode::Home,
                 modifiers: KeyModifiers::NONE,
+                ..
             }
             | KeyEvent {
                 code: KeyCode::Char('a'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             } => self.move_start(),
             KeyEvent {
                 code: KeyCode::Char('w'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             } => self.delete_word_backwards(),
             KeyEvent {
                 code: KeyCode::Char('k'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             } => self.kill_to_end_of_line(),
             KeyEvent {
                 code: KeyCode::Backspace,
                 modifiers: KeyModifiers::NONE,
+                ..
             } => {
                 self.delete_char_backwards();
                 (self.callback_fn)(cx, &self.line, PromptEvent::Update);
@@ -494,6 +508,7 @@ impl Component for Prompt {
             KeyEvent {
                 code: KeyCode::Char('p'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             }
             | KeyEvent {
                 code: KeyCode::Up, ..
@@ -506,6 +521,7 @@ impl Component for Prompt {
             KeyEvent {
                 code: KeyCode::Char('n'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             }
             | KeyEvent {
                 code: KeyCode::Down,
@@ -526,11 +542,13 @@ impl Component for Prompt {
             KeyEvent {
                 code: KeyCode::Char('q'),
                 modifiers: KeyModifiers::CONTROL,
+                ..
             } => self.exit_selection(),
             // any char event that's not combined with control or mapped to any other combo
             KeyEvent {
                 code: KeyCode::Char(c),
                 modifiers,
+                ..
             } if !modifiers.contains(KeyModifiers::CONTROL) => {
                 self.insert_char(c);
                 (self.callback_fn)(cx, &self.line, PromptEvent::Update);
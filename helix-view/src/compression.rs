@@ -0,0 +1,94 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+/// A compression format that files are transparently decompressed from when opened and
+/// compressed with again when saved.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    /// Detects the compression format from the magic bytes at the start of a file.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::Gzip)
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::Zstd)
+        } else if bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Self::Xz)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+            Self::Xz => "xz",
+        }
+    }
+
+    /// Wraps `reader` in a decoder for this format.
+    pub fn decoder<'a, R: BufRead + 'a>(self, reader: R) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Self::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+            Self::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(reader)?),
+            Self::Xz => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(reader)),
+        })
+    }
+
+    /// Compresses `data` with the default compression level of this format.
+    pub fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Self::Zstd => zstd::stream::encode_all(data, 0),
+            Self::Xz => {
+                let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Returns a reader of the contents of `file`, decompressing them if the file starts with
+/// the magic bytes of one of the supported compression formats.
+pub fn open(file: File) -> io::Result<(Box<dyn Read>, Option<Compression>)> {
+    let mut reader = BufReader::new(file);
+    match Compression::detect(reader.fill_buf()?) {
+        Some(compression) => Ok((compression.decoder(reader)?, Some(compression))),
+        None => Ok((Box::new(reader), None)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let text = b"fn main() {\n    println!(\"hello\");\n}\n";
+        for &compression in &[Compression::Gzip, Compression::Zstd, Compression::Xz] {
+            let compressed = compression.compress(text).unwrap();
+            assert_eq!(Compression::detect(&compressed), Some(compression));
+
+            let mut decompressed = Vec::new();
+            compression
+                .decoder(&compressed[..])
+                .unwrap()
+                .read_to_end(&mut decompressed)
+                .unwrap();
+            assert_eq!(decompressed, text);
+        }
+        assert_eq!(Compression::detect(text), None);
+    }
+}
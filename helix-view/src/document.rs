@@ -1,4 +1,4 @@
-use anyhow::{anyhow, Context, Error};
+use anyhow::{anyhow, bail, Context, Error};
 use serde::de::{self, Deserialize, Deserializer};
 use std::cell::Cell;
 use std::collections::HashMap;
@@ -9,20 +9,30 @@ use std::str::FromStr;
 use std::sync::Arc;
 
 use helix_core::{
-    history::History,
+    fold::{Folds, HiddenLines},
+    history::{History, SelectionHistory},
     indent::{auto_detect_indent_style, IndentStyle},
     line_ending::auto_detect_line_ending,
     syntax::{self, LanguageConfiguration},
+    tags::Tag,
     ChangeSet, Diagnostic, LineEnding, Rope, RopeBuilder, Selection, State, Syntax, Transaction,
     DEFAULT_LINE_ENDING,
 };
 use helix_lsp::util::LspFormatting;
 
-use crate::{DocumentId, Theme, ViewId};
+use crate::{
+    compression::{self, Compression},
+    DocumentId, Theme, ViewId,
+};
+
+pub use encoding_rs as encoding;
 
 /// 8kB of buffer space for encoding and decoding `Rope`s.
 const BUF_SIZE: usize = 8192;
 
+/// Number of bytes at the start of a large file used to detect indentation and line endings.
+const LARGE_FILE_DETECT_LEN: usize = 64 * 1024;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Mode {
     Normal,
@@ -68,9 +78,18 @@ pub struct Document {
     pub(crate) id: DocumentId,
     text: Rope,
     pub(crate) selections: HashMap<ViewId, Selection>,
+    /// The folded ranges of each view of the document.
+    pub(crate) folds: HashMap<ViewId, Folds>,
+    /// The earlier selections of each view of the document.
+    pub(crate) selection_histories: HashMap<ViewId, SelectionHistory>,
 
     path: Option<PathBuf>,
     encoding: &'static encoding_rs::Encoding,
+    /// Whether the file starts with a byte order mark.
+    has_bom: bool,
+    /// The compression format of the file, it is decompressed on open and compressed again on
+    /// save.
+    compression: Option<Compression>,
 
     /// Current editing mode.
     pub mode: Mode,
@@ -100,6 +119,10 @@ pub struct Document {
 
     diagnostics: Vec<Diagnostic>,
     language_server: Option<Arc<helix_lsp::Client>>,
+
+    /// Whether the document is over the large file threshold. Large files are opened
+    /// without syntax highlighting or a language server.
+    large_file: bool,
 }
 
 use std::{fmt, mem};
@@ -109,8 +132,12 @@ impl fmt::Debug for Document {
             .field("id", &self.id)
             .field("text", &self.text)
             .field("selections", &self.selections)
+            .field("folds", &self.folds)
+            .field("selection_histories", &self.selection_histories)
             .field("path", &self.path)
             .field("encoding", &self.encoding)
+            .field("has_bom", &self.has_bom)
+            .field("compression", &self.compression)
             .field("mode", &self.mode)
             .field("restore_cursor", &self.restore_cursor)
             .field("syntax", &self.syntax)
@@ -122,6 +149,7 @@ impl fmt::Debug for Document {
             .field("version", &self.version)
             .field("diagnostics", &self.diagnostics)
             // .field("language_server", &self.language_server)
+            .field("large_file", &self.large_file)
             .finish()
     }
 }
@@ -129,13 +157,14 @@ impl fmt::Debug for Document {
 // The documentation and implementation of this function should be up-to-date with
 // its sibling function, `to_writer()`.
 //
-/// Decodes a stream of bytes into UTF-8, returning a `Rope` and the
-/// encoding it was decoded as. The optional `encoding` parameter can
-/// be used to override encoding auto-detection.
+/// Decodes a stream of bytes into UTF-8, returning a `Rope`, the
+/// encoding it was decoded as and whether the stream started with a
+/// byte order mark. The optional `encoding` parameter can be used to
+/// override encoding auto-detection.
 pub fn from_reader<R: std::io::Read + ?Sized>(
     reader: &mut R,
     encoding: Option<&'static encoding_rs::Encoding>,
-) -> Result<(Rope, &'static encoding_rs::Encoding), Error> {
+) -> Result<(Rope, &'static encoding_rs::Encoding, bool), Error> {
     // These two buffers are 8192 bytes in size each and are used as
     // intermediaries during the decoding process. Text read into `buf`
     // from `reader` is decoded into `buf_out` as UTF-8. Once either
@@ -150,22 +179,42 @@ pub fn from_reader<R: std::io::Read + ?Sized>(
     // As a manual override to this auto-detection is possible, the
     // same data is read into `buf` to ensure symmetry in the upcoming
     // loop.
-    let (encoding, mut decoder, mut slice, mut is_empty) = {
+    //
+    // A byte order mark takes precedence over the auto-detected encoding,
+    // but not over a manual override with a different encoding. Files with
+    // NUL bytes in the first chunk and no byte order mark are binary, and are
+    // decoded as `x-user-defined` (see `binary_byte_to_char()`), unless the
+    // NUL bytes look like UTF-16 (see `detect_utf16()`).
+    let (encoding, has_bom, mut decoder, mut slice, mut is_empty) = {
         let read = reader.read(&mut buf)?;
         let is_empty = read == 0;
-        let encoding = encoding.unwrap_or_else(|| {
+        let bom = encoding_rs::Encoding::for_bom(&buf[..read])
+            .map(|(bom, _)| bom)
+            .filter(|&bom| encoding.is_none() || encoding == Some(bom));
+        let encoding = bom.or(encoding).unwrap_or_else(|| {
+            if buf[..read].contains(&0) {
+                return detect_utf16(&buf[..read]).unwrap_or(encoding_rs::X_USER_DEFINED);
+            }
             let mut encoding_detector = chardetng::EncodingDetector::new();
             encoding_detector.feed(&buf, is_empty);
             encoding_detector.guess(None, true)
         });
-        let decoder = encoding.new_decoder();
+        let decoder = if bom.is_some() {
+            encoding.new_decoder_with_bom_removal()
+        } else {
+            encoding.new_decoder_without_bom_handling()
+        };
 
         // If the amount of bytes read from the reader is less than
         // `buf.len()`, it is undesirable to read the bytes afterwards.
         let slice = &buf[..read];
-        (encoding, decoder, slice, is_empty)
+        (encoding, bom.is_some(), decoder, slice, is_empty)
     };
 
+    if encoding == encoding_rs::X_USER_DEFINED {
+        return Ok((binary_from_reader(reader, slice)?, encoding, false));
+    }
+
     // `RopeBuilder::append()` expects a `&str`, so this is the "real"
     // output buffer. When decoding, the number of bytes in the output
     // buffer will often exceed the number of bytes in the input buffer.
@@ -231,7 +280,102 @@ pub fn from_reader<R: std::io::Read + ?Sized>(
         is_empty = read == 0;
     }
     let rope = builder.finish();
-    Ok((rope, encoding))
+    Ok((rope, encoding, has_bom))
+}
+
+/// Maps a byte of a binary document to the char representing it in the `Rope`. Printable
+/// ASCII is kept as is, every other byte is mapped into the private use area in the same
+/// way `x-user-defined` maps bytes above `0x7F`. This keeps each byte a grapheme of its own,
+/// so char offsets in the document are byte offsets in the file.
+pub fn binary_byte_to_char(byte: u8) -> char {
+    match byte {
+        b' '..=b'~' => byte as char,
+        _ => char::from_u32(0xF700 + byte as u32).unwrap(),
+    }
+}
+
+/// The inverse of [`binary_byte_to_char`]. ASCII chars that were typed into a binary document
+/// are also mapped to their byte.
+pub fn binary_char_to_byte(ch: char) -> Option<u8> {
+    match ch as u32 {
+        byte @ 0..=0x7F => Some(byte as u8),
+        ch @ 0xF700..=0xF7FF => Some((ch - 0xF700) as u8),
+        _ => None,
+    }
+}
+
+/// Detects UTF-16 text without a byte order mark. Where the text is mostly ASCII, every other
+/// byte is NUL: the odd ones in little endian and the even ones in big endian. Binary files
+/// have NUL bytes in both.
+fn detect_utf16(buf: &[u8]) -> Option<&'static encoding_rs::Encoding> {
+    let (mut even, mut odd) = (0, 0);
+    for (i, _) in buf.iter().enumerate().filter(|(_, &byte)| byte == 0) {
+        if i % 2 == 0 {
+            even += 1;
+        } else {
+            odd += 1;
+        }
+    }
+    // a stray NUL in other text doesn't make it UTF-16
+    let units = buf.len() / 2;
+    match (even, odd) {
+        (0, odd) if odd * 4 >= units => Some(encoding_rs::UTF_16LE),
+        (even, 0) if even * 4 >= units => Some(encoding_rs::UTF_16BE),
+        _ => None,
+    }
+}
+
+/// Checks that every char of a binary document stands for a byte, so that it can be written
+/// back without changing the chars that don't.
+fn check_binary(rope: &Rope) -> Result<(), Error> {
+    match rope
+        .chars()
+        .position(|ch| binary_char_to_byte(ch).is_none())
+    {
+        Some(pos) => bail!(
+            "can't save binary file, {:?} at {:08x} isn't a byte",
+            rope.char(pos),
+            pos
+        ),
+        None => Ok(()),
+    }
+}
+
+/// Reads the rest of a binary file, one char per byte. `first` holds the bytes that were
+/// already read from `reader`.
+fn binary_from_reader<R: std::io::Read + ?Sized>(
+    reader: &mut R,
+    first: &[u8],
+) -> Result<Rope, Error> {
+    let mut builder = RopeBuilder::new();
+    let mut chunk = String::with_capacity(BUF_SIZE * 3);
+    chunk.extend(first.iter().map(|&byte| binary_byte_to_char(byte)));
+    builder.append(&chunk);
+
+    let mut buf = [0u8; BUF_SIZE];
+    loop {
+        let read = reader.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        chunk.clear();
+        chunk.extend(buf[..read].iter().map(|&byte| binary_byte_to_char(byte)));
+        builder.append(&chunk);
+    }
+    Ok(builder.finish())
+}
+
+/// Returns the byte order mark of `encoding`, if it has one.
+pub fn byte_order_mark(encoding: &'static encoding_rs::Encoding) -> Option<&'static [u8]> {
+    if encoding == encoding_rs::UTF_8 {
+        Some(&[0xEF, 0xBB, 0xBF])
+    } else if encoding == encoding_rs::UTF_16LE {
+        Some(&[0xFF, 0xFE])
+    } else if encoding == encoding_rs::UTF_16BE {
+        Some(&[0xFE, 0xFF])
+    } else {
+        None
+    }
 }
 
 // The documentation and implementation of this function should be up-to-date with
@@ -239,12 +383,58 @@ pub fn from_reader<R: std::io::Read + ?Sized>(
 //
 /// Encodes the text inside `rope` into the given `encoding` and writes the
 /// encoded output into `writer.` As a `Rope` can only contain valid UTF-8,
-/// replacement characters may appear in the encoded text.
+/// replacement characters may appear in the encoded text. If `bom` is set,
+/// the output starts with the byte order mark of `encoding`.
 pub async fn to_writer<'a, W: tokio::io::AsyncWriteExt + Unpin + ?Sized>(
     writer: &'a mut W,
     encoding: &'static encoding_rs::Encoding,
+    bom: bool,
     rope: &'a Rope,
 ) -> Result<(), Error> {
+    if let Some(bom) = byte_order_mark(encoding).filter(|_| bom) {
+        writer.write_all(bom).await?;
+    }
+
+    // Binary documents are written back byte for byte. Chars that don't stand
+    // for a byte can only have been typed in, and nothing is written if there
+    // are any.
+    if encoding == encoding_rs::X_USER_DEFINED {
+        check_binary(rope)?;
+        let mut buf = Vec::with_capacity(BUF_SIZE);
+        for chunk in rope.chunks() {
+            buf.extend(chunk.chars().filter_map(binary_char_to_byte));
+            if buf.len() >= BUF_SIZE {
+                writer.write_all(&buf).await?;
+                buf.clear();
+            }
+        }
+        writer.write_all(&buf).await?;
+        writer.flush().await?;
+        return Ok(());
+    }
+
+    // `encoding_rs` doesn't encode into UTF-16, its UTF-16 encoders output
+    // UTF-8 instead, so UTF-16 is encoded by hand.
+    if encoding == encoding_rs::UTF_16LE || encoding == encoding_rs::UTF_16BE {
+        let mut buf = Vec::with_capacity(BUF_SIZE);
+        for chunk in rope.chunks() {
+            for unit in chunk.encode_utf16() {
+                if encoding == encoding_rs::UTF_16LE {
+                    buf.extend_from_slice(&unit.to_le_bytes());
+                } else {
+                    buf.extend_from_slice(&unit.to_be_bytes());
+                }
+            }
+            if buf.len() >= BUF_SIZE {
+                writer.write_all(&buf).await?;
+                buf.clear();
+            }
+        }
+        writer.write_all(&buf).await?;
+        writer.flush().await?;
+        return Ok(());
+    }
+
     // Text inside a `Rope` is stored as non-contiguous blocks of data called
     // chunks. The absolute size of each chunk is unknown, thus it is impossible
     // to predict the end of the chunk iterator ahead of time. Instead, it is
@@ -323,8 +513,12 @@ impl Document {
             id: DocumentId::default(),
             path: None,
             encoding,
+            has_bom: false,
+            compression: None,
             text,
             selections: HashMap::default(),
+            folds: HashMap::default(),
+            selection_histories: HashMap::default(),
             indent_style: IndentStyle::Spaces(4),
             mode: Mode::Normal,
             restore_cursor: false,
@@ -338,33 +532,48 @@ impl Document {
             last_saved_revision: 0,
             language_server: None,
             line_ending: DEFAULT_LINE_ENDING,
+            large_file: false,
         }
     }
 
     // TODO: async fn?
     /// Create a new document from `path`. Encoding is auto-detected, but it can be manually
-    /// overwritten with the `encoding` parameter.
+    /// overwritten with the `encoding` parameter. Documents with at least `large_file_threshold`
+    /// bytes of text are opened in large file mode, which turns off the features working on all
+    /// of the text. The text itself is still read in full.
     pub fn open(
         path: &Path,
         encoding: Option<&'static encoding_rs::Encoding>,
         theme: Option<&Theme>,
-        config_loader: Option<&syntax::Loader>,
+        config_loader: Option<&Arc<syntax::Loader>>,
+        large_file_threshold: Option<u64>,
     ) -> Result<Self, Error> {
         // Open the file if it exists, otherwise assume it is a new file (and thus empty).
-        let (rope, encoding) = if path.exists() {
-            let mut file =
-                std::fs::File::open(path).context(format!("unable to open {:?}", path))?;
-            from_reader(&mut file, encoding)?
+        let (rope, encoding, has_bom, compression) = if path.exists() {
+            let file = std::fs::File::open(path).context(format!("unable to open {:?}", path))?;
+            let (mut reader, compression) = compression::open(file)?;
+            let (rope, encoding, has_bom) = from_reader(&mut reader, encoding)?;
+            (rope, encoding, has_bom, compression)
         } else {
             let encoding = encoding.unwrap_or(encoding_rs::UTF_8);
-            (Rope::from(DEFAULT_LINE_ENDING.as_str()), encoding)
+            (
+                Rope::from(DEFAULT_LINE_ENDING.as_str()),
+                encoding,
+                false,
+                None,
+            )
         };
 
         let mut doc = Self::from(rope, Some(encoding));
+        doc.has_bom = has_bom;
+        doc.compression = compression;
+        // the size of the text, which a compressed file is much smaller than
+        let len = doc.text.len_bytes() as u64;
+        doc.large_file = matches!(large_file_threshold, Some(threshold) if len >= threshold);
 
-        // set the path and try detecting the language
+        // set the path and try detecting the language, binary files are shown as hex instead
         doc.set_path(path)?;
-        if let Some(loader) = config_loader {
+        if let Some(loader) = config_loader.filter(|_| !doc.is_binary()) {
             doc.detect_language(theme, loader);
         }
 
@@ -443,10 +652,12 @@ impl Document {
         self.reset_modified();
 
         let encoding = self.encoding;
+        let has_bom = self.has_bom;
+        let compression = self.compression;
 
         // We encode the file according to the `Document`'s encoding.
         async move {
-            use tokio::fs::File;
+            use tokio::{fs::File, io::AsyncWriteExt};
             if let Some(parent) = path.parent() {
                 // TODO: display a prompt asking the user if the directories should be created
                 if !parent.exists() {
@@ -465,8 +676,31 @@ impl Document {
                 }
             }
 
+            // the file isn't truncated if the text can't be written to it
+            if encoding == encoding_rs::X_USER_DEFINED {
+                check_binary(&text)?;
+            }
+
+            // compressed before the file is truncated, in case compressing fails
+            let compressed = match compression {
+                Some(compression) => {
+                    let mut buf = Vec::new();
+                    to_writer(&mut buf, encoding, has_bom, &text).await?;
+                    let buf =
+                        tokio::task::spawn_blocking(move || compression.compress(&buf)).await??;
+                    Some(buf)
+                }
+                None => None,
+            };
+
             let mut file = File::create(path).await?;
-            to_writer(&mut file, encoding, &text).await?;
+            match compressed {
+                Some(buf) => {
+                    file.write_all(&buf).await?;
+                    file.flush().await?;
+                }
+                None => to_writer(&mut file, encoding, has_bom, &text).await?,
+            }
 
             if let Some(language_server) = language_server {
                 language_server
@@ -478,11 +712,14 @@ impl Document {
         }
     }
 
-    /// Detect the programming language based on the file type.
-    pub fn detect_language(&mut self, theme: Option<&Theme>, config_loader: &syntax::Loader) {
+    /// Detect the programming language based on the file name, or otherwise on the shebang or
+    /// first line of the file.
+    pub fn detect_language(&mut self, theme: Option<&Theme>, config_loader: &Arc<syntax::Loader>) {
         if let Some(path) = &self.path {
-            let language_config = config_loader.language_config_for_file_name(path);
-            self.set_language(theme, language_config);
+            let language_config = config_loader
+                .language_config_for_file_name(path)
+                .or_else(|| config_loader.language_config_for_content(self.text.slice(..)));
+            self.set_language(theme, language_config, Some(config_loader));
         }
     }
 
@@ -491,7 +728,20 @@ impl Document {
     /// specified. Line ending is likewise auto-detected, and will fallback to the default OS
     /// line ending.
     pub fn detect_indent_and_line_ending(&mut self) {
-        self.indent_style = auto_detect_indent_style(&self.text).unwrap_or_else(|| {
+        // only look at the start of large files, a single huge line would otherwise be
+        // scanned in full
+        let prefix;
+        let text = if self.large_file {
+            let end = self
+                .text
+                .byte_to_char(self.text.len_bytes().min(LARGE_FILE_DETECT_LEN));
+            prefix = Rope::from(self.text.slice(..end));
+            &prefix
+        } else {
+            &self.text
+        };
+
+        self.indent_style = auto_detect_indent_style(text).unwrap_or_else(|| {
             IndentStyle::from_str(
                 self.language
                     .as_ref()
@@ -499,7 +749,7 @@ impl Document {
                     .map_or("  ", |config| config.unit.as_str()), // Fallback to 2 spaces.
             )
         });
-        self.line_ending = auto_detect_line_ending(&self.text).unwrap_or(DEFAULT_LINE_ENDING);
+        self.line_ending = auto_detect_line_ending(text).unwrap_or(DEFAULT_LINE_ENDING);
     }
 
     /// Reload the document from its path.
@@ -512,8 +762,9 @@ impl Document {
             return Err(anyhow!("can't find file to reload from"));
         }
 
-        let mut file = std::fs::File::open(path.unwrap())?;
-        let (rope, ..) = from_reader(&mut file, Some(encoding))?;
+        let file = std::fs::File::open(path.unwrap())?;
+        let (mut reader, compression) = compression::open(file)?;
+        let (rope, _, has_bom) = from_reader(&mut reader, Some(encoding))?;
 
         // Calculate the difference between the buffer and source text, and apply it.
         // This is not considered a modification of the contents of the file regardless
@@ -522,6 +773,8 @@ impl Document {
         self.apply(&transaction, view_id);
         self.append_changes_to_history(view_id);
         self.reset_modified();
+        self.has_bom = has_bom;
+        self.compression = compression;
 
         self.detect_indent_and_line_ending();
 
@@ -534,6 +787,8 @@ impl Document {
             Some(encoding) => self.encoding = encoding,
             None => return Err(anyhow::anyhow!("unknown encoding")),
         }
+        // only unicode encodings have a byte order mark
+        self.has_bom &= byte_order_mark(self.encoding).is_some();
         Ok(())
     }
 
@@ -542,6 +797,35 @@ impl Document {
         self.encoding
     }
 
+    /// Whether the [`Document`] holds a binary file, which is shown as hex. Each char of the
+    /// text stands for one byte, see [`binary_byte_to_char`].
+    pub fn is_binary(&self) -> bool {
+        self.encoding == encoding_rs::X_USER_DEFINED
+    }
+
+    /// Returns the compression format the [`Document`] is saved with, if any.
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    /// Whether the [`Document`] is saved with a byte order mark.
+    pub fn has_bom(&self) -> bool {
+        self.has_bom
+    }
+
+    /// Sets whether the [`Document`] is saved with a byte order mark. Fails if the current
+    /// encoding doesn't have one.
+    pub fn set_bom(&mut self, has_bom: bool) -> Result<(), Error> {
+        if has_bom && byte_order_mark(self.encoding).is_none() {
+            return Err(anyhow!(
+                "{} doesn't have a byte order mark",
+                self.encoding.name()
+            ));
+        }
+        self.has_bom = has_bom;
+        Ok(())
+    }
+
     pub fn set_path(&mut self, path: &Path) -> Result<(), std::io::Error> {
         let path = helix_core::path::get_canonicalized_path(path)?;
 
@@ -553,18 +837,35 @@ impl Document {
     }
 
     /// Set the programming language for the file and load associated data (e.g. highlighting)
-    /// if it exists.
+    /// if it exists. Languages injected into the file are only parsed if a `config_loader` is
+    /// given to look them up in.
     pub fn set_language(
         &mut self,
         theme: Option<&Theme>,
         language_config: Option<Arc<helix_core::syntax::LanguageConfiguration>>,
+        config_loader: Option<&Arc<syntax::Loader>>,
     ) {
         if let Some(language_config) = language_config {
             let scopes = theme.map(|theme| theme.scopes()).unwrap_or(&[]);
-            if let Some(highlight_config) = language_config.highlight_config(scopes) {
-                let syntax = Syntax::new(&self.text, highlight_config);
+            // large files are never parsed
+            let highlight_config = if self.large_file {
+                None
+            } else {
+                language_config.highlight_config(scopes)
+            };
+            if let Some(highlight_config) = highlight_config {
+                let syntax = match config_loader {
+                    Some(loader) => Syntax::new_with_injections(
+                        &self.text,
+                        highlight_config,
+                        loader.injection_callback(scopes),
+                    ),
+                    None => Syntax::new(&self.text, highlight_config),
+                };
                 self.syntax = Some(syntax);
                 // TODO: config.configure(scopes) is now delayed, is that ok?
+            } else {
+                self.syntax = None;
             }
 
             self.language = Some(language_config);
@@ -584,7 +885,20 @@ impl Document {
     ) {
         let language_config = config_loader.language_config_for_scope(scope);
 
-        self.set_language(theme, language_config);
+        self.set_language(theme, language_config, Some(&config_loader));
+    }
+
+    /// Whether the document is in large file mode.
+    pub fn is_large_file(&self) -> bool {
+        self.large_file
+    }
+
+    /// Leave large file mode. The caller should set the language again to get syntax
+    /// highlighting back, see [`Editor::enable_full_features`].
+    ///
+    /// [`Editor::enable_full_features`]: crate::Editor::enable_full_features
+    pub fn disable_large_file(&mut self) {
+        self.large_file = false;
     }
 
     /// Set the LSP.
@@ -597,6 +911,21 @@ impl Document {
         // TODO: use a transaction?
         self.selections
             .insert(view_id, selection.ensure_invariants(self.text().slice(..)));
+        self.reveal_selection(view_id);
+    }
+
+    /// Unfolds the folds hiding a cursor of a view, so that the cursors are always shown
+    /// whatever moved them.
+    fn reveal_selection(&mut self, view_id: ViewId) {
+        let (folds, selection) = match (self.folds.get_mut(&view_id), self.selections.get(&view_id))
+        {
+            (Some(folds), Some(selection)) if !folds.is_empty() => (folds, selection),
+            _ => return,
+        };
+        let text = self.text.slice(..);
+        for range in selection.iter() {
+            folds.reveal_line(text, range.cursor_line(text));
+        }
     }
 
     /// Apply a [`Transaction`] to the [`Document`] to change its text.
@@ -615,12 +944,21 @@ impl Document {
                     .ensure_invariants(self.text.slice(..));
             }
 
+            for folds in self.folds.values_mut() {
+                folds.map(transaction.changes(), self.text.slice(..));
+            }
+
+            for selection_history in self.selection_histories.values_mut() {
+                selection_history.map(transaction.changes());
+            }
+
             // if specified, the current selection should instead be replaced by transaction.selection
             if let Some(selection) = transaction.selection() {
                 self.selections.insert(
                     view_id,
                     selection.clone().ensure_invariants(self.text.slice(..)),
                 );
+                self.reveal_selection(view_id);
             }
         }
 
@@ -684,6 +1022,20 @@ impl Document {
         success
     }
 
+    /// Replaces the whole text with `text` generated by the editor, like the tree shown by the
+    /// syntax tree playground. This isn't an edit of the document: its history starts over.
+    pub fn set_text(&mut self, text: &str, view_id: ViewId) {
+        let transaction = Transaction::change(
+            &self.text,
+            std::iter::once((0, self.text.len_chars(), Some(text.into()))),
+        );
+        self.apply_impl(&transaction, view_id);
+        self.changes = ChangeSet::new(self.text());
+        self.old_state = None;
+        self.history = Cell::new(History::default());
+        self.last_saved_revision = 0;
+    }
+
     /// Undo the last modification to the [`Document`].
     pub fn undo(&mut self, view_id: ViewId) {
         let mut history = self.history.take();
@@ -806,6 +1158,13 @@ impl Document {
         self.syntax.as_ref()
     }
 
+    /// The definitions in the document, if its language has a tags query.
+    pub fn definitions(&self) -> Option<Vec<Tag>> {
+        let tags_config = self.language.as_ref()?.tags_config()?;
+        let syntax = self.syntax.as_ref()?;
+        Some(tags_config.definitions(syntax.tree(), self.text.slice(..)))
+    }
+
     /// Tab size in columns.
     pub fn tab_width(&self) -> usize {
         self.language
@@ -847,6 +1206,58 @@ impl Document {
         &self.selections
     }
 
+    pub fn folds(&self, view_id: ViewId) -> Option<&Folds> {
+        self.folds.get(&view_id)
+    }
+
+    pub fn folds_mut(&mut self, view_id: ViewId) -> &mut Folds {
+        self.folds.entry(view_id).or_default()
+    }
+
+    /// Records the current selection of a view in its selection history.
+    pub fn record_selection(&mut self, view_id: ViewId) {
+        if let Some(selection) = self.selections.get(&view_id) {
+            self.selection_histories
+                .entry(view_id)
+                .or_default()
+                .record(selection);
+        }
+    }
+
+    /// Goes back to the previous selection of a view. Returns whether there was one.
+    pub fn undo_selection(&mut self, view_id: ViewId) -> bool {
+        let current = self.selection(view_id).clone();
+        let history = self.selection_histories.entry(view_id).or_default();
+        match history.undo(&current).cloned() {
+            Some(selection) => {
+                self.set_selection(view_id, selection);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Goes forward to the selection of a view that was undone last. Returns whether there was
+    /// one.
+    pub fn redo_selection(&mut self, view_id: ViewId) -> bool {
+        let current = self.selection(view_id).clone();
+        let history = self.selection_histories.entry(view_id).or_default();
+        match history.redo(&current).cloned() {
+            Some(selection) => {
+                self.set_selection(view_id, selection);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The lines hidden by the folds of a view.
+    pub fn hidden_lines(&self, view_id: ViewId) -> HiddenLines {
+        self.folds(view_id)
+            .map(|folds| folds.hidden_lines(self.text.slice(..)))
+            .unwrap_or_default()
+    }
+
     pub fn relative_path(&self) -> Option<PathBuf> {
         self.path
             .as_deref()
@@ -1013,6 +1424,106 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_bom() {
+        let cases: &[(&[u8], &'static encoding_rs::Encoding)] = &[
+            (b"\xEF\xBB\xBFhello\n", encoding_rs::UTF_8),
+            (b"\xFF\xFEh\0e\0l\0l\0o\0\n\0", encoding_rs::UTF_16LE),
+            (b"\xFE\xFF\0h\0e\0l\0l\0o\0\n", encoding_rs::UTF_16BE),
+        ];
+        for &(input, expected_encoding) in cases {
+            let (text, encoding, has_bom) = from_reader(&mut &input[..], None).unwrap();
+            assert_eq!(text, "hello\n");
+            assert_eq!(encoding, expected_encoding);
+            assert!(has_bom);
+
+            let mut buf: Vec<u8> = Vec::new();
+            helix_lsp::block_on(to_writer(&mut buf, encoding, has_bom, &text)).unwrap();
+            assert_eq!(buf, input);
+        }
+
+        let (text, encoding, has_bom) = from_reader(&mut &b"hello\n"[..], None).unwrap();
+        assert_eq!(text, "hello\n");
+        assert_eq!(encoding, encoding_rs::UTF_8);
+        assert!(!has_bom);
+
+        // UTF-16 without a byte order mark isn't taken for binary
+        let cases: &[(&[u8], &'static encoding_rs::Encoding)] = &[
+            (b"h\0e\0l\0l\0o\0\n\0", encoding_rs::UTF_16LE),
+            (b"\0h\0e\0l\0l\0o\0\n", encoding_rs::UTF_16BE),
+        ];
+        for &(input, expected_encoding) in cases {
+            let (text, encoding, has_bom) = from_reader(&mut &input[..], None).unwrap();
+            assert_eq!(text, "hello\n");
+            assert_eq!(encoding, expected_encoding);
+            assert!(!has_bom);
+        }
+    }
+
+    #[test]
+    fn test_binary() {
+        let input: &[u8] = b"\x7fELF\x02\x01\x00\x00\r\n\xff text";
+        let (text, encoding, has_bom) = from_reader(&mut &input[..], None).unwrap();
+        assert_eq!(encoding, encoding_rs::X_USER_DEFINED);
+        assert!(!has_bom);
+        // one char per byte
+        assert_eq!(text.len_chars(), input.len());
+        assert_eq!(text.len_lines(), 1);
+        assert_eq!(text.char(1), 'E');
+        assert_eq!(binary_char_to_byte(text.char(10)), Some(0xff));
+
+        let mut buf: Vec<u8> = Vec::new();
+        helix_lsp::block_on(to_writer(&mut buf, encoding, has_bom, &text)).unwrap();
+        assert_eq!(buf, input);
+
+        // chars typed in that don't stand for a byte
+        let mut buf: Vec<u8> = Vec::new();
+        let text = Rope::from("ab\u{e9}");
+        assert!(helix_lsp::block_on(to_writer(&mut buf, encoding, false, &text)).is_err());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_large_file() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/encoding/big5_in.txt");
+        let doc = Document::open(&path, None, None, None, None).unwrap();
+        assert!(!doc.is_large_file());
+        // the size of the decoded text counts, not the size of the file
+        let len = doc.text().len_bytes() as u64;
+        assert_ne!(len, std::fs::metadata(&path).unwrap().len());
+
+        let doc = Document::open(&path, None, None, None, Some(len)).unwrap();
+        assert!(doc.is_large_file());
+
+        let doc = Document::open(&path, None, None, None, Some(len + 1)).unwrap();
+        assert!(!doc.is_large_file());
+    }
+
+    #[tokio::test]
+    async fn test_compressed() {
+        use std::io::Read;
+
+        let text = "{ \"compressed\": true }\n";
+        let path = std::env::temp_dir().join(format!("helix-test-{}.json.gz", std::process::id()));
+        std::fs::write(&path, Compression::Gzip.compress(text.as_bytes()).unwrap()).unwrap();
+
+        let mut doc = Document::open(&path, None, None, None, None).unwrap();
+        assert_eq!(doc.compression(), Some(Compression::Gzip));
+        assert_eq!(doc.text(), text);
+
+        doc.save().await.unwrap();
+        let saved = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(Compression::detect(&saved), Some(Compression::Gzip));
+        let mut decompressed = String::new();
+        Compression::Gzip
+            .decoder(&saved[..])
+            .unwrap()
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, text);
+    }
+
     macro_rules! test_decode {
         ($label:expr, $label_override:expr) => {
             let encoding = encoding_rs::Encoding::for_label($label_override.as_bytes()).unwrap();
@@ -1043,7 +1554,7 @@ mod test {
 
             let text = Rope::from_str(&std::fs::read_to_string(path).unwrap());
             let mut buf: Vec<u8> = Vec::new();
-            helix_lsp::block_on(to_writer(&mut buf, encoding, &text)).unwrap();
+            helix_lsp::block_on(to_writer(&mut buf, encoding, false, &text)).unwrap();
 
             let expectation = std::fs::read(ref_path).unwrap();
             assert_eq!(buf, expectation);
@@ -1,8 +1,12 @@
 use crate::{
     clipboard::{get_clipboard_provider, ClipboardProvider},
     graphics::{CursorKind, Rect},
+    state::SavedState,
+    swap::SwapFiles,
+    symbol_index::SymbolIndex,
     theme::{self, Theme},
     tree::Tree,
+    view::Jump,
     Document, DocumentId, RegisterSelection, View, ViewId,
 };
 
@@ -20,7 +24,7 @@ use anyhow::Error;
 pub use helix_core::diagnostic::Severity;
 pub use helix_core::register::Registers;
 use helix_core::syntax;
-use helix_core::Position;
+use helix_core::{Position, Selection};
 
 use serde::Deserialize;
 
@@ -39,6 +43,54 @@ pub struct Config {
     pub line_number: LineNumber,
     /// Middle click paste support. Defaults to true
     pub middle_click_paste: bool,
+    /// Whitespace rendering.
+    pub whitespace: WhitespaceConfig,
+    /// Columns at which to draw vertical rulers, 1-based. Defaults to none.
+    pub rulers: Vec<u16>,
+    /// Indentation guides.
+    pub indent_guides: IndentGuidesConfig,
+    /// Colour brackets by nesting depth using the `ui.bracket.N` theme scopes. Defaults to false.
+    pub rainbow_brackets: bool,
+    /// Pin the first lines of the syntax nodes the top of the view is inside of, like its
+    /// function, to the top of the view. Defaults to false.
+    pub sticky_context: bool,
+    /// Files with at least this many bytes of text are opened without syntax highlighting or a
+    /// language server. Defaults to 20 MiB.
+    pub large_file_threshold: u64,
+    /// Saving modified documents automatically.
+    pub auto_save: AutoSaveConfig,
+    /// Write swap files of modified documents to recover their changes after a crash, and warn
+    /// when a file is open in another instance. Defaults to true.
+    pub swap_file: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct AutoSaveConfig {
+    /// Save modified documents after this many milliseconds without input. Defaults to never.
+    pub after_delay: Option<u64>,
+    /// Save a modified document when a view switches to another one. Defaults to false.
+    pub buffer_switch: bool,
+    /// Save modified documents when the terminal loses focus. Defaults to false.
+    pub focus_lost: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct IndentGuidesConfig {
+    /// Whether to draw indent guides. Defaults to false.
+    pub render: bool,
+    /// Glyph used for the guides. Defaults to `│`.
+    pub character: char,
+}
+
+impl Default for IndentGuidesConfig {
+    fn default() -> Self {
+        Self {
+            render: false,
+            character: '│',
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
@@ -51,6 +103,105 @@ pub enum LineNumber {
     Relative,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct WhitespaceConfig {
+    /// Which kinds of whitespace to render. Defaults to none.
+    pub render: WhitespaceRender,
+    /// Glyphs used to render each kind of whitespace.
+    pub characters: WhitespaceCharacters,
+}
+
+impl Default for WhitespaceConfig {
+    fn default() -> Self {
+        Self {
+            render: WhitespaceRender::Basic(WhitespaceRenderValue::None),
+            characters: WhitespaceCharacters::default(),
+        }
+    }
+}
+
+/// Either a single value for all kinds of whitespace (`render = "all"`) or a table with
+/// a value per kind (`render = { space = "all", tab = "all" }`).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged, rename_all = "kebab-case")]
+pub enum WhitespaceRender {
+    Basic(WhitespaceRenderValue),
+    Specific {
+        default: Option<WhitespaceRenderValue>,
+        space: Option<WhitespaceRenderValue>,
+        nbsp: Option<WhitespaceRenderValue>,
+        tab: Option<WhitespaceRenderValue>,
+        newline: Option<WhitespaceRenderValue>,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WhitespaceRenderValue {
+    None,
+    All,
+}
+
+impl WhitespaceRender {
+    fn value(&self, get: impl Fn(&Self) -> Option<WhitespaceRenderValue>) -> WhitespaceRenderValue {
+        match self {
+            Self::Basic(value) => *value,
+            Self::Specific { default, .. } => get(self)
+                .or(*default)
+                .unwrap_or(WhitespaceRenderValue::None),
+        }
+    }
+
+    pub fn space(&self) -> WhitespaceRenderValue {
+        self.value(|render| match render {
+            Self::Specific { space, .. } => *space,
+            Self::Basic(_) => None,
+        })
+    }
+
+    pub fn nbsp(&self) -> WhitespaceRenderValue {
+        self.value(|render| match render {
+            Self::Specific { nbsp, .. } => *nbsp,
+            Self::Basic(_) => None,
+        })
+    }
+
+    pub fn tab(&self) -> WhitespaceRenderValue {
+        self.value(|render| match render {
+            Self::Specific { tab, .. } => *tab,
+            Self::Basic(_) => None,
+        })
+    }
+
+    pub fn newline(&self) -> WhitespaceRenderValue {
+        self.value(|render| match render {
+            Self::Specific { newline, .. } => *newline,
+            Self::Basic(_) => None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct WhitespaceCharacters {
+    pub space: char,
+    pub nbsp: char,
+    pub tab: char,
+    pub newline: char,
+}
+
+impl Default for WhitespaceCharacters {
+    fn default() -> Self {
+        Self {
+            space: '·',   // U+00B7
+            nbsp: '⍽',    // U+237D
+            tab: '→',     // U+2192
+            newline: '⏎', // U+23CE
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -64,6 +215,14 @@ impl Default for Config {
             },
             line_number: LineNumber::Absolute,
             middle_click_paste: true,
+            whitespace: WhitespaceConfig::default(),
+            rulers: Vec::new(),
+            indent_guides: IndentGuidesConfig::default(),
+            rainbow_brackets: false,
+            sticky_context: false,
+            large_file_threshold: 20 * 1024 * 1024,
+            auto_save: AutoSaveConfig::default(),
+            swap_file: true,
         }
     }
 }
@@ -81,6 +240,12 @@ pub struct Editor {
 
     pub syn_loader: Arc<syntax::Loader>,
     pub theme_loader: Arc<theme::Loader>,
+    /// Definitions in the workspace, for languages without a language server.
+    symbol_index: SymbolIndex,
+    /// Selections and registers kept between restarts.
+    pub saved_state: SavedState,
+    /// The swap files of the open documents.
+    pub swap_files: SwapFiles,
 
     pub status_msg: Option<(String, Severity)>,
 
@@ -116,6 +281,9 @@ impl Editor {
             language_servers,
             syn_loader: config_loader,
             theme_loader: themes,
+            symbol_index: SymbolIndex::default(),
+            saved_state: SavedState::default(),
+            swap_files: SwapFiles::default(),
             registers: Registers::default(),
             clipboard_provider: get_clipboard_provider(),
             status_msg: None,
@@ -135,6 +303,67 @@ impl Editor {
         self.status_msg = Some((error, Severity::Error));
     }
 
+    /// The definitions of the files in the working directory. It's empty until
+    /// [`Self::ensure_symbol_index`] was called.
+    pub fn symbol_index(&self) -> &SymbolIndex {
+        &self.symbol_index
+    }
+
+    /// Starts indexing the working directory in the background, unless that was already done.
+    /// Called by the commands that need the index, the first time they're used.
+    pub fn ensure_symbol_index(&mut self) {
+        if let Ok(cwd) = std::env::current_dir() {
+            self.symbol_index
+                .index_workspace(cwd, self.syn_loader.clone());
+        }
+    }
+
+    /// Reindexes the definitions of a document from its current text, usually once it's saved.
+    pub fn refresh_symbol_index(&mut self, doc_id: DocumentId) {
+        let doc = match self.documents.get(doc_id) {
+            Some(doc) => doc,
+            None => return,
+        };
+        if let (Some(path), Some(tags)) = (doc.path(), doc.definitions()) {
+            self.symbol_index.update(path.clone(), tags);
+        }
+    }
+
+    /// Loads the state saved by earlier instances and restores their registers.
+    pub fn load_state(&mut self) {
+        self.saved_state = SavedState::load();
+        for (name, values) in self.saved_state.registers() {
+            self.registers.write(name, values.clone());
+        }
+    }
+
+    /// Saves the selections of the open views and the registers for later instances.
+    pub fn save_state(&mut self) -> Result<(), Error> {
+        let views: Vec<_> = self.tree.views().map(|(view, _)| view.id).collect();
+        for view_id in views {
+            self.remember_selection(view_id);
+        }
+        self.saved_state.set_registers(&self.registers);
+        self.saved_state.save()
+    }
+
+    /// Writes the swap files of the documents that changed since they were last written.
+    pub fn write_swap_files(&mut self) {
+        if self.config.swap_file {
+            self.swap_files.update(&self.documents);
+        }
+    }
+
+    /// Remembers the selection of a view in the file it shows, for when the file is opened
+    /// again.
+    fn remember_selection(&mut self, view_id: ViewId) {
+        let view = self.tree.get(view_id);
+        let doc = &self.documents[view.doc];
+        if let (Some(path), Some(selection)) = (doc.path(), doc.selections().get(&view_id)) {
+            self.saved_state.set_selection(path, selection);
+        }
+    }
+
     pub fn set_theme(&mut self, theme: Theme) {
         let scopes = theme.scopes();
         for config in self
@@ -168,7 +397,6 @@ impl Editor {
 
     pub fn switch(&mut self, id: DocumentId, action: Action) {
         use crate::tree::Layout;
-        use helix_core::Selection;
 
         if !self.documents.contains_key(id) {
             log::error!("cannot switch to document that does not exist (anymore)");
@@ -177,8 +405,9 @@ impl Editor {
 
         match action {
             Action::Replace => {
+                self.remember_selection(self.tree.focus);
                 let view = view!(self);
-                let jump = (
+                let jump = Jump::new(
                     view.doc,
                     self.documents[view.doc].selection(view.id).clone(),
                 );
@@ -189,12 +418,11 @@ impl Editor {
                 view.doc = id;
                 view.offset = Position::default();
 
+                let selection = self.initial_selection(id);
                 let (view, doc) = current!(self);
 
                 // initialize selection for view
-                doc.selections
-                    .entry(view.id)
-                    .or_insert_with(|| Selection::point(0));
+                doc.selections.entry(view.id).or_insert(selection);
                 // TODO: reuse align_view
                 let pos = doc
                     .selection(view.id)
@@ -212,23 +440,39 @@ impl Editor {
                 let view = View::new(id);
                 let view_id = self.tree.split(view, Layout::Horizontal);
                 // initialize selection for view
+                let selection = self.initial_selection(id);
                 let doc = &mut self.documents[id];
-                doc.selections.insert(view_id, Selection::point(0));
+                doc.selections.insert(view_id, selection);
             }
             Action::VerticalSplit => {
                 let view = View::new(id);
                 let view_id = self.tree.split(view, Layout::Vertical);
                 // initialize selection for view
+                let selection = self.initial_selection(id);
                 let doc = &mut self.documents[id];
-                doc.selections.insert(view_id, Selection::point(0));
+                doc.selections.insert(view_id, selection);
             }
         }
 
         self._refresh();
     }
 
+    /// The selection of a new view of a document. A document that isn't shown yet starts at the
+    /// selection it was left with.
+    fn initial_selection(&self, id: DocumentId) -> Selection {
+        let doc = &self.documents[id];
+        doc.path()
+            .filter(|_| doc.selections.is_empty())
+            .and_then(|path| self.saved_state.selection(path, doc.text().slice(..)))
+            .unwrap_or_else(|| Selection::point(0))
+    }
+
     pub fn new_file(&mut self, action: Action) -> DocumentId {
-        let doc = Document::default();
+        self.new_file_from_document(action, Document::default())
+    }
+
+    /// Open a scratch buffer with the contents of `doc`.
+    pub fn new_file_from_document(&mut self, action: Action, doc: Document) -> DocumentId {
         let id = self.documents.insert(doc);
         self.documents[id].id = id;
         self.switch(id, action);
@@ -236,7 +480,14 @@ impl Editor {
     }
 
     pub fn open(&mut self, path: PathBuf, action: Action) -> Result<DocumentId, Error> {
-        let path = helix_core::path::get_canonicalized_path(&path)?;
+        let id = self.open_document(&path)?;
+        self.switch(id, action);
+        Ok(id)
+    }
+
+    /// Opens the document at `path` without showing it, or finds it if it's open already.
+    pub fn open_document(&mut self, path: &Path) -> Result<DocumentId, Error> {
+        let path = helix_core::path::get_canonicalized_path(path)?;
 
         let id = self
             .documents()
@@ -246,64 +497,128 @@ impl Editor {
         let id = if let Some(id) = id {
             id
         } else {
-            let mut doc = Document::open(&path, None, Some(&self.theme), Some(&self.syn_loader))?;
-
-            // try to find a language server based on the language name
-            let language_server = doc
-                .language
-                .as_ref()
-                .and_then(|language| self.language_servers.get(language).ok());
-
-            if let Some(language_server) = language_server {
-                doc.set_language_server(Some(language_server.clone()));
-
-                let language_id = doc
-                    .language()
-                    .and_then(|s| s.split('.').last()) // source.rust
-                    .map(ToOwned::to_owned)
-                    .unwrap_or_default();
-
-                tokio::spawn(language_server.text_document_did_open(
-                    doc.url().unwrap(),
-                    doc.version(),
-                    doc.text(),
-                    language_id,
-                ));
-            }
+            let doc = Document::open(
+                &path,
+                None,
+                Some(&self.theme),
+                Some(&self.syn_loader),
+                Some(self.config.large_file_threshold),
+            )?;
 
             let id = self.documents.insert(doc);
             self.documents[id].id = id;
+            self.launch_language_server(id);
+            if self.config.swap_file {
+                if let Some(warning) = self.swap_files.open(&self.documents[id]) {
+                    self.set_error(warning);
+                }
+            }
             id
         };
 
-        self.switch(id, action);
         Ok(id)
     }
 
+    /// Start the language server for the document's language and open the document in it.
+    /// Documents in large file mode don't get a language server.
+    fn launch_language_server(&mut self, doc_id: DocumentId) {
+        // get around borrowck issues
+        let language_servers = &mut self.language_servers;
+        let doc = &mut self.documents[doc_id];
+        if doc.is_large_file() {
+            return;
+        }
+
+        // try to find a language server based on the language name
+        let language_server = doc
+            .language
+            .as_ref()
+            .and_then(|language| language_servers.get(language).ok());
+
+        if let Some(language_server) = language_server {
+            doc.set_language_server(Some(language_server.clone()));
+
+            let language_id = doc
+                .language()
+                .and_then(|s| s.split('.').last()) // source.rust
+                .map(ToOwned::to_owned)
+                .unwrap_or_default();
+
+            tokio::spawn(language_server.text_document_did_open(
+                doc.url().unwrap(),
+                doc.version(),
+                doc.text(),
+                language_id,
+            ));
+        }
+    }
+
+    /// Override the detected language of a document, restarting its language server.
+    pub fn set_doc_language(
+        &mut self,
+        doc_id: DocumentId,
+        language_config: Option<Arc<syntax::LanguageConfiguration>>,
+    ) {
+        let doc = &mut self.documents[doc_id];
+        if let Some(language_server) = doc.language_server() {
+            tokio::spawn(language_server.text_document_did_close(doc.identifier()));
+        }
+        doc.set_language_server(None);
+        doc.set_language(Some(&self.theme), language_config, Some(&self.syn_loader));
+        self.launch_language_server(doc_id);
+    }
+
+    /// Take a document out of large file mode, turning syntax highlighting and the
+    /// language server on.
+    pub fn enable_full_features(&mut self, doc_id: DocumentId) {
+        let doc = &mut self.documents[doc_id];
+        if !doc.is_large_file() {
+            return;
+        }
+
+        doc.disable_large_file();
+        doc.detect_language(Some(&self.theme), &self.syn_loader);
+        doc.detect_indent_and_line_ending();
+        self.launch_language_server(doc_id);
+    }
+
     pub fn close(&mut self, id: ViewId, close_buffer: bool) {
-        let view = self.tree.get(self.tree.focus);
+        self.remember_selection(id);
+        let doc_id = self.tree.get(id).doc;
         // remove selection
-        self.documents[view.doc].selections.remove(&id);
+        self.documents[doc_id].selections.remove(&id);
+        self.documents[doc_id].folds.remove(&id);
+        self.documents[doc_id].selection_histories.remove(&id);
 
         if close_buffer {
-            // get around borrowck issues
-            let language_servers = &mut self.language_servers;
-            let doc = &self.documents[view.doc];
-
-            let language_server = doc
-                .language
-                .as_ref()
-                .and_then(|language| language_servers.get(language).ok());
-            if let Some(language_server) = language_server {
-                tokio::spawn(language_server.text_document_did_close(doc.identifier()));
-            }
-            self.documents.remove(view.doc);
+            self.close_document(doc_id);
         }
 
         self.tree.remove(id);
         self._refresh();
     }
 
+    /// Closes a document, dropping it from the jumplists. It shouldn't be shown in any view
+    /// but the one being closed.
+    pub fn close_document(&mut self, doc_id: DocumentId) {
+        // get around borrowck issues
+        let language_servers = &mut self.language_servers;
+        let doc = &self.documents[doc_id];
+
+        let language_server = doc
+            .language
+            .as_ref()
+            .and_then(|language| language_servers.get(language).ok());
+        if let Some(language_server) = language_server {
+            tokio::spawn(language_server.text_document_did_close(doc.identifier()));
+        }
+        let path = doc.path().cloned();
+        for (view, _) in self.tree.views_mut() {
+            view.jumps.close_document(doc_id, path.as_deref());
+        }
+        self.documents.remove(doc_id);
+    }
+
     pub fn resize(&mut self, area: Rect) {
         if self.tree.resize(area) {
             self._refresh();
@@ -151,6 +151,17 @@ impl From<crossterm::event::KeyCode> for KeyCode {
             CKeyCode::Char(character) => KeyCode::Char(character),
             CKeyCode::Null => KeyCode::Null,
             CKeyCode::Esc => KeyCode::Esc,
+            CKeyCode::CapsLock
+            | CKeyCode::ScrollLock
+            | CKeyCode::NumLock
+            | CKeyCode::PrintScreen
+            | CKeyCode::Pause
+            | CKeyCode::Menu
+            | CKeyCode::KeypadBegin
+            | CKeyCode::Media(_)
+            | CKeyCode::Modifier(_) => unreachable!(
+                "these keys are only reported with the keyboard enhancement flags, which aren't enabled"
+            ),
         }
     }
 }
@@ -2,6 +2,7 @@
 pub mod macros;
 
 pub mod clipboard;
+pub mod compression;
 pub mod document;
 pub mod editor;
 pub mod graphics;
@@ -9,6 +10,10 @@ pub mod info;
 pub mod input;
 pub mod keyboard;
 pub mod register_selection;
+pub mod session;
+pub mod state;
+pub mod swap;
+pub mod symbol_index;
 pub mod theme;
 pub mod tree;
 pub mod view;
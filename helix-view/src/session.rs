@@ -0,0 +1,316 @@
+//! Named sessions, saved to `sessions/<name>.toml` in the cache directory: the layout of the
+//! views, the file, selection and scroll position of each view, the working directory and the
+//! open files.
+
+use crate::{
+    editor::Action,
+    state,
+    tree::{Layout, Shape, Tree},
+    Document, DocumentId, Editor, ViewId,
+};
+use anyhow::{anyhow, bail, Context, Error};
+use helix_core::Position;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fs, io, path::PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    /// The working directory.
+    cwd: PathBuf,
+    /// The open files, also the ones not shown in a view.
+    buffers: Vec<PathBuf>,
+    /// The index of the focused view, in the order the views are shown.
+    focus: usize,
+    layout: Node,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Node {
+    View(ViewState),
+    Container { layout: Layout, children: Vec<Node> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ViewState {
+    /// The file shown, `None` for a scratch buffer.
+    path: Option<PathBuf>,
+    /// The anchor and head of each range of the selection, the primary range first.
+    selection: Vec<(usize, usize)>,
+    /// The first line and column shown.
+    offset: (usize, usize),
+}
+
+impl Session {
+    pub fn dir() -> PathBuf {
+        helix_core::cache_dir().join("sessions")
+    }
+
+    pub fn path(name: &str) -> Result<PathBuf, Error> {
+        if name.is_empty() || name.contains(std::path::is_separator) {
+            bail!("invalid session name: {}", name);
+        }
+        Ok(Self::dir().join(format!("{}.toml", name)))
+    }
+
+    /// The names of the saved sessions.
+    pub fn names() -> Vec<String> {
+        let entries = match fs::read_dir(Self::dir()) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != "toml" {
+                    return None;
+                }
+                Some(path.file_stem()?.to_str()?.to_string())
+            })
+            .collect()
+    }
+
+    /// The session of the views of `editor`.
+    pub fn new(editor: &Editor) -> Result<Self, Error> {
+        let cwd = std::env::current_dir().context("couldn't get the working directory")?;
+        let buffers = editor
+            .documents()
+            .filter_map(|doc| doc.path().cloned())
+            .collect();
+        let focus = editor
+            .tree
+            .traverse()
+            .position(|(view_id, _)| view_id == editor.tree.focus)
+            .unwrap_or(0);
+        Ok(Self {
+            cwd,
+            buffers,
+            focus,
+            layout: Node::new(editor, &editor.tree.shape()),
+        })
+    }
+
+    pub fn load(name: &str) -> Result<Self, Error> {
+        let path = Self::path(name)?;
+        let session = match fs::read_to_string(&path) {
+            Ok(session) => session,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                bail!("no session named {}", name)
+            }
+            Err(err) => return Err(err.into()),
+        };
+        toml::from_str(&session).with_context(|| format!("invalid session file {}", path.display()))
+    }
+
+    pub fn save(&self, name: &str) -> Result<(), Error> {
+        let path = Self::path(name)?;
+        fs::create_dir_all(Self::dir())?;
+        fs::write(&path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Replaces the views of `editor` with the views of the session.
+    pub fn restore(&self, editor: &mut Editor) -> Result<(), Error> {
+        // everything that can fail is done before the views are replaced, and the documents
+        // opened until then are closed again if it does
+        let existing: HashSet<_> = editor.documents.keys().collect();
+        let docs = match self.open_documents(editor) {
+            Ok(docs) => docs,
+            Err(err) => {
+                let opened: Vec<_> = editor
+                    .documents
+                    .keys()
+                    .filter(|doc_id| !existing.contains(doc_id))
+                    .collect();
+                for doc_id in opened {
+                    editor.close_document(doc_id);
+                }
+                return Err(err);
+            }
+        };
+        let first = docs[0];
+
+        let views: Vec<_> = editor.tree.views().map(|(view, _)| view.id).collect();
+        for view_id in views {
+            editor.close(view_id, false);
+        }
+        // the tree has no focus left to split
+        editor.tree = Tree::new(editor.tree.area());
+        editor.switch(first, Action::VerticalSplit);
+        self.layout.restore(editor, editor.tree.focus, &docs);
+
+        if let Some((view_id, _)) = editor.tree.traverse().nth(self.focus) {
+            editor.tree.focus = view_id;
+        }
+        Ok(())
+    }
+
+    /// Opens the buffers of the session and the documents of its views, in the order the
+    /// views are shown, and changes to its working directory.
+    fn open_documents(&self, editor: &mut Editor) -> Result<Vec<DocumentId>, Error> {
+        if self.layout.view_count() == 0 {
+            bail!("the session has no views");
+        }
+        for path in &self.buffers {
+            if let Err(err) = editor.open_document(path) {
+                log::warn!("failed to open {}: {:#}", path.display(), err);
+            }
+        }
+        let mut docs = Vec::new();
+        self.layout.documents(editor, &mut docs)?;
+
+        std::env::set_current_dir(&self.cwd).with_context(|| {
+            format!(
+                "couldn't change the working directory to {}",
+                self.cwd.display()
+            )
+        })?;
+        Ok(docs)
+    }
+}
+
+impl Node {
+    fn new(editor: &Editor, shape: &Shape) -> Self {
+        match shape {
+            &Shape::View(view_id) => {
+                let view = editor.tree.get(view_id);
+                let doc = &editor.documents[view.doc];
+                Self::View(ViewState {
+                    path: doc.path().cloned(),
+                    selection: state::to_ranges(doc.selection(view_id)),
+                    offset: (view.offset.row, view.offset.col),
+                })
+            }
+            Shape::Container(layout, children) => Self::Container {
+                layout: *layout,
+                children: children
+                    .iter()
+                    .map(|child| Self::new(editor, child))
+                    .collect(),
+            },
+        }
+    }
+
+    fn view_count(&self) -> usize {
+        match self {
+            Self::View(_) => 1,
+            Self::Container { children, .. } => children.iter().map(Self::view_count).sum(),
+        }
+    }
+
+    /// Opens the document of each view, in the order the views are shown.
+    fn documents(&self, editor: &mut Editor, docs: &mut Vec<DocumentId>) -> Result<(), Error> {
+        match self {
+            Self::View(ViewState {
+                path: Some(path), ..
+            }) => {
+                let doc = editor
+                    .open_document(path)
+                    .map_err(|err| anyhow!("failed to open {}: {:#}", path.display(), err))?;
+                docs.push(doc);
+            }
+            Self::View(ViewState { path: None, .. }) => {
+                let doc = editor.documents.insert(Document::default());
+                editor.documents[doc].id = doc;
+                docs.push(doc);
+            }
+            Self::Container { children, .. } => {
+                for child in children {
+                    child.documents(editor, docs)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Restores the node in the view `view_id`, which shows the document of its first view,
+    /// splitting it for the other views. `docs` are the documents of the views of the node.
+    fn restore(&self, editor: &mut Editor, view_id: ViewId, docs: &[DocumentId]) {
+        let (layout, children) = match self {
+            Self::View(view) => return view.restore(editor, view_id),
+            Self::Container { layout, children } => (layout, children),
+        };
+        let action = match layout {
+            Layout::Horizontal => Action::HorizontalSplit,
+            Layout::Vertical => Action::VerticalSplit,
+        };
+        let children: Vec<_> = children
+            .iter()
+            .map(|child| (child, child.view_count()))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+
+        // the views of the children, each split off the one before
+        editor.tree.focus = view_id;
+        let mut views = vec![view_id];
+        let mut start = 0;
+        for window in children.windows(2) {
+            start += window[0].1;
+            editor.switch(docs[start], action);
+            views.push(editor.tree.focus);
+        }
+
+        let mut start = 0;
+        for ((child, count), view_id) in children.into_iter().zip(views) {
+            child.restore(editor, view_id, &docs[start..start + count]);
+            start += count;
+        }
+    }
+}
+
+impl ViewState {
+    fn restore(&self, editor: &mut Editor, view_id: ViewId) {
+        let view = editor.tree.get_mut(view_id);
+        let doc = &mut editor.documents[view.doc];
+        if let Some(selection) = state::from_ranges(&self.selection, doc.text().slice(..)) {
+            doc.set_selection(view_id, selection);
+        }
+        let (row, col) = self.offset;
+        let last_line = doc.text().len_lines().saturating_sub(1);
+        view.offset = Position::new(row.min(last_line), col);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_file() {
+        let view = |path: Option<&str>| {
+            Node::View(ViewState {
+                path: path.map(PathBuf::from),
+                selection: vec![(1, 2), (5, 5)],
+                offset: (3, 0),
+            })
+        };
+        let session = Session {
+            cwd: "/tmp".into(),
+            buffers: vec!["/tmp/a".into(), "/tmp/b".into()],
+            focus: 1,
+            layout: Node::Container {
+                layout: Layout::Vertical,
+                children: vec![
+                    view(Some("/tmp/a")),
+                    Node::Container {
+                        layout: Layout::Horizontal,
+                        children: vec![view(Some("/tmp/b")), view(None)],
+                    },
+                ],
+            },
+        };
+        assert_eq!(session.layout.view_count(), 3);
+
+        let toml = toml::to_string(&session).unwrap();
+        assert_eq!(toml::from_str::<Session>(&toml).unwrap(), session);
+    }
+
+    #[test]
+    fn test_path() {
+        assert!(Session::path("work")
+            .unwrap()
+            .ends_with("sessions/work.toml"));
+        assert!(Session::path("").is_err());
+        assert!(Session::path("../work").is_err());
+    }
+}
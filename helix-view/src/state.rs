@@ -0,0 +1,339 @@
+//! State kept between restarts in `state.toml` in the cache directory: the last selection in
+//! each file, the prompt histories and the named registers. Each instance merges its state
+//! into the file when it exits, under a lock file, so that instances running at the same time
+//! don't lose each other's state.
+
+use anyhow::{Context, Error};
+use helix_core::{register::Registers, Range, RopeSlice, Selection};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// The number of files whose selection is kept, the most recently left ones.
+const MAX_FILES: usize = 1000;
+/// The number of entries kept of each prompt history.
+const MAX_HISTORY: usize = 100;
+/// The registers holding the command, search, selection regex and shell prompt histories.
+const HISTORY_REGISTERS: &[char] = &[':', '/', '\\', '|'];
+
+/// How long to wait for another instance to finish writing the state file.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+/// A lock older than this was left behind by an instance that didn't exit cleanly.
+const STALE_LOCK: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedState {
+    /// The registers by name, a string since TOML keys have to be.
+    registers: BTreeMap<String, Vec<String>>,
+    files: HashMap<PathBuf, FileState>,
+    /// The registers as they were loaded, to tell what changed in this instance.
+    #[serde(skip)]
+    loaded_registers: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct FileState {
+    /// The anchor and head of each range of the selection, the primary range first.
+    selection: Vec<(usize, usize)>,
+    /// When the file was left, in seconds since the unix epoch.
+    time: u64,
+}
+
+impl SavedState {
+    pub fn path() -> PathBuf {
+        helix_core::cache_dir().join("state.toml")
+    }
+
+    /// Loads the state file, or starts with an empty state if it can't be read.
+    pub fn load() -> Self {
+        match Self::read(&Self::path()) {
+            Ok(mut state) => {
+                state.loaded_registers = state.registers.clone();
+                state
+            }
+            Err(err) => {
+                log::warn!("failed to load state: {:#}", err);
+                Self::default()
+            }
+        }
+    }
+
+    fn read(path: &Path) -> Result<Self, Error> {
+        match fs::read_to_string(path) {
+            Ok(state) => toml::from_str(&state).context("invalid state file"),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Merges this state into the state file.
+    pub fn save(&self) -> Result<(), Error> {
+        let path = Self::path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let _lock = Lock::acquire(path.with_extension("toml.lock"))?;
+        let mut state = Self::read(&path).unwrap_or_default();
+        state.merge(self);
+
+        // a reader never sees a partly written file
+        let tmp = path.with_extension(format!("toml.{}", std::process::id()));
+        fs::write(&tmp, toml::to_string(&state)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// Merges `ours` into the state read from the state file, keeping what other instances
+    /// saved in the meantime.
+    fn merge(&mut self, ours: &Self) {
+        for (path, file) in &ours.files {
+            match self.files.get(path) {
+                Some(theirs) if theirs.time > file.time => (),
+                _ => {
+                    self.files.insert(path.clone(), file.clone());
+                }
+            }
+        }
+        if self.files.len() > MAX_FILES {
+            let mut times: Vec<_> = self.files.values().map(|file| file.time).collect();
+            times.sort_unstable_by(|a, b| b.cmp(a));
+            let oldest = times[MAX_FILES - 1];
+            self.files.retain(|_, file| file.time >= oldest);
+        }
+
+        for (name, values) in &ours.registers {
+            let loaded = ours.loaded_registers.get(name);
+            if is_history(name) {
+                // only the entries added by this instance
+                let new = match loaded {
+                    Some(loaded) if values.starts_with(loaded) => &values[loaded.len()..],
+                    _ => &values[..],
+                };
+                let history = self.registers.entry(name.clone()).or_default();
+                for value in new {
+                    history.retain(|entry| entry != value);
+                    history.push(value.clone());
+                }
+                let excess = history.len().saturating_sub(MAX_HISTORY);
+                history.drain(..excess);
+            } else if loaded != Some(values) {
+                self.registers.insert(name.clone(), values.clone());
+            }
+        }
+    }
+
+    /// The last selection in the file at `path`, if it still fits in its `text`.
+    pub fn selection(&self, path: &Path, text: RopeSlice) -> Option<Selection> {
+        let file = self.files.get(path)?;
+        from_ranges(&file.selection, text)
+    }
+
+    /// Remembers `selection` as the last selection in the file at `path`.
+    pub fn set_selection(&mut self, path: &Path, selection: &Selection) {
+        // paths are keys of the state file, which has to be valid UTF-8
+        if path.to_str().is_none() {
+            return;
+        }
+        let ranges = to_ranges(selection);
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default();
+        self.files.insert(
+            path.to_path_buf(),
+            FileState {
+                selection: ranges,
+                time,
+            },
+        );
+    }
+
+    /// The prompt histories and named registers.
+    pub fn registers(&self) -> impl Iterator<Item = (char, &Vec<String>)> {
+        self.registers.iter().filter_map(|(name, values)| {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(name), None) => Some((name, values)),
+                _ => None,
+            }
+        })
+    }
+
+    /// Remembers the prompt histories and named registers in `registers`.
+    pub fn set_registers(&mut self, registers: &Registers) {
+        for register in registers.iter() {
+            let name = register.name();
+            if HISTORY_REGISTERS.contains(&name) || name.is_ascii_alphabetic() {
+                let values = register.read();
+                let values = if HISTORY_REGISTERS.contains(&name) {
+                    &values[values.len().saturating_sub(MAX_HISTORY)..]
+                } else {
+                    values
+                };
+                self.registers.insert(name.to_string(), values.to_vec());
+            }
+        }
+    }
+}
+
+/// The anchor and head of each range of `selection`, the primary range first.
+pub(crate) fn to_ranges(selection: &Selection) -> Vec<(usize, usize)> {
+    let primary = selection.primary();
+    std::iter::once(primary)
+        .chain(selection.iter().copied().filter(|&range| range != primary))
+        .map(|range| (range.anchor, range.head))
+        .collect()
+}
+
+/// The selection of `ranges` saved by [`to_ranges`], if it still fits in `text`. Ranges past
+/// its end are dropped.
+pub(crate) fn from_ranges(ranges: &[(usize, usize)], text: RopeSlice) -> Option<Selection> {
+    let len = text.len_chars();
+    let fits = |&(anchor, head): &(usize, usize)| anchor <= len && head <= len;
+    if !matches!(ranges.first(), Some(primary) if fits(primary)) {
+        return None;
+    }
+    let ranges = ranges
+        .iter()
+        .filter(|range| fits(range))
+        .map(|&(anchor, head)| Range::new(anchor, head))
+        .collect();
+    Some(Selection::new(ranges, 0).ensure_invariants(text))
+}
+
+fn is_history(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!((chars.next(), chars.next()), (Some(name), None) if HISTORY_REGISTERS.contains(&name))
+}
+
+/// A lock file, removed again when dropped.
+struct Lock(PathBuf);
+
+impl Lock {
+    fn acquire(path: PathBuf) -> Result<Self, Error> {
+        let start = Instant::now();
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => return Ok(Self(path)),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    let age = fs::metadata(&path)
+                        .and_then(|metadata| metadata.modified())
+                        .ok()
+                        .and_then(|modified| modified.elapsed().ok());
+                    let stale = matches!(age, Some(age) if age > STALE_LOCK);
+                    if stale {
+                        let _ = fs::remove_file(&path);
+                    } else if start.elapsed() > LOCK_TIMEOUT {
+                        return Err(Error::new(err).context("state file is locked"));
+                    } else {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helix_core::{Rope, SmallVec};
+
+    #[test]
+    fn test_selection() {
+        let mut state = SavedState::default();
+        let path = Path::new("/tmp/a.txt");
+        let selection = Selection::new(
+            SmallVec::from_slice(&[Range::new(0, 2), Range::new(4, 6)]),
+            0,
+        );
+        state.set_selection(path, &selection);
+
+        let text = Rope::from("abcdefgh");
+        assert_eq!(state.selection(path, text.slice(..)), Some(selection));
+
+        // ranges past the end of the changed file are dropped
+        let text = Rope::from("abc");
+        assert_eq!(
+            state.selection(path, text.slice(..)),
+            Some(Selection::single(0, 2))
+        );
+        let text = Rope::from("a");
+        assert_eq!(state.selection(path, text.slice(..)), None);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut theirs = SavedState::default();
+        theirs
+            .registers
+            .insert(":".into(), vec!["w".into(), "q".into()]);
+        theirs.registers.insert("\\".into(), vec!["fn".into()]);
+        theirs.registers.insert("a".into(), vec!["theirs".into()]);
+        theirs.registers.insert("b".into(), vec!["theirs".into()]);
+        theirs.files.insert(
+            "/a".into(),
+            FileState {
+                selection: vec![(1, 1)],
+                time: 20,
+            },
+        );
+
+        let mut ours = SavedState::default();
+        ours.loaded_registers.insert(":".into(), vec!["w".into()]);
+        ours.loaded_registers
+            .insert("a".into(), vec!["loaded".into()]);
+        ours.loaded_registers
+            .insert("b".into(), vec!["loaded".into()]);
+        ours.registers = ours.loaded_registers.clone();
+        ours.registers
+            .get_mut(":")
+            .unwrap()
+            .extend(["w".into(), "e".into()]);
+        ours.registers
+            .insert("\\".into(), vec!["fn".into(), "let".into()]);
+        ours.registers.insert("b".into(), vec!["ours".into()]);
+        for (path, time) in [("/a", 10), ("/b", 10)] {
+            ours.files.insert(
+                path.into(),
+                FileState {
+                    selection: vec![(2, 2)],
+                    time,
+                },
+            );
+        }
+
+        theirs.merge(&ours);
+        assert_eq!(theirs.registers[":"], ["q", "w", "e"]);
+        assert_eq!(theirs.registers["\\"], ["fn", "let"]);
+        // unchanged registers don't overwrite newer ones
+        assert_eq!(theirs.registers["a"], ["theirs"]);
+        assert_eq!(theirs.registers["b"], ["ours"]);
+        assert_eq!(theirs.files[Path::new("/a")].time, 20);
+        assert_eq!(theirs.files[Path::new("/b")].time, 10);
+
+        // the state file round trips
+        let toml = toml::to_string(&theirs).unwrap();
+        let state: SavedState = toml::from_str(&toml).unwrap();
+        assert_eq!(state.registers, theirs.registers);
+        assert_eq!(state.files, theirs.files);
+    }
+}
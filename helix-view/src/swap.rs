@@ -0,0 +1,445 @@
+//! Swap files in `swap/` in the cache directory, to recover unsaved changes after a crash.
+//! Every instance keeps a swap file for each file it has open, which holds a snapshot of the
+//! document while it has unsaved changes. Swap files are removed when the document is closed,
+//! so one left behind by an instance that isn't running anymore means it crashed, and one of a
+//! running instance means the file is open twice.
+//!
+//! Swap files are named by a hash of the path of their file and the pid of their instance, and
+//! start with the path, to tell apart files whose paths hash the same. They're written and
+//! removed by a thread of their own, so that writing large documents doesn't hold up the
+//! editor.
+
+use crate::{Document, DocumentId};
+use anyhow::{bail, Error};
+use helix_core::Rope;
+use slotmap::SlotMap;
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+/// How often the swap files of changed documents are written.
+pub const INTERVAL: Duration = Duration::from_secs(4);
+
+const MODIFIED: &str = "helix swap file: modified\n";
+const SAVED: &str = "helix swap file: saved\n";
+
+#[derive(Debug, Default)]
+pub struct SwapFiles {
+    /// The file each document's swap file was written for and the version of the document in
+    /// it, `None` if the document had no unsaved changes.
+    written: HashMap<DocumentId, (PathBuf, Option<i32>)>,
+    /// Swap files with unsaved changes left behind by instances that crashed.
+    found: HashMap<DocumentId, SwapFile>,
+    /// The thread writing the swap files, started on the first write.
+    writer: Option<Writer>,
+}
+
+#[derive(Debug)]
+struct Writer {
+    sender: mpsc::Sender<Job>,
+    thread: thread::JoinHandle<()>,
+}
+
+#[derive(Debug)]
+enum Job {
+    /// Writes the swap file of this instance for a file, with a snapshot of its document if it
+    /// has unsaved changes.
+    Write(PathBuf, Option<Rope>),
+    /// Removes the swap file of this instance for a file.
+    Remove(PathBuf),
+}
+
+/// A swap file of another instance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwapFile {
+    path: PathBuf,
+    pid: u32,
+    /// The file it's the swap file of.
+    file: PathBuf,
+}
+
+impl SwapFiles {
+    pub fn dir() -> PathBuf {
+        helix_core::cache_dir().join("swap")
+    }
+
+    /// Looks for the swap files other instances have of the file of a document that was just
+    /// opened, and writes the document's own. Returns a warning to show if there are any.
+    pub fn open(&mut self, doc: &Document) -> Option<String> {
+        let path = doc.path()?;
+        let mut warning = None;
+        for swap in SwapFile::find(&Self::dir(), path) {
+            if swap.is_running() {
+                warning = Some(format!(
+                    "{} is already open in another instance (pid {})",
+                    path.display(),
+                    swap.pid
+                ));
+            } else if swap.has_newer_changes(path, doc.text()) {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                warning = Some(format!(
+                    "a swap file has unsaved changes to {}: :recover, :recover-diff or \
+                     :recover-discard",
+                    name
+                ));
+                self.found.insert(doc.id(), swap);
+            } else {
+                swap.remove();
+            }
+        }
+        self.write(doc);
+        warning
+    }
+
+    /// Writes the swap files of the documents that changed since they were last written, and
+    /// removes the ones of closed documents.
+    pub fn update(&mut self, documents: &SlotMap<DocumentId, Document>) {
+        for (_, doc) in documents {
+            self.write(doc);
+        }
+        let (closed, written): (HashMap<_, _>, HashMap<_, _>) = self
+            .written
+            .drain()
+            .partition(|(doc_id, _)| !documents.contains_key(*doc_id));
+        self.written = written;
+        for (path, _) in closed.into_values() {
+            self.send(Job::Remove(path));
+        }
+        self.found
+            .retain(|&doc_id, _| documents.contains_key(doc_id));
+    }
+
+    fn write(&mut self, doc: &Document) {
+        let path = match doc.path() {
+            Some(path) => path,
+            None => return,
+        };
+        let version = if doc.is_modified() {
+            Some(doc.version())
+        } else {
+            None
+        };
+        match self.written.get(&doc.id()) {
+            Some((written, written_version)) if written == path && *written_version == version => {
+                return
+            }
+            // the document was written to another file
+            Some((written, _)) if written != path => {
+                let written = written.clone();
+                self.send(Job::Remove(written));
+            }
+            _ => (),
+        }
+
+        // cloning the rope only shares its chunks, the thread writes them out
+        let text = version.map(|_| doc.text().clone());
+        self.send(Job::Write(path.clone(), text));
+        self.written.insert(doc.id(), (path.clone(), version));
+    }
+
+    fn send(&mut self, job: Job) {
+        let writer = self.writer.get_or_insert_with(Writer::spawn);
+        if writer.sender.send(job).is_err() {
+            log::error!("the thread writing swap files stopped");
+        }
+    }
+
+    /// Removes all swap files of this instance, when it exits. Waits for the writes still
+    /// queued first, so that none of them writes a swap file again afterwards.
+    pub fn remove_all(&mut self) {
+        if let Some(Writer { sender, thread }) = self.writer.take() {
+            drop(sender);
+            if thread.join().is_err() {
+                log::error!("the thread writing swap files panicked");
+            }
+        }
+        for (_, (path, _)) in self.written.drain() {
+            remove(&path);
+        }
+    }
+
+    /// The swap file with unsaved changes found when the document was opened.
+    pub fn found(&self, doc_id: DocumentId) -> Option<&SwapFile> {
+        self.found.get(&doc_id)
+    }
+
+    /// Removes the swap file found for the document, once it's recovered or discarded.
+    pub fn discard(&mut self, doc_id: DocumentId) {
+        if let Some(swap) = self.found.remove(&doc_id) {
+            swap.remove();
+        }
+    }
+}
+
+impl Writer {
+    fn spawn() -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let thread = thread::spawn(move || {
+            #[cfg(windows)]
+            let lock = lock(&SwapFiles::dir())
+                .map_err(|err| log::error!("failed to create swap lock file: {}", err))
+                .ok();
+            for job in receiver {
+                job.run();
+            }
+            #[cfg(windows)]
+            if let Some(lock) = lock {
+                drop(lock);
+                remove_file(&lock_path(&SwapFiles::dir(), std::process::id()));
+            }
+        });
+        Self { sender, thread }
+    }
+}
+
+impl Job {
+    fn run(self) {
+        match self {
+            Self::Write(path, text) => {
+                if let Err(err) = write(&SwapFiles::dir(), &path, text.as_ref()) {
+                    log::error!("failed to write swap file of {}: {:#}", path.display(), err);
+                }
+            }
+            Self::Remove(path) => remove(&path),
+        }
+    }
+}
+
+impl SwapFile {
+    /// The swap files of other instances for the file at `path`.
+    fn find(dir: &Path, file: &Path) -> Vec<Self> {
+        let prefix = hash(file);
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                let name = path.file_name()?.to_str()?.strip_suffix(".swp")?;
+                let (name, pid) = name.rsplit_once('.')?;
+                let pid = pid.parse().ok()?;
+                if name != prefix || pid == std::process::id() {
+                    return None;
+                }
+                let swap = Self {
+                    path,
+                    pid,
+                    file: file.to_path_buf(),
+                };
+                swap.contents().ok()?;
+                Some(swap)
+            })
+            .collect()
+    }
+
+    /// The snapshot of the document in the swap file, `None` if it had no unsaved changes.
+    pub fn read(&self) -> Result<Option<Rope>, Error> {
+        Ok(self.contents()?.map(Rope::from))
+    }
+
+    /// The text after the header, `None` if the document had no unsaved changes. Fails if the
+    /// swap file is of another file whose path has the same hash.
+    fn contents(&self) -> Result<Option<String>, Error> {
+        let contents = fs::read_to_string(&self.path)?;
+        let (modified, rest) = match (
+            contents.strip_prefix(MODIFIED),
+            contents.strip_prefix(SAVED),
+        ) {
+            (Some(rest), _) => (true, rest),
+            (_, Some(rest)) => (false, rest),
+            _ => bail!("invalid swap file {}", self.path.display()),
+        };
+        match rest.strip_prefix(&header(&self.file)) {
+            Some(text) if modified => Ok(Some(text.to_string())),
+            Some(_) => Ok(None),
+            None => bail!(
+                "swap file {} isn't of {}",
+                self.path.display(),
+                self.file.display()
+            ),
+        }
+    }
+
+    /// Whether the swap file has changes to the file at `path` that were made after the file
+    /// was last written.
+    fn has_newer_changes(&self, path: &Path, text: &Rope) -> bool {
+        let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified());
+        let newer = match (modified(&self.path), modified(path)) {
+            (Ok(swap), Ok(file)) => swap > file,
+            (Ok(_), Err(_)) => true,
+            _ => false,
+        };
+        newer && matches!(self.read(), Ok(Some(swapped)) if swapped != *text)
+    }
+
+    /// Whether the instance that wrote the swap file is still running.
+    fn is_running(&self) -> bool {
+        is_running(self.pid)
+    }
+
+    fn remove(&self) {
+        remove_file(&self.path);
+    }
+}
+
+/// The start of the file name of swap files for `path`: its FNV-1a hash, which is the same
+/// across versions of helix unlike the hashers of the standard library. File names made of
+/// the whole path would be too long for deep paths.
+fn hash(path: &Path) -> String {
+    let hash = path
+        .to_string_lossy()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("{:016x}", hash)
+}
+
+/// The line after the first one of swap files, the path of their file.
+fn header(path: &Path) -> String {
+    format!("{}\n", path.to_string_lossy())
+}
+
+fn swap_path(dir: &Path, path: &Path, pid: u32) -> PathBuf {
+    dir.join(format!("{}.{}.swp", hash(path), pid))
+}
+
+fn write(dir: &Path, path: &Path, text: Option<&Rope>) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let pid = std::process::id();
+    let mut contents = String::from(if text.is_some() { MODIFIED } else { SAVED });
+    contents.push_str(&header(path));
+    if let Some(text) = text {
+        contents.extend(text.chunks());
+    }
+    // the swap file is never partly written if the editor crashes
+    let tmp = dir.join(format!("{}.{}.tmp", hash(path), pid));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // only the user may read the unsaved changes, whoever may read the file
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(&tmp)?.write_all(contents.as_bytes())?;
+    fs::rename(&tmp, swap_path(dir, path, pid))
+}
+
+/// Removes the swap file of this instance for the file at `path`.
+fn remove(path: &Path) {
+    remove_file(&swap_path(&SwapFiles::dir(), path, std::process::id()));
+}
+
+fn remove_file(swap: &Path) {
+    if let Err(err) = fs::remove_file(swap) {
+        if err.kind() != io::ErrorKind::NotFound {
+            log::error!("failed to remove swap file {}: {}", swap.display(), err);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    use std::convert::TryFrom;
+
+    let pid = match libc::pid_t::try_from(pid) {
+        Ok(pid) if pid > 0 => pid,
+        _ => return false,
+    };
+    // signal 0 only checks whether the process exists
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// The lock file of the instance with `pid`, which it holds open while it's running.
+#[cfg(windows)]
+fn lock_path(dir: &Path, pid: u32) -> PathBuf {
+    dir.join(format!("{}.lock", pid))
+}
+
+/// Creates the lock file of this instance and opens it without sharing it, so that other
+/// instances fail to open it for as long as it's held.
+#[cfg(windows)]
+fn lock(dir: &Path) -> io::Result<fs::File> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    fs::create_dir_all(dir)?;
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .share_mode(0)
+        .open(lock_path(dir, std::process::id()))
+}
+
+#[cfg(windows)]
+fn is_running(pid: u32) -> bool {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    // the lock file is left behind by an instance that crashed, but can be opened again
+    let lock = fs::OpenOptions::new()
+        .read(true)
+        .share_mode(0)
+        .open(lock_path(&SwapFiles::dir(), pid));
+    match lock {
+        Ok(_) => false,
+        Err(err) => err.kind() != io::ErrorKind::NotFound,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let dir = std::env::temp_dir().join(format!("helix-swap-test-{}", std::process::id()));
+        let path = Path::new("/tmp/a.txt");
+        let text = Rope::from("unsaved\n");
+
+        // an instance that isn't running anymore
+        fs::create_dir_all(&dir).unwrap();
+        let other = swap_path(&dir, path, i32::MAX as u32);
+        fs::write(&other, format!("{}{}unsaved\n", MODIFIED, header(path))).unwrap();
+        let b = Path::new("/tmp/b.txt");
+        fs::write(swap_path(&dir, b, 1), format!("{}{}", SAVED, header(b))).unwrap();
+        // a file whose path hashes the same
+        let c = Path::new("/tmp/c.txt");
+        fs::write(swap_path(&dir, path, 2), format!("{}{}", SAVED, header(c))).unwrap();
+        // the swap file of this instance isn't found
+        write(&dir, path, Some(&text)).unwrap();
+
+        let found = SwapFile::find(&dir, path);
+        assert_eq!(
+            found,
+            [SwapFile {
+                path: other,
+                pid: i32::MAX as u32,
+                file: path.to_path_buf(),
+            }]
+        );
+        assert_eq!(found[0].read().unwrap(), Some(text));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            assert!(!found[0].is_running());
+            assert!(is_running(std::process::id()));
+            let swap = fs::metadata(swap_path(&dir, path, std::process::id())).unwrap();
+            assert_eq!(swap.permissions().mode() & 0o777, 0o600);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_hash() {
+        assert_ne!(hash(Path::new("/a%b")), hash(Path::new("/a/b")));
+        let deep = "/dir".repeat(100);
+        assert_eq!(hash(Path::new(&deep)).len(), 16);
+    }
+}
@@ -0,0 +1,118 @@
+//! The definitions of the files in the workspace, found with the tags queries of their
+//! languages. It's built in the background the first time it's used and files are
+//! reindexed when they're saved.
+
+use helix_core::{syntax::Loader, tags::Tag, Rope};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+};
+
+/// Larger files aren't indexed, they're most likely generated.
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+/// The walk stops this deep below the root...
+const MAX_DEPTH: usize = 16;
+/// ...or after this many files, so that opening helix in the home directory doesn't index
+/// all of it.
+const MAX_FILES: usize = 20_000;
+
+#[derive(Debug, Default, Clone)]
+pub struct SymbolIndex {
+    files: Arc<RwLock<HashMap<PathBuf, Vec<Tag>>>>,
+    started: Arc<AtomicBool>,
+    indexing: Arc<AtomicBool>,
+}
+
+impl SymbolIndex {
+    /// Indexes the files under `root` in a background thread, unless that was already done.
+    /// Ignored and hidden files are skipped like in the file picker.
+    pub fn index_workspace(&self, root: PathBuf, loader: Arc<Loader>) {
+        if self.started.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.indexing.store(true, Ordering::SeqCst);
+        let files = self.files.clone();
+        let indexing = self.indexing.clone();
+        std::thread::spawn(move || {
+            let walk = ignore::WalkBuilder::new(&root)
+                .max_depth(Some(MAX_DEPTH))
+                .build()
+                .flatten()
+                .filter(|entry| matches!(entry.file_type(), Some(file_type) if file_type.is_file()))
+                .take(MAX_FILES);
+            for entry in walk {
+                if let Some(tags) = index_file(&loader, entry.path()) {
+                    files
+                        .write()
+                        .unwrap()
+                        .insert(entry.path().to_path_buf(), tags);
+                }
+            }
+            indexing.store(false, Ordering::SeqCst);
+        });
+    }
+
+    /// Whether the workspace is still being indexed, so that not all definitions are known.
+    pub fn is_indexing(&self) -> bool {
+        self.indexing.load(Ordering::SeqCst)
+    }
+
+    /// Replaces the definitions of the file at `path`.
+    pub fn update(&self, path: PathBuf, tags: Vec<Tag>) {
+        self.files.write().unwrap().insert(path, tags);
+    }
+
+    /// All definitions, by file.
+    pub fn definitions(&self) -> Vec<(PathBuf, Tag)> {
+        let files = self.files.read().unwrap();
+        let mut definitions: Vec<_> = files
+            .iter()
+            .flat_map(|(path, tags)| tags.iter().map(move |tag| (path.clone(), tag.clone())))
+            .collect();
+        definitions.sort_by(|(a, _), (b, _)| a.cmp(b));
+        definitions
+    }
+
+    /// The definitions named `name`.
+    pub fn find(&self, name: &str) -> Vec<(PathBuf, Tag)> {
+        let files = self.files.read().unwrap();
+        let mut definitions: Vec<_> = files
+            .iter()
+            .flat_map(|(path, tags)| {
+                tags.iter()
+                    .filter(|tag| tag.name == name)
+                    .map(move |tag| (path.clone(), tag.clone()))
+            })
+            .collect();
+        definitions.sort_by(|(a, _), (b, _)| a.cmp(b));
+        definitions
+    }
+}
+
+/// The definitions in the file at `path`, if its language has a tags query.
+fn index_file(loader: &Loader, path: &Path) -> Option<Vec<Tag>> {
+    let language_config = loader.language_config_for_file_name(path);
+    match &language_config {
+        Some(language_config) => {
+            language_config.tags_config()?;
+        }
+        // only scripts without an extension are detected by their contents, so that not
+        // every file has to be read
+        None if path.extension().is_some() => return None,
+        None => (),
+    }
+    if fs::metadata(path).ok()?.len() > MAX_FILE_SIZE {
+        return None;
+    }
+
+    let text = Rope::from(fs::read_to_string(path).ok()?);
+    let language_config =
+        language_config.or_else(|| loader.language_config_for_content(text.slice(..)))?;
+    let tags_config = language_config.tags_config()?;
+    Some(tags_config.parse_definitions(&text))
+}
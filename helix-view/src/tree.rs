@@ -1,4 +1,5 @@
 use crate::{graphics::Rect, View, ViewId};
+use serde::{Deserialize, Serialize};
 use slotmap::HopSlotMap;
 
 // the dimensions are recomputed on window resize/tree change.
@@ -47,13 +48,21 @@ impl Node {
 
 // TODO: screen coord to container + container coordinate helpers
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Layout {
     Horizontal,
     Vertical,
     // could explore stacked/tabbed
 }
 
+/// The containers of a tree and the views in them, in the order they're shown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Shape {
+    View(ViewId),
+    Container(Layout, Vec<Shape>),
+}
+
 #[derive(Debug)]
 pub struct Container {
     layout: Layout,
@@ -389,6 +398,24 @@ impl Tree {
         }
     }
 
+    pub fn shape(&self) -> Shape {
+        self.node_shape(self.root)
+    }
+
+    fn node_shape(&self, key: ViewId) -> Shape {
+        match &self.nodes[key].content {
+            Content::View(_) => Shape::View(key),
+            Content::Container(container) => Shape::Container(
+                container.layout,
+                container
+                    .children
+                    .iter()
+                    .map(|&child| self.node_shape(child))
+                    .collect(),
+            ),
+        }
+    }
+
     pub fn traverse(&self) -> Traverse {
         Traverse::new(self)
     }
@@ -472,3 +499,37 @@ impl<'a> Iterator for Traverse<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DocumentId;
+
+    #[test]
+    fn test_shape() {
+        let mut tree = Tree::new(Rect::new(0, 0, 80, 24));
+        let doc = DocumentId::default();
+        let a = tree.insert(View::new(doc));
+        let b = tree.split(View::new(doc), Layout::Horizontal);
+        let c = tree.split(View::new(doc), Layout::Vertical);
+        tree.focus = a;
+        let d = tree.split(View::new(doc), Layout::Vertical);
+
+        assert_eq!(
+            tree.shape(),
+            Shape::Container(
+                Layout::Vertical,
+                vec![Shape::Container(
+                    Layout::Horizontal,
+                    vec![
+                        Shape::Container(Layout::Vertical, vec![Shape::View(a), Shape::View(d)]),
+                        Shape::Container(Layout::Vertical, vec![Shape::View(b), Shape::View(c)]),
+                    ]
+                )]
+            )
+        );
+        // the shape is in the order of traversal
+        let views: Vec<_> = tree.traverse().map(|(view_id, _)| view_id).collect();
+        assert_eq!(views, [a, d, b, c]);
+    }
+}
@@ -1,14 +1,35 @@
-use std::borrow::Cow;
-
 use crate::{graphics::Rect, Document, DocumentId, ViewId};
 use helix_core::{
-    coords_at_pos,
-    graphemes::{grapheme_width, RopeGraphemes},
-    line_ending::line_end_char_index,
-    Position, RopeSlice, Selection,
+    fold::HiddenLines, line_column_at_pos, line_ending::line_end_char_index, pos_at_visual_col,
+    LineColumn, Position, RopeSlice, Selection,
+};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    path::{Path, PathBuf},
 };
 
-type Jump = (DocumentId, Selection);
+/// A selection in a document that can be jumped back to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Jump {
+    pub doc_id: DocumentId,
+    pub selection: Selection,
+    /// The path of the document once it's closed, so that it can be opened again.
+    pub path: Option<PathBuf>,
+}
+
+impl Jump {
+    pub fn new(doc_id: DocumentId, selection: Selection) -> Self {
+        Self {
+            doc_id,
+            selection,
+            path: None,
+        }
+    }
+}
+
+/// The number of bytes shown on each row of a binary document.
+pub const HEX_ROW_LEN: usize = 16;
 
 #[derive(Debug, Clone)]
 pub struct JumpList {
@@ -46,7 +67,7 @@ impl JumpList {
     pub fn backward(&mut self, view_id: ViewId, doc: &mut Document, count: usize) -> Option<&Jump> {
         if let Some(current) = self.current.checked_sub(count) {
             if self.current == self.jumps.len() {
-                let jump = (doc.id(), doc.selection(view_id).clone());
+                let jump = Jump::new(doc.id(), doc.selection(view_id).clone());
                 self.push(jump);
             }
             self.current = current;
@@ -55,6 +76,65 @@ impl JumpList {
             None
         }
     }
+
+    /// The jumps, oldest first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Jump> {
+        self.jumps.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.jumps.clear();
+        self.current = 0;
+    }
+
+    /// Keeps the path of a closed document in the jumps into it, or drops them if it has none.
+    pub fn close_document(&mut self, doc_id: DocumentId, path: Option<&Path>) {
+        match path {
+            Some(path) => {
+                for jump in self.jumps.iter_mut().filter(|jump| jump.doc_id == doc_id) {
+                    jump.path = Some(path.to_path_buf());
+                }
+            }
+            None => {
+                let dropped = self.jumps[..self.current.min(self.jumps.len())]
+                    .iter()
+                    .filter(|jump| jump.doc_id == doc_id)
+                    .count();
+                self.jumps.retain(|jump| jump.doc_id != doc_id);
+                self.current -= dropped;
+            }
+        }
+    }
+}
+
+/// The positions that rendering last seeked to on each line of a document, so that long lines
+/// scrolled far to the right are seeked from there on the next frame instead of from their start.
+#[derive(Debug, Default)]
+pub struct LineSeeks {
+    /// The document, its version and the tab width that the lines were seeked in.
+    key: Cell<Option<(DocumentId, i32, usize)>>,
+    /// The seeked positions and their visual columns by line.
+    lines: RefCell<HashMap<usize, (usize, usize)>>,
+}
+
+impl LineSeeks {
+    /// Bounds the number of remembered lines, they're forgotten when there are more.
+    const MAX_LINES: usize = 1024;
+
+    /// Seeks the first grapheme on `line` of `doc` that starts at or after the visual column
+    /// `col`, like [`pos_at_visual_col`].
+    pub fn pos_at_visual_col(&self, doc: &Document, line: usize, col: usize) -> (usize, usize) {
+        let key = (doc.id(), doc.version(), doc.tab_width());
+        let mut lines = self.lines.borrow_mut();
+        if self.key.get() != Some(key) || lines.len() >= Self::MAX_LINES {
+            self.key.set(Some(key));
+            lines.clear();
+        }
+        let known = lines.get(&line).copied();
+        let seek = pos_at_visual_col(doc.text().slice(..), line, col, key.2, known);
+        lines.insert(line, seek);
+        seek
+    }
 }
 
 #[derive(Debug)]
@@ -66,6 +146,11 @@ pub struct View {
     pub jumps: JumpList,
     /// the last accessed file before the current one
     pub last_accessed_doc: Option<DocumentId>,
+    /// The columns last computed by [`View::line_column`], with the document, its version and
+    /// the tab width they were computed for.
+    column: Cell<Option<(DocumentId, i32, usize, LineColumn)>>,
+    /// The positions that rendering the view last seeked to on each line.
+    pub line_seeks: LineSeeks,
 }
 
 impl View {
@@ -75,8 +160,10 @@ impl View {
             doc,
             offset: Position::new(0, 0),
             area: Rect::default(), // will get calculated upon inserting into tree
-            jumps: JumpList::new((doc, Selection::point(0))), // TODO: use actual sel
+            jumps: JumpList::new(Jump::new(doc, Selection::point(0))), // TODO: use actual sel
             last_accessed_doc: None,
+            column: Cell::new(None),
+            line_seeks: LineSeeks::default(),
         }
     }
 
@@ -91,9 +178,21 @@ impl View {
             .selection(self.id)
             .primary()
             .cursor(doc.text().slice(..));
-        let Position { col, row: line } = coords_at_pos(doc.text().slice(..), cursor);
+        let Position { col, row: line } = if doc.is_binary() {
+            // binary documents are shown as rows of `HEX_ROW_LEN` bytes, without horizontal scrolling
+            Position::new(cursor / HEX_ROW_LEN, 0)
+        } else {
+            // the view is scrolled by visual columns
+            let line = doc.text().char_to_line(cursor);
+            Position::new(line, self.line_column(doc, cursor).visual_col)
+        };
+        // scroll by visual rows, which folded lines don't take up
+        let hidden = doc.hidden_lines(self.id);
+        let line = hidden.to_visual(line);
+        let mut offset_row = hidden.to_visual(self.offset.row);
+
         let inner_area = self.inner_area();
-        let last_line = (self.offset.row + inner_area.height as usize).saturating_sub(1);
+        let last_line = (offset_row + inner_area.height as usize).saturating_sub(1);
 
         // - 1 so we have at least one gap in the middle.
         // a height of 6 with padding of 3 on each side will keep shifting the view back and forth
@@ -104,11 +203,12 @@ impl View {
 
         if line > last_line.saturating_sub(scrolloff) {
             // scroll down
-            self.offset.row += line - (last_line.saturating_sub(scrolloff));
-        } else if line < self.offset.row + scrolloff {
+            offset_row += line - (last_line.saturating_sub(scrolloff));
+        } else if line < offset_row + scrolloff {
             // scroll up
-            self.offset.row = line.saturating_sub(scrolloff);
+            offset_row = line.saturating_sub(scrolloff);
         }
+        self.offset.row = hidden.from_visual(offset_row);
 
         if col > last_col.saturating_sub(scrolloff) {
             // scroll right
@@ -119,17 +219,36 @@ impl View {
         }
     }
 
-    /// Calculates the last visible line on screen
+    /// Calculates the last visible line on screen, skipping folded lines.
     #[inline]
     pub fn last_line(&self, doc: &Document) -> usize {
         let height = self.inner_area().height;
+        let hidden = doc.hidden_lines(self.id);
         std::cmp::min(
             // Saturating subs to make it inclusive zero indexing.
-            (self.offset.row + height as usize).saturating_sub(1),
+            hidden.from_visual(
+                (hidden.to_visual(self.offset.row) + height as usize).saturating_sub(1),
+            ),
             doc.text().len_lines().saturating_sub(1),
         )
     }
 
+    /// The grapheme and visual columns of `pos` in the text of `doc`. They're counted from the
+    /// position they were last computed for when that's on the same line, so moving the cursor
+    /// along a long line doesn't walk the line from its start on every keypress.
+    pub fn line_column(&self, doc: &Document, pos: usize) -> LineColumn {
+        let key = (doc.id(), doc.version(), doc.tab_width());
+        let known = match self.column.get() {
+            Some((doc_id, version, tab_width, column)) if (doc_id, version, tab_width) == key => {
+                Some(column)
+            }
+            _ => None,
+        };
+        let column = line_column_at_pos(doc.text().slice(..), pos, key.2, known);
+        self.column.set(Some((key.0, key.1, key.2, column)));
+        column
+    }
+
     /// Translates a document position to an absolute position in the terminal.
     /// Returns a (line, col) position if the position is visible on screen.
     // TODO: Could return width as well for the character width at cursor.
@@ -140,28 +259,17 @@ impl View {
         pos: usize,
     ) -> Option<Position> {
         let line = text.char_to_line(pos);
+        let hidden = doc.hidden_lines(self.id);
 
-        if line < self.offset.row || line > self.last_line(doc) {
+        if line < self.offset.row || line > self.last_line(doc) || hidden.is_hidden(line) {
             // Line is not visible on screen
             return None;
         }
 
-        let line_start = text.line_to_char(line);
-        let line_slice = text.slice(line_start..pos);
-        let mut col = 0;
-        let tab_width = doc.tab_width();
-
-        for grapheme in RopeGraphemes::new(line_slice) {
-            if grapheme == "\t" {
-                col += tab_width;
-            } else {
-                let grapheme = Cow::from(grapheme);
-                col += grapheme_width(&grapheme);
-            }
-        }
+        let col = self.line_column(doc, pos).visual_col;
 
         // It is possible for underflow to occur if the buffer length is larger than the terminal width.
-        let row = line.saturating_sub(self.offset.row);
+        let row = hidden.to_visual(line) - hidden.to_visual(self.offset.row);
         let col = col.saturating_sub(self.offset.col);
 
         Some(Position::new(row, col))
@@ -173,6 +281,23 @@ impl View {
         row: u16,
         column: u16,
         tab_width: usize,
+    ) -> Option<usize> {
+        self.text_pos_at_screen_coords_with_folds(
+            text,
+            &HiddenLines::default(),
+            row,
+            column,
+            tab_width,
+        )
+    }
+
+    fn text_pos_at_screen_coords_with_folds(
+        &self,
+        text: &RopeSlice,
+        hidden: &HiddenLines,
+        row: u16,
+        column: u16,
+        tab_width: usize,
     ) -> Option<usize> {
         let inner = self.inner_area();
         // 1 for status
@@ -184,31 +309,15 @@ impl View {
             return None;
         }
 
-        let line_number = (row - inner.y) as usize + self.offset.row;
+        let line_number =
+            hidden.from_visual((row - inner.y) as usize + hidden.to_visual(self.offset.row));
 
         if line_number > text.len_lines() - 1 {
             return Some(text.len_chars());
         }
 
-        let mut pos = text.line_to_char(line_number);
-
-        let current_line = text.line(line_number);
-
         let target = (column - inner.x) as usize + self.offset.col;
-        let mut selected = 0;
-
-        for grapheme in RopeGraphemes::new(current_line) {
-            if selected >= target {
-                break;
-            }
-            if grapheme == "\t" {
-                selected += tab_width;
-            } else {
-                let width = grapheme_width(&Cow::from(grapheme));
-                selected += width;
-            }
-            pos += grapheme.chars().count();
-        }
+        let (pos, _) = pos_at_visual_col(*text, line_number, target, tab_width, None);
 
         Some(pos.min(line_end_char_index(&text.slice(..), line_number)))
     }
@@ -216,7 +325,13 @@ impl View {
     /// Translates a screen position to position in the text document.
     /// Returns a usize typed position in bounds of the text if found in this view, None if out of view.
     pub fn pos_at_screen_coords(&self, doc: &Document, row: u16, column: u16) -> Option<usize> {
-        self.text_pos_at_screen_coords(&doc.text().slice(..), row, column, doc.tab_width())
+        self.text_pos_at_screen_coords_with_folds(
+            &doc.text().slice(..),
+            &doc.hidden_lines(self.id),
+            row,
+            column,
+            doc.tab_width(),
+        )
     }
     // pub fn traverse<F>(&self, text: RopeSlice, start: usize, end: usize, fun: F)
     // where
@@ -357,4 +472,28 @@ mod tests {
             Some(7)
         );
     }
+
+    #[test]
+    fn test_jumplist_close_document() {
+        let mut docs = slotmap::SlotMap::<DocumentId, ()>::with_key();
+        let (a, b) = (docs.insert(()), docs.insert(()));
+
+        let mut jumps = JumpList::new(Jump::new(a, Selection::point(0)));
+        jumps.push(Jump::new(b, Selection::point(1)));
+        jumps.push(Jump::new(a, Selection::point(2)));
+
+        jumps.close_document(a, Some(Path::new("a.txt")));
+        let paths: Vec<_> = jumps.iter().map(|jump| jump.path.as_deref()).collect();
+        assert_eq!(paths, [None, Some(Path::new("a.txt"))]);
+
+        jumps.close_document(b, None);
+        assert_eq!(jumps.iter().count(), 1);
+        let mut doc = Document::from(helix_core::Rope::from("abc"), None);
+        doc.set_selection(ViewId::default(), Selection::point(3));
+        let jump = jumps.backward(ViewId::default(), &mut doc, 1).unwrap();
+        assert_eq!(jump.selection, Selection::point(2));
+
+        jumps.clear();
+        assert_eq!(jumps.iter().count(), 0);
+    }
 }
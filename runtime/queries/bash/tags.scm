@@ -0,0 +1,5 @@
+(function_definition
+  name: (word) @name) @definition.function
+
+(variable_assignment
+  name: (variable_name) @name) @definition.variable
@@ -0,0 +1,10 @@
+[
+  (function_definition)
+  (struct_specifier)
+  (enum_specifier)
+  (union_specifier)
+  (for_statement)
+  (while_statement)
+  (if_statement)
+  (switch_statement)
+] @context
@@ -0,0 +1,6 @@
+; inherits: c
+
+[
+  (class_specifier)
+  (namespace_definition)
+] @context
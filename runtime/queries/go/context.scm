@@ -0,0 +1,11 @@
+[
+  (function_declaration)
+  (method_declaration)
+  (type_declaration)
+  (func_literal)
+  (for_statement)
+  (if_statement)
+  (expression_switch_statement)
+  (type_switch_statement)
+  (select_statement)
+] @context
@@ -0,0 +1,13 @@
+[
+  (class_declaration)
+  (function_declaration)
+  (generator_function_declaration)
+  (method_definition)
+  (arrow_function)
+  (for_statement)
+  (for_in_statement)
+  (while_statement)
+  (if_statement)
+  (switch_statement)
+  (try_statement)
+] @context
@@ -0,0 +1,5 @@
+(account_directive
+  (account) @name) @definition.account
+
+(commodity_directive
+  (commodity) @name) @definition.commodity
@@ -0,0 +1,2 @@
+(bind
+  attrpath: (attrpath) @name) @definition.attribute
@@ -0,0 +1,9 @@
+[
+  (class_definition)
+  (function_definition)
+  (for_statement)
+  (while_statement)
+  (if_statement)
+  (with_statement)
+  (try_statement)
+] @context
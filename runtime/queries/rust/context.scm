@@ -0,0 +1,17 @@
+[
+  (mod_item)
+  (impl_item)
+  (trait_item)
+  (struct_item)
+  (enum_item)
+  (union_item)
+  (function_item)
+  (macro_definition)
+  (closure_expression)
+  (for_expression)
+  (while_expression)
+  (loop_expression)
+  (if_expression)
+  (match_expression)
+  (match_arm)
+] @context